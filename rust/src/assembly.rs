@@ -0,0 +1,286 @@
+// Copyright 2024 Bewusstsein Labs
+
+//! Consistent initial configurations. The coordinates of the tree joints are moved by damped least
+//! squares, each step the smallest change that removes the link and joint constraint errors, until all
+//! are satisfied or the iteration budget runs out; whatever remains unsatisfied is reported.
+
+use std::fmt::Debug;
+
+use linear_algebra::vector::Vector;
+
+use crate::{
+    scalar::Scalar,
+    constraint::Constraint,
+    kinematics::Pose,
+    linkage::{ Linkage, Error },
+    inverse_kinematics::Target,
+    loops::damped_step,
+    dense::{ self, DenseMatrix },
+    rotation
+};
+
+#[derive( Clone, Copy, Debug, PartialEq )]
+pub struct Assembly<T> {
+    damping: T,
+    tolerance: T,
+    max_iterations: usize
+}
+
+impl<T> Assembly<T>
+where
+    T: Scalar
+{
+    /// Levenberg-Marquardt with initial damping `damping`, halved after each improving step and doubled
+    /// after each rejected one.
+    pub fn new( damping: T, tolerance: T, max_iterations: usize ) -> Self {
+        Self { damping, tolerance, max_iterations }
+    }
+
+    pub fn damping( &self ) -> &T { &self.damping }
+    pub fn tolerance( &self ) -> &T { &self.tolerance }
+    pub fn max_iterations( &self ) -> &usize { &self.max_iterations }
+}
+
+impl<T> Default for Assembly<T>
+where
+    T: Scalar
+{
+    fn default() -> Self {
+        Self::new( T::from_f64( 1e-3 ), T::from_f64( 1e-9 ), 1000 )
+    }
+}
+
+/// Constraint left unsatisfied by `Linkage::assemble`, with the distance and rotation angle separating
+/// the joint from the nearest pose the constraint allows.
+#[derive( Clone, Copy, Debug, PartialEq )]
+pub enum Unsatisfied<I, T> {
+    Link { first: I, second: I, distance: T, angle: T },
+    Joint { joint: I, distance: T, angle: T }
+}
+
+#[derive( Clone, Default, Debug, PartialEq )]
+pub struct AssemblyReport<I, T> {
+    converged: bool,
+    iterations: usize,
+    unsatisfied: Vec<Unsatisfied<I, T>>
+}
+
+impl<I, T> AssemblyReport<I, T> {
+    pub fn converged( &self ) -> bool { self.converged }
+    pub fn iterations( &self ) -> usize { self.iterations }
+    pub fn unsatisfied( &self ) -> &[Unsatisfied<I, T>] { &self.unsatisfied }
+}
+
+/// Distance and rotation angle between `pose` and `nearest`.
+fn separation<T, const DIM: usize>( pose: &Pose<T, DIM>, nearest: &Pose<T, DIM> ) -> ( T, T )
+where
+    T: Scalar
+{
+    let rotation = rotation::compose( &( Vector::default() - *pose.rotation() ), nearest.rotation() );
+    ( rotation::norm( &( *nearest.position() - *pose.position() ) ), rotation::norm( &rotation ) )
+}
+
+impl <I, T, const DIM: usize, const ORD: usize> Linkage<I, T, DIM, ORD>
+where
+    I: 'static + Default + Copy + Debug + Ord,
+    T: Scalar,
+    [Constraint<T, DIM>; (ORD + 1) * 2]: Default,
+    [(); ORD + 1]:,
+    [(); (ORD + 1) * 2]:
+{
+    /// Every link and joint position or rotation constraint not satisfied to within `tolerance`.
+    fn unsatisfied( &self, tolerance: T ) -> Vec<Unsatisfied<I, T>> {
+        let mut unsatisfied = Vec::new();
+        for ( first, second ) in self.links() {
+            let ( Some( link ), Some( joint1 ), Some( joint2 ) ) = ( self.get_link( *first, *second ), self.get_joint( *first ), self.get_joint( *second ) ) else { continue };
+            let pose = Pose::new( *joint2.position(), *joint2.rotation() );
+            let ( distance, angle ) = separation( &pose, &link.nearest( &Pose::new( *joint1.position(), *joint1.rotation() ), &pose ) );
+            if distance > tolerance || angle > tolerance {
+                unsatisfied.push( Unsatisfied::Link { first: *first, second: *second, distance, angle } );
+            }
+        }
+        for id in self.joint_ids() {
+            let Some( joint ) = self.get_joint( id ) else { continue };
+            let pose = Pose::new( *joint.position(), *joint.rotation() );
+            let ( distance, angle ) = separation( &pose, &joint.nearest() );
+            if distance > tolerance || angle > tolerance {
+                unsatisfied.push( Unsatisfied::Joint { joint: id, distance, angle } );
+            }
+        }
+        unsatisfied
+    }
+
+    /// Link and joint pose errors, as `Target::Pose` residuals.
+    fn assembly_residual( &self ) -> Vec<T> {
+        let mut residual = Vec::new();
+        for ( first, second ) in self.links() {
+            let ( Some( link ), Some( joint1 ), Some( joint2 ) ) = ( self.get_link( *first, *second ), self.get_joint( *first ), self.get_joint( *second ) ) else { continue };
+            let pose = Pose::new( *joint2.position(), *joint2.rotation() );
+            residual.extend( Target::Pose( link.nearest( &Pose::new( *joint1.position(), *joint1.rotation() ), &pose ) ).residual( &pose ) );
+        }
+        for id in self.joint_ids() {
+            let Some( joint ) = self.get_joint( id ) else { continue };
+            residual.extend( Target::Pose( joint.nearest() ).residual( &Pose::new( *joint.position(), *joint.rotation() ) ) );
+        }
+        residual
+    }
+
+    /// Forward difference Jacobian of the assembly residual at `residual` with respect to the scaled `columns`.
+    fn assembly_jacobian( &mut self, root: I, base: Pose<T, DIM>, columns: &[( I, usize, usize, T )], residual: &[T] ) -> Result<DenseMatrix<T>, Error> {
+        let step = T::epsilon().sqrt();
+        let mut transposed = Vec::with_capacity( columns.len() );
+        for ( id, k, count, scale ) in columns.iter() {
+            let saved = self.saved_displacements( &[ *id ] );
+            let mut amounts = vec![ T::zero(); *count ];
+            amounts[ *k ] = step * *scale;
+            self.get_joint_mut( *id ).ok_or( Error::JointNotFound )?.displace( &amounts );
+            self.apply_forward_kinematics( root, base )?;
+            let trial = self.assembly_residual();
+            self.restore_displacements( &saved );
+            transposed.push( trial.iter().zip( residual.iter() ).map( |( after, before )| ( *after - *before ) / step ).collect::<Vec<T>>() );
+        }
+        self.apply_forward_kinematics( root, base )?;
+        Ok( transposed )
+    }
+
+    /// Moves the coordinates of the tree joints from `root` onto the configuration nearest the input
+    /// poses that satisfies every link and the position and rotation constraints of every joint.
+    /// Coordinate changes are weighted by mass, so massless joints stay put, and the coordinates and
+    /// rates are synced to the assembled poses. Constraints that cannot all be met are reported with
+    /// their remaining violation.
+    pub fn assemble( &mut self, assembly: &Assembly<T>, root: I, base: Pose<T, DIM> ) -> Result<AssemblyReport<I, T>, Error> {
+        self.sync_coordinates( root, base )?;
+        let tree = self.spanning_tree( root )?;
+        // Free columns of the joints with mass, scaled by the square root of their inverse mass.
+        let mut columns = Vec::new();
+        for id in tree.order() {
+            let joint = self.get_joint( *id ).ok_or( Error::JointNotFound )?;
+            if *joint.mass() <= T::zero() {
+                continue;
+            }
+            let scale = ( T::one() / *joint.mass() ).sqrt();
+            let locked = joint.locked_axes();
+            columns.extend( ( 0..joint.degrees_of_freedom() ).filter( |k| !locked[ *k ] ).map( |k| ( *id, k, joint.degrees_of_freedom(), scale ) ) );
+        }
+
+        self.apply_forward_kinematics( root, base )?;
+        let mut residual = self.assembly_residual();
+        let mut error = dense::norm( &residual );
+        let mut damping = *assembly.damping();
+        let mut report = AssemblyReport::default();
+        report.unsatisfied = self.unsatisfied( *assembly.tolerance() );
+        while !report.unsatisfied.is_empty() && report.iterations < *assembly.max_iterations() && !columns.is_empty() {
+            report.iterations += 1;
+            let saved = self.saved_displacements( tree.order() );
+            let transposed = self.assembly_jacobian( root, base, &columns, &residual )?;
+            let negated: Vec<T> = residual.iter().map( |value| -*value ).collect();
+            let Some( delta ) = damped_step( &transposed, &negated, damping ) else { break };
+
+            for ( ( id, k, count, scale ), amount ) in columns.iter().zip( delta.iter() ) {
+                let mut amounts = vec![ T::zero(); *count ];
+                amounts[ *k ] = *amount * *scale;
+                if let Some( joint ) = self.get_joint_mut( *id ) {
+                    joint.displace( &amounts );
+                }
+            }
+            self.apply_forward_kinematics( root, base )?;
+            let trial = self.assembly_residual();
+            let trial_error = dense::norm( &trial );
+            if trial_error >= error {
+                self.restore_displacements( &saved );
+                self.apply_forward_kinematics( root, base )?;
+                damping = ( damping * T::two() ).max( T::epsilon().sqrt() );
+            } else {
+                damping *= T::half();
+                residual = trial;
+                error = trial_error;
+            }
+            report.unsatisfied = self.unsatisfied( *assembly.tolerance() );
+        }
+        self.sync_coordinates( root, base )?;
+        report.converged = report.unsatisfied.is_empty();
+        Ok( report )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+    use crate::{
+        body::Body3D,
+        joint::Joint3D,
+        link::Link,
+        constraint::{ Range, Constraint3D },
+        linkage::Linkage3D
+    };
+
+    /// Anchored chain whose second link points along y, built from positions that do not fit it.
+    fn chain( constraint: Constraint3D<f64> ) -> Linkage3D<usize, f64, 1> {
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        let joints = [ ( 0.0, [ 0.0, 0.0, 0.0 ], Constraint3D::default() ), ( 1.0, [ 1.2, 0.1, 0.0 ], Constraint3D::default() ), ( 1.0, [ 0.9, 1.3, 0.2 ], constraint ) ];
+        for ( id, ( mass, position, constraint ) ) in joints.into_iter().enumerate() {
+            let body = Body3D::new( mass, [ Vector3::from( position ), Vector3::default() ], [ Vector3::default(); 2 ] );
+            linkage.add_joint( id, Joint3D::new( body, [ constraint, Constraint3D::default(), Constraint3D::default(), Constraint3D::default() ] ) ).unwrap();
+        }
+        let fixed = Constraint3D::new([ Some( Range::new( 0.0, 0.0 ) ); 3 ]);
+        linkage.add_link( 0, 1, Link::new( 0.0, fixed ).with_transform( Pose::new( Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::default() ) ) ).unwrap();
+        linkage.add_link( 1, 2, Link::new( 0.0, fixed ).with_transform( Pose::new( Vector3::from([ 0.0, 1.0, 0.0 ]), Vector3::default() ) ) ).unwrap();
+        linkage
+    }
+
+    #[test]
+    fn assemble_test() {
+        let mut linkage = chain( Constraint3D::default() );
+        let report = linkage.assemble( &Assembly::default(), 0, Pose::identity() ).unwrap();
+        assert!( report.converged() );
+        assert!( report.unsatisfied().is_empty() );
+        assert_eq!( *linkage.get_joint( 0 ).unwrap().position(), Vector3::default() );
+        let position = *linkage.get_joint( 2 ).unwrap().position();
+        assert!( ( position[ 0 ] - 1.0 ).abs() < 1e-9 && ( position[ 1 ] - 1.0 ).abs() < 1e-9 && position[ 2 ].abs() < 1e-9 );
+
+        // The coordinates are synced, so forward kinematics keeps the assembled poses.
+        let kinematics = linkage.forward_kinematics( 0, Pose::identity() ).unwrap();
+        for ( id, pose ) in kinematics.poses() {
+            let joint = linkage.get_joint( *id ).unwrap();
+            assert!( rotation::norm( &( *pose.position() - *joint.position() ) ) < 1e-12 );
+            assert!( rotation::norm( &( *pose.rotation() - *joint.rotation() ) ) < 1e-12 );
+        }
+    }
+
+    #[test]
+    fn rotation_limited_test() {
+        let twist = Constraint3D::new([ None, None, Some( Range::new( -0.2, 0.2 ) ) ]);
+        let mut linkage = chain( Constraint3D::default() );
+        let joint2 = linkage.get_joint_mut( 2 ).unwrap();
+        *joint2.rotation_constraint_mut() = twist;
+        *joint2.rotation_mut() = Vector3::from([ 0.0, 0.0, 0.6 ]);
+        let report = linkage.assemble( &Assembly::default(), 0, Pose::identity() ).unwrap();
+        assert!( report.converged() );
+        assert!( ( linkage.get_joint( 2 ).unwrap().rotation()[ 2 ] - 0.2 ).abs() < 1e-9 );
+
+        // Rigid links demand a rotation of 0.5 the joint cannot reach.
+        *linkage.get_link_mut( 0, 1 ).unwrap() = Link::rigid( 0.0, Pose::new( Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::default() ) );
+        *linkage.get_link_mut( 1, 2 ).unwrap() = Link::rigid( 0.0, Pose::new( Vector3::from([ 0.0, 1.0, 0.0 ]), Vector3::from([ 0.0, 0.0, 0.5 ]) ) );
+        let report = linkage.assemble( &Assembly::new( 1e-3, 1e-9, 50 ), 0, Pose::identity() ).unwrap();
+        assert!( !report.converged() );
+        let angle: f64 = report.unsatisfied().iter().map( |unsatisfied| match unsatisfied {
+            Unsatisfied::Link { angle, .. } | Unsatisfied::Joint { angle, .. } => *angle
+        } ).sum();
+        assert!( angle > 0.1 );
+        assert!( linkage.get_joint( 2 ).unwrap().rotation()[ 2 ] <= 0.2 + 1e-9 );
+    }
+
+    #[test]
+    fn unsatisfiable_test() {
+        let low = Constraint3D::new([ None, Some( Range::new( -1.0, 0.5 ) ), None ]);
+        let mut linkage = chain( low );
+        let report = linkage.assemble( &Assembly::new( 1e-3, 1e-9, 50 ), 0, Pose::identity() ).unwrap();
+        assert!( !report.converged() );
+        assert_eq!( report.iterations(), 50 );
+        let total: f64 = report.unsatisfied().iter().map( |unsatisfied| match unsatisfied {
+            Unsatisfied::Link { distance, .. } | Unsatisfied::Joint { distance, .. } => *distance
+        } ).sum();
+        assert!( total > 0.1 );
+    }
+}
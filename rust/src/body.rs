@@ -8,7 +8,14 @@ use std::{
 use linear_algebra::vector::Vector;
 use const_expr_bounds::{ Assert, IsTrue };
 
-use crate::{ particle::Particle };
+use crate::{
+    scalar::Scalar,
+    particle::Particle,
+    integrator::Integrator,
+    inertia::MassProperties,
+    linkage::Error,
+    rotation::{ self, Quaternion, matrix_vector, matrix_matrix, transpose, inverse }
+};
 
 #[derive( Clone, Debug, PartialEq )]
 pub struct Body<T, const DIM: usize, const ORD: usize>
@@ -17,6 +24,10 @@ where
     [(); ORD + 1]:
 {
     mass: T,
+    center_of_mass: Vector<T, DIM>,
+    inertia: Vector<Vector<T, DIM>, DIM>,
+    force: Vector<T, DIM>,
+    torque: Vector<T, DIM>,
     particle: Particle<T, DIM, ORD>,
 }
 
@@ -29,12 +40,227 @@ where
     pub fn new( mass: T, spatial: [Vector<T, DIM>; ORD + 1], angular: [Vector<T, DIM>; ORD + 1]  ) -> Self {
         Self {
             mass,
+            center_of_mass: Vector::default(),
+            inertia: Vector::default(),
+            force: Vector::default(),
+            torque: Vector::default(),
             particle: Particle::new( spatial, angular )
         }
     }
 
     pub fn mass<'a>( &'a self ) -> &'a T { &self.mass }
     pub fn mass_mut<'b>( &'b mut self ) -> &'b mut T { &mut self.mass }
+    pub fn center_of_mass<'a>( &'a self ) -> &'a Vector<T, DIM> { &self.center_of_mass }
+    pub fn center_of_mass_mut<'b>( &'b mut self ) -> &'b mut Vector<T, DIM> { &mut self.center_of_mass }
+    pub fn inertia<'a>( &'a self ) -> &'a Vector<Vector<T, DIM>, DIM> { &self.inertia }
+    pub fn inertia_mut<'b>( &'b mut self ) -> &'b mut Vector<Vector<T, DIM>, DIM> { &mut self.inertia }
+    pub fn force<'a>( &'a self ) -> &'a Vector<T, DIM> { &self.force }
+    pub fn torque<'a>( &'a self ) -> &'a Vector<T, DIM> { &self.torque }
+
+    pub fn apply_force( &mut self, force: Vector<T, DIM> )
+    where
+        T: Scalar
+    {
+        self.force += force;
+    }
+
+    /// Applies `force` at the world frame `point`, adding its moment about the centre of mass.
+    pub fn apply_force_at_point( &mut self, force: Vector<T, DIM>, point: Vector<T, DIM> )
+    where
+        T: Scalar
+    {
+        let arm = point - self.world_center_of_mass();
+        self.force += force;
+        self.torque += rotation::moment( &arm, &force );
+    }
+
+    pub fn apply_torque( &mut self, torque: Vector<T, DIM> )
+    where
+        T: Scalar
+    {
+        self.torque += torque;
+    }
+
+    pub fn clear_forces( &mut self ) {
+        self.force = Vector::default();
+        self.torque = Vector::default();
+    }
+
+    pub fn world_center_of_mass( &self ) -> Vector<T, DIM>
+    where
+        T: Scalar
+    {
+        *self.position() + rotation::rotate( self.rotation(), &self.center_of_mass )
+    }
+
+    /// Inertia tensor about the centre of mass, expressed in world axes. In two dimensions the first
+    /// diagonal entry is the planar moment of inertia and is left unchanged.
+    pub fn world_inertia( &self ) -> Vector<Vector<T, DIM>, DIM>
+    where
+        T: Scalar
+    {
+        self.inertia_at( self.rotation() )
+    }
+
+    /// Inertia tensor about the centre of mass with the body turned to `rotation`.
+    pub fn inertia_at( &self, rotation: &Vector<T, DIM> ) -> Vector<Vector<T, DIM>, DIM>
+    where
+        T: Scalar
+    {
+        if DIM == 3 {
+            let rotation = Quaternion::from_rotation_vector( &rotation::resize( rotation ) ).to_rotation_matrix();
+            let inertia = rotation::resize_matrix( &self.inertia );
+            rotation::resize_matrix( &matrix_matrix( &matrix_matrix( &rotation, &inertia ), &transpose( &rotation ) ) )
+        } else {
+            self.inertia
+        }
+    }
+
+    /// Inverse of the world inertia tensor, zero for a body without rotational inertia; `None` if it
+    /// is otherwise singular.
+    pub fn inverse_world_inertia( &self ) -> Option<Vector<Vector<T, DIM>, DIM>>
+    where
+        T: Scalar
+    {
+        let inertia = self.world_inertia();
+        if DIM == 3 {
+            if inertia == Vector::default() {
+                return Some( inertia );
+            }
+            inverse( &rotation::resize_matrix( &inertia ) ).map( |inverse| rotation::resize_matrix( &inverse ) )
+        } else {
+            let mut inverse = Vector::<Vector<T, DIM>, DIM>::default();
+            for i in 0..DIM {
+                if inertia[ i ][ i ] != T::zero() {
+                    inverse[ i ][ i ] = T::one() / inertia[ i ][ i ];
+                }
+            }
+            Some( inverse )
+        }
+    }
+
+    /// `None` for a singular inertia tensor.
+    pub fn angular_acceleration_at( &self, velocity: &Vector<T, DIM> ) -> Option<Vector<T, DIM>>
+    where
+        T: Scalar
+    {
+        let inverse = self.inverse_world_inertia()?;
+        Some( angular_acceleration( &self.world_inertia(), &inverse, &self.torque, velocity ) )
+    }
+
+    pub fn linear_acceleration_at( &self, angular_velocity: &Vector<T, DIM>, angular_acceleration: &Vector<T, DIM> ) -> Vector<T, DIM>
+    where
+        T: Scalar
+    {
+        if self.mass == T::zero() {
+            return Vector::default();
+        }
+        let acceleration = self.force / self.mass;
+        if DIM == 3 {
+            let arm: Vector<T, 3> = rotation::resize( &rotation::rotate( self.rotation(), &self.center_of_mass ) );
+            let velocity = rotation::resize( angular_velocity );
+            let tangential = rotation::cross( &rotation::resize( angular_acceleration ), &arm );
+            let centripetal = rotation::cross( &velocity, &rotation::cross( &velocity, &arm ) );
+            acceleration - rotation::resize( &( tangential + centripetal ) )
+        } else {
+            acceleration
+        }
+    }
+
+    /// Holds the highest derivative; accumulated forces cannot act and are cleared.
+    pub fn update<I>( &mut self, integrator: &I, time_step: T )
+    where
+        T: Scalar,
+        I: Integrator<T>
+    {
+        self.particle.update( integrator, time_step );
+        self.clear_forces();
+    }
+
+    /// Newton-Euler step, clearing the accumulated force and torque. Fails with
+    /// `Error::SingularInertia`, leaving the body in place, if the inertia tensor cannot be inverted.
+    pub fn update_dynamics<I>( &mut self, integrator: &I, time_step: T ) -> Result<(), Error>
+    where
+        T: Scalar,
+        I: Integrator<T>,
+        Assert<{ ORD >= 2 }>: IsTrue
+    {
+        self.update_dynamics_with( integrator, time_step, |_| Vector::default(), |_| Vector::default() )
+    }
+
+    /// `update_dynamics` with `linear` and `angular` added at every stage, the angular stack holding
+    /// the rotation increment over the step.
+    pub(crate) fn update_dynamics_with<I, F, G>( &mut self, integrator: &I, time_step: T, linear: F, angular: G ) -> Result<(), Error>
+    where
+        T: Scalar,
+        I: Integrator<T>,
+        F: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>,
+        G: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>
+    {
+        let Some( inverse ) = self.inverse_world_inertia() else {
+            self.clear_forces();
+            return Err( Error::SingularInertia );
+        };
+        let ( inertia, torque ) = ( self.world_inertia(), self.torque );
+        let angular_velocity = self.particle.angular[ 1 ];
+        let initial = angular_acceleration( &inertia, &inverse, &torque, &angular_velocity );
+        let acceleration = self.linear_acceleration_at( &angular_velocity, &initial );
+        self.particle.update_to_with(
+            integrator,
+            time_step,
+            2,
+            |stack| acceleration + linear( stack ),
+            |stack| angular_acceleration( &inertia, &inverse, &torque, &stack[ 1 ] ) + angular( stack )
+        );
+        self.clear_forces();
+        Ok( () )
+    }
+}
+
+/// `I⁻¹ ( τ - ω × Iω )`, the gyroscopic term only in three dimensions.
+fn angular_acceleration<T, const DIM: usize>(
+    inertia: &Vector<Vector<T, DIM>, DIM>,
+    inverse: &Vector<Vector<T, DIM>, DIM>,
+    torque: &Vector<T, DIM>,
+    velocity: &Vector<T, DIM>
+) -> Vector<T, DIM>
+where
+    T: Scalar
+{
+    let mut net = *torque;
+    if DIM == 3 {
+        let velocity: Vector<T, 3> = rotation::resize( velocity );
+        let momentum = matrix_vector( &rotation::resize_matrix( inertia ), &velocity );
+        net -= rotation::resize( &rotation::cross( &velocity, &momentum ) );
+    }
+    let mut acceleration = Vector::<T, DIM>::default();
+    for i in 0..DIM {
+        acceleration[ i ] = rotation::dot( &inverse[ i ], &net );
+    }
+    acceleration
+}
+
+impl<T, const ORD: usize> Body<T, 3, ORD>
+where
+    T: Scalar,
+    [(); ORD + 1]:
+{
+    pub fn from_mass_properties( properties: MassProperties<T>, spatial: [Vector<T, 3>; ORD + 1], angular: [Vector<T, 3>; ORD + 1] ) -> Self {
+        let mut body = Self::new( *properties.mass(), spatial, angular );
+        body.set_mass_properties( properties );
+        body
+    }
+
+    pub fn mass_properties( &self ) -> MassProperties<T> {
+        MassProperties::new( self.mass, self.center_of_mass, self.inertia )
+    }
+
+    pub fn set_mass_properties( &mut self, properties: MassProperties<T> ) {
+        self.mass = *properties.mass();
+        self.center_of_mass = *properties.center_of_mass();
+        self.inertia = *properties.inertia();
+    }
+
 }
 
 impl<T, const DIM: usize, const ORD: usize> Default for Body<T, DIM, ORD>
@@ -45,6 +271,10 @@ where
     fn default() -> Self {
         Self {
             mass: T::default(),
+            center_of_mass: Vector::default(),
+            inertia: Vector::default(),
+            force: Vector::default(),
+            torque: Vector::default(),
             particle: Particle::default()
         }
     }
@@ -81,6 +311,10 @@ pub type Body4D<T, const ORD: usize> = Body<T, 4, ORD>;
 mod tests {
     use linear_algebra::vector::Vector3;
     use super::*;
+    use crate::{
+        inertia::Shape,
+        integrator::{ SemiImplicitEuler, RungeKutta4 }
+    };
 
     #[test]
     fn new_test() {
@@ -94,4 +328,57 @@ mod tests {
         assert_eq!( *body.spatial_velocity(), spatial_velocity );
         assert_eq!( *body.angular_velocity(), angular_velocity );
     }
+
+    #[test]
+    fn mass_properties_test() {
+        let quarter = std::f64::consts::FRAC_PI_2;
+        let properties = Shape::Cylinder { radius: 1.0, height: 2.0 }.mass_properties( 6.0 );
+        let body = Body3D::<f64, 1>::from_mass_properties( properties, [ Vector3::default(); 2 ], [ Vector3::from([ quarter, 0.0, 0.0 ]), Vector3::default() ] );
+        let inertia = body.world_inertia();
+        assert!( ( inertia[ 1 ][ 1 ] - 3.0 ).abs() < 1e-12 );
+        assert!( ( inertia[ 2 ][ 2 ] - 3.5 ).abs() < 1e-12 );
+    }
+
+    #[test]
+    fn update_dynamics_test() {
+        let properties = Shape::Sphere { radius: 1.0 }.mass_properties( 2.5 );
+        let mut body = Body3D::<f64, 2>::from_mass_properties( properties, [ Vector3::default(); 3 ], [ Vector3::default(); 3 ] );
+        body.apply_force_at_point( Vector3::from([ 0.0, 5.0, 0.0 ]), Vector3::from([ 1.0, 0.0, 0.0 ]) );
+        assert_eq!( *body.torque(), Vector3::from([ 0.0, 0.0, 5.0 ]) );
+        body.update_dynamics( &SemiImplicitEuler, 0.5 ).unwrap();
+        assert_eq!( *body.force(), Vector3::default() );
+        assert!( ( body.spatial_velocity()[ 1 ] - 1.0 ).abs() < 1e-12 );
+        assert!( ( body.angular_velocity()[ 2 ] - 2.5 ).abs() < 1e-12 );
+
+        // A 1 cm, 10 g sphere is small but far from singular.
+        let properties = Shape::Sphere { radius: 0.01 }.mass_properties( 0.01 );
+        let mut body = Body3D::<f64, 2>::from_mass_properties( properties, [ Vector3::default(); 3 ], [ Vector3::default(); 3 ] );
+        body.apply_torque( Vector3::from([ 4e-7, 0.0, 0.0 ]) );
+        body.update_dynamics( &SemiImplicitEuler, 1.0 ).unwrap();
+        assert!( ( body.angular_velocity()[ 0 ] - 1.0 ).abs() < 1e-9 );
+
+        // A thin rod has no inertia about its axis, so a torque about it has no defined effect.
+        let mut body = Body3D::<f64, 2>::new( 1.0, [ Vector3::default(); 3 ], [ Vector3::default(); 3 ] );
+        *body.inertia_mut() = Vector3::from([ Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::from([ 0.0, 1.0, 0.0 ]), Vector3::default() ]);
+        body.apply_torque( Vector3::from([ 0.0, 0.0, 1.0 ]) );
+        assert!( body.angular_acceleration_at( &Vector3::default() ).is_none() );
+        assert!( matches!( body.update_dynamics( &SemiImplicitEuler, 0.1 ), Err( Error::SingularInertia ) ) );
+        assert_eq!( *body.torque(), Vector3::default() );
+        assert_eq!( *body.angular_velocity(), Vector3::default() );
+    }
+
+    #[test]
+    fn gyroscopic_test() {
+        // Torque free rotation about an intermediate axis drifts; the jerk follows the acceleration.
+        let inertia = Vector3::from([ Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::from([ 0.0, 2.0, 0.0 ]), Vector3::from([ 0.0, 0.0, 3.0 ]) ]);
+        let mut body = Body3D::<f64, 3>::new( 1.0, [ Vector3::default(); 4 ], [ Vector3::default(), Vector3::from([ 0.1, 1.0, 0.1 ]), Vector3::default(), Vector3::default() ] );
+        *body.inertia_mut() = inertia;
+        body.update_dynamics( &RungeKutta4, 0.01 ).unwrap();
+        let before = *body.angular_acceleration();
+        body.update_dynamics( &RungeKutta4, 0.01 ).unwrap();
+        assert!( ( body.angular_acceleration()[ 0 ] - before[ 0 ] ).abs() > 1e-6 );
+        for i in 0..3 {
+            assert!( ( body.angular_jerk()[ i ] - ( body.angular_acceleration()[ i ] - before[ i ] ) / 0.01 ).abs() < 1e-9 );
+        }
+    }
 }
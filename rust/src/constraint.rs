@@ -8,10 +8,27 @@ use num::traits::clamp;
 
 use linear_algebra::vector::Vector;
 
+use crate::scalar::Scalar;
+
+/// How a `Range` acts on a value that leaves it.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub enum Limit<T> {
+    /// The value is clamped back into range and its derivatives are left untouched.
+    #[default]
+    Hard,
+    /// The value may leave the range and is pulled back by the corrective acceleration
+    /// `-stiffness * excess - damping * rate`.
+    Soft { stiffness: T, damping: T },
+    /// The value is clamped back into range and the outward parts of all its higher derivatives are
+    /// reversed and scaled by `restitution`, zero giving a perfectly inelastic stop.
+    Stop { restitution: T }
+}
+
 #[derive( Clone, Copy, Default, Debug, PartialEq )]
 pub struct Range<T> {
     min: T,
-    max: T
+    max: T,
+    limit: Limit<T>
 }
 
 impl<T> Range<T>
@@ -19,7 +36,12 @@ where
     T: Copy
 {
     pub fn new( min: T, max: T ) -> Self {
-        Self { min, max }
+        Self { min, max, limit: Limit::Hard }
+    }
+
+    pub fn with_limit( mut self, limit: Limit<T> ) -> Self {
+        self.limit = limit;
+        self
     }
 
     pub fn min( &self ) -> &T {
@@ -30,6 +52,10 @@ where
         &self.max
     }
 
+    pub fn limit( &self ) -> &Limit<T> {
+        &self.limit
+    }
+
     pub fn clamp( &self, value: &mut T )
     where
         T: PartialOrd
@@ -38,6 +64,63 @@ where
     }
 }
 
+impl<T> Range<T>
+where
+    T: Scalar
+{
+    /// Positive above `max`, negative below `min` and zero inside.
+    pub fn excess( &self, value: T ) -> T {
+        if value > self.max {
+            value - self.max
+        } else if value < self.min {
+            value - self.min
+        } else {
+            T::zero()
+        }
+    }
+
+    /// Zero inside the range and for limits that are not soft.
+    pub fn correction( &self, value: T, rate: T ) -> T {
+        match self.limit {
+            Limit::Soft { stiffness, damping } => {
+                let excess = self.excess( value );
+                if excess == T::zero() { T::zero() } else { -stiffness * excess - damping * rate }
+            },
+            _ => T::zero()
+        }
+    }
+}
+
+/// Axis of a constrained vector found outside its constraint, with `bound` the nearest value allowed:
+/// the end of its range, or the projection onto a joint's shape once the ranges are applied.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct Violation<T> {
+    axis: usize,
+    value: T,
+    bound: T,
+    magnitude: T,
+    soft: bool
+}
+
+impl<T> Violation<T> {
+    pub(crate) fn between<const DIM: usize>( vec: &Vector<T, DIM>, bounded: &Vector<T, DIM>, constraint: &Constraint<T, DIM> ) -> Vec<Self>
+    where
+        T: Scalar
+    {
+        ( 0..DIM ).filter( |i| bounded[ *i ] != vec[ *i ] ).map( |i| {
+            let soft = constraint[ i ].is_some_and( |range| matches!( range.limit(), Limit::Soft { .. } ) && range.excess( vec[ i ] ) != T::zero() );
+            Violation { axis: i, value: vec[ i ], bound: bounded[ i ], magnitude: ( vec[ i ] - bounded[ i ] ).abs(), soft }
+        } ).collect()
+    }
+
+    pub fn axis( &self ) -> usize { self.axis }
+    pub fn value( &self ) -> &T { &self.value }
+    pub fn bound( &self ) -> &T { &self.bound }
+    pub fn magnitude( &self ) -> &T { &self.magnitude }
+    /// Outside a soft limit, which dynamic stepping pulls it back from gradually.
+    pub fn soft( &self ) -> bool { self.soft }
+}
+
 #[derive( Clone, Copy, Default, Debug, PartialEq )]
 pub struct Constraint<T, const DIM: usize>( Vector<Option<Range<T>>, DIM> )
 where
@@ -51,11 +134,67 @@ where
         Self( Vector::from( options ) )
     }
 
-    pub fn constrain( &self, vec: &mut Vector<T, DIM>)
-    {
+    /// Clamps soft limits like hard ones, for callers with no acceleration for `correction`.
+    pub fn constrain( &self, vec: &mut Vector<T, DIM> ) {
+        self.clamp( vec, true );
+    }
+
+    /// Like `constrain`, but leaves soft limits to `correction`.
+    pub fn constrain_rigid( &self, vec: &mut Vector<T, DIM> ) {
+        self.clamp( vec, false );
+    }
+
+    fn clamp( &self, vec: &mut Vector<T, DIM>, soft: bool ) {
         for i in 0..DIM {
             if let Some( range ) = &self[i] {
-                range.clamp( &mut vec[i] );
+                if soft || !matches!( range.limit(), Limit::Soft { .. } ) {
+                    range.clamp( &mut vec[i] );
+                }
+            }
+        }
+    }
+}
+
+impl<T, const DIM: usize> Constraint<T, DIM>
+where
+    T: Scalar
+{
+    pub fn check( &self, vec: &Vector<T, DIM> ) -> Vec<Violation<T>> {
+        let mut bounded = *vec;
+        for i in 0..DIM {
+            if let Some( range ) = &self[i] {
+                range.clamp( &mut bounded[i] );
+            }
+        }
+        Violation::between( vec, &bounded, self )
+    }
+
+    pub fn correction( &self, value: &Vector<T, DIM>, rate: &Vector<T, DIM> ) -> Vector<T, DIM> {
+        let mut correction = Vector::<T, DIM>::default();
+        for i in 0..DIM {
+            if let Some( range ) = &self[i] {
+                correction[ i ] = range.correction( value[ i ], rate[ i ] );
+            }
+        }
+        correction
+    }
+
+    /// Clamps a derivative stack `[ x, x', .. ]` onto its stops, reversing the outward parts of the
+    /// higher derivatives scaled by the restitution.
+    pub fn stop( &self, stack: &mut [Vector<T, DIM>] ) {
+        let Some( ( value, derivatives ) ) = stack.split_first_mut() else { return };
+        for i in 0..DIM {
+            let Some( range ) = &self[i] else { continue };
+            let Limit::Stop { restitution } = *range.limit() else { continue };
+            let excess = range.excess( value[ i ] );
+            if excess == T::zero() {
+                continue;
+            }
+            range.clamp( &mut value[ i ] );
+            for derivative in derivatives.iter_mut() {
+                if excess * derivative[ i ] > T::zero() {
+                    derivative[ i ] = -restitution * derivative[ i ];
+                }
             }
         }
     }
@@ -0,0 +1,150 @@
+// Copyright 2024 Bewusstsein Labs
+
+use crate::scalar::Scalar;
+
+/// Row major matrix whose size is only known at run time, e.g. one column per joint coordinate.
+pub type DenseMatrix<T> = Vec<Vec<T>>;
+
+pub fn zeros<T>( rows: usize, columns: usize ) -> DenseMatrix<T>
+where
+    T: Scalar
+{
+    vec![ vec![ T::zero(); columns ]; rows ]
+}
+
+pub fn transpose<T>( matrix: &DenseMatrix<T> ) -> DenseMatrix<T>
+where
+    T: Scalar
+{
+    let columns = matrix.first().map_or( 0, |row| row.len() );
+    ( 0..columns ).map( |j| matrix.iter().map( |row| row[ j ] ).collect() ).collect()
+}
+
+pub fn multiply<T>( a: &DenseMatrix<T>, b: &DenseMatrix<T> ) -> DenseMatrix<T>
+where
+    T: Scalar
+{
+    let columns = b.first().map_or( 0, |row| row.len() );
+    a.iter().map( |row| {
+        ( 0..columns ).map( |j| row.iter().zip( b.iter() ).fold( T::zero(), |sum, ( x, b_row )| sum + *x * b_row[ j ] ) ).collect()
+    } ).collect()
+}
+
+pub fn multiply_vector<T>( matrix: &DenseMatrix<T>, vector: &[T] ) -> Vec<T>
+where
+    T: Scalar
+{
+    matrix.iter().map( |row| row.iter().zip( vector.iter() ).fold( T::zero(), |sum, ( x, y )| sum + *x * *y ) ).collect()
+}
+
+pub fn dot<T>( a: &[T], b: &[T] ) -> T
+where
+    T: Scalar
+{
+    a.iter().zip( b.iter() ).fold( T::zero(), |sum, ( x, y )| sum + *x * *y )
+}
+
+pub fn norm<T>( a: &[T] ) -> T
+where
+    T: Scalar
+{
+    dot( a, a ).sqrt()
+}
+
+pub fn determinant<T>( matrix: &DenseMatrix<T> ) -> T
+where
+    T: Scalar
+{
+    let n = matrix.len();
+    let mut a = matrix.clone();
+    let mut determinant = T::one();
+    for k in 0..n {
+        let Some( pivot ) = ( k..n ).max_by( |i, j| a[ *i ][ k ].abs().partial_cmp( &a[ *j ][ k ].abs() ).unwrap_or( std::cmp::Ordering::Equal ) ) else {
+            return T::zero();
+        };
+        if a[ pivot ][ k ] == T::zero() {
+            return T::zero();
+        }
+        if pivot != k {
+            a.swap( k, pivot );
+            determinant = -determinant;
+        }
+        determinant *= a[ k ][ k ];
+        for i in ( k + 1 )..n {
+            let factor = a[ i ][ k ] / a[ k ][ k ];
+            for j in k..n {
+                let value = a[ k ][ j ];
+                a[ i ][ j ] -= factor * value;
+            }
+        }
+    }
+    determinant
+}
+
+/// Solves `matrix x = rhs` by Gaussian elimination with partial pivoting, `None` if singular: a pivot
+/// vanishes relative to the largest entry of the matrix.
+pub fn solve<T>( matrix: &DenseMatrix<T>, rhs: &[T] ) -> Option<Vec<T>>
+where
+    T: Scalar
+{
+    let n = rhs.len();
+    let mut a = matrix.clone();
+    let mut b = rhs.to_vec();
+    let scale = a.iter().flatten().fold( T::zero(), |scale, x| scale.max( x.abs() ) );
+    for k in 0..n {
+        let pivot = ( k..n ).max_by( |i, j| a[ *i ][ k ].abs().partial_cmp( &a[ *j ][ k ].abs() ).unwrap_or( std::cmp::Ordering::Equal ) )?;
+        if scale == T::zero() || a[ pivot ][ k ].abs() <= T::epsilon() * scale * T::from_usize( n ) {
+            return None;
+        }
+        a.swap( k, pivot );
+        b.swap( k, pivot );
+        for i in ( k + 1 )..n {
+            let factor = a[ i ][ k ] / a[ k ][ k ];
+            for j in k..n {
+                let value = a[ k ][ j ];
+                a[ i ][ j ] -= factor * value;
+            }
+            let value = b[ k ];
+            b[ i ] -= factor * value;
+        }
+    }
+    let mut x = vec![ T::zero(); n ];
+    for i in ( 0..n ).rev() {
+        let sum = ( ( i + 1 )..n ).fold( b[ i ], |sum, j| sum - a[ i ][ j ] * x[ j ] );
+        x[ i ] = sum / a[ i ][ i ];
+    }
+    Some( x )
+}
+
+/// Inverse of a square matrix, `None` if singular.
+pub fn inverse<T>( matrix: &DenseMatrix<T> ) -> Option<DenseMatrix<T>>
+where
+    T: Scalar
+{
+    let n = matrix.len();
+    let columns = ( 0..n ).map( |j| {
+        let mut unit = vec![ T::zero(); n ];
+        unit[ j ] = T::one();
+        solve( matrix, &unit )
+    } ).collect::<Option<Vec<_>>>()?;
+    Some( transpose( &columns ) )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solve_test() {
+        let matrix = vec![ vec![ 0.0, 2.0, 1.0 ], vec![ 1.0, 1.0, 0.0 ], vec![ 3.0, 0.0, 1.0 ] ];
+        let x = solve( &matrix, &[ 5.0, 3.0, 6.0 ] ).unwrap();
+        let rhs = multiply_vector( &matrix, &x );
+        for ( value, expected ) in rhs.iter().zip( [ 5.0, 3.0, 6.0 ] ) {
+            assert!( ( value - expected ).abs() < 1e-12 );
+        }
+        assert!( solve( &vec![ vec![ 1.0, 2.0 ], vec![ 2.0, 4.0 ] ], &[ 1.0, 2.0 ] ).is_none() );
+        // Small but well conditioned, e.g. the inertia of a light link.
+        let x = solve( &vec![ vec![ 2e-9, 0.0 ], vec![ 0.0, 4e-9 ] ], &[ 2e-9, 2e-9 ] ).unwrap();
+        assert!( ( x[ 0 ] - 1.0 ).abs() < 1e-12 && ( x[ 1 ] - 0.5 ).abs() < 1e-12 );
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright 2024 Bewusstsein Labs
+
+use std::{
+    fmt::{ self, Debug, Formatter },
+    sync::Arc
+};
+
+use crate::{
+    scalar::Scalar,
+    constraint::{ Range, Constraint },
+    particle::Particle
+};
+
+pub type Bounds<T, const DIM: usize, const ORD: usize> = Arc<dyn Fn( T, &Particle<T, DIM, ORD> ) -> Constraint<T, DIM> + Send + Sync>;
+
+/// Constraint recomputed by a `Joint` every time it is constrained, e.g. the travel of a telescoping
+/// stage or a speed limit that falls with load.
+#[derive( Clone )]
+pub enum DynamicConstraint<T, const DIM: usize, const ORD: usize>
+where
+    T: 'static + Default + Copy + Debug,
+    [(); ORD + 1]:
+{
+    /// Keyframes `( time, constraint )` in increasing time. Range ends are interpolated linearly between
+    /// keyframes where both have a range on the axis and held before the first and after the last;
+    /// limits and shapes are taken from the earlier keyframe.
+    Schedule( Vec<( T, Constraint<T, DIM> )> ),
+    /// User supplied constraint, given the joint time and the joint's particle state.
+    Function( Bounds<T, DIM, ORD> )
+}
+
+impl<T, const DIM: usize, const ORD: usize> DynamicConstraint<T, DIM, ORD>
+where
+    T: Scalar,
+    [(); ORD + 1]:
+{
+    pub fn function<F>( bounds: F ) -> Self
+    where
+        F: 'static + Fn( T, &Particle<T, DIM, ORD> ) -> Constraint<T, DIM> + Send + Sync
+    {
+        DynamicConstraint::Function( Arc::new( bounds ) )
+    }
+
+    /// Constraint in force at `time` for a joint in state `particle`.
+    pub fn evaluate( &self, time: T, particle: &Particle<T, DIM, ORD> ) -> Constraint<T, DIM> {
+        match self {
+            DynamicConstraint::Schedule( keyframes ) => {
+                let Some( ( first, last ) ) = keyframes.first().zip( keyframes.last() ) else { return Constraint::default() };
+                if time <= first.0 {
+                    return first.1;
+                }
+                if time >= last.0 {
+                    return last.1;
+                }
+                let window = keyframes.windows( 2 ).find( |window| time < window[ 1 ].0 ).unwrap_or( &keyframes[ keyframes.len() - 2.. ] );
+                let ( ( start, from ), ( end, to ) ) = ( window[ 0 ], window[ 1 ] );
+                interpolate( &from, &to, ( time - start ) / ( end - start ) )
+            },
+            DynamicConstraint::Function( bounds ) => bounds( time, particle )
+        }
+    }
+}
+
+fn interpolate<T, const DIM: usize>( from: &Constraint<T, DIM>, to: &Constraint<T, DIM>, fraction: T ) -> Constraint<T, DIM>
+where
+    T: Scalar
+{
+    let ranges = std::array::from_fn( |i| match ( from[ i ], to[ i ] ) {
+        ( Some( a ), Some( b ) ) => Some( Range::new(
+            *a.min() + ( *b.min() - *a.min() ) * fraction,
+            *a.max() + ( *b.max() - *a.max() ) * fraction
+        ).with_limit( *a.limit() ) ),
+        ( range, _ ) => range
+    } );
+    Constraint::new( ranges )
+}
+
+impl<T, const DIM: usize, const ORD: usize> Debug for DynamicConstraint<T, DIM, ORD>
+where
+    T: 'static + Default + Copy + Debug,
+    [(); ORD + 1]:
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> fmt::Result {
+        match self {
+            DynamicConstraint::Schedule( keyframes ) => f.debug_tuple( "Schedule" ).field( keyframes ).finish(),
+            DynamicConstraint::Function( _ ) => f.write_str( "Function" )
+        }
+    }
+}
+
+/// Schedules compare by keyframes, functions by identity.
+impl<T, const DIM: usize, const ORD: usize> PartialEq for DynamicConstraint<T, DIM, ORD>
+where
+    T: 'static + Default + Copy + Debug + PartialEq,
+    [(); ORD + 1]:
+{
+    fn eq( &self, other: &Self ) -> bool {
+        match ( self, other ) {
+            ( DynamicConstraint::Schedule( a ), DynamicConstraint::Schedule( b ) ) => a == b,
+            ( DynamicConstraint::Function( a ), DynamicConstraint::Function( b ) ) => Arc::ptr_eq( a, b ),
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+
+    #[test]
+    fn schedule_test() {
+        let travel = |max: f64| Constraint::<f64, 3>::new([ Some( Range::new( 0.0, max ) ), None, None ]);
+        let schedule = DynamicConstraint::<f64, 3, 1>::Schedule( vec![ ( 1.0, travel( 1.0 ) ), ( 3.0, travel( 2.0 ) ), ( 4.0, travel( 4.0 ) ) ] );
+        let particle = Particle::default();
+        assert_eq!( schedule.evaluate( 0.0, &particle ), travel( 1.0 ) );
+        assert_eq!( schedule.evaluate( 2.0, &particle ), travel( 1.5 ) );
+        assert_eq!( schedule.evaluate( 3.5, &particle ), travel( 3.0 ) );
+        assert_eq!( schedule.evaluate( 9.0, &particle ), travel( 4.0 ) );
+
+        let mut position = Vector3::from([ 5.0, 0.0, 0.0 ]);
+        schedule.evaluate( 2.0, &particle ).constrain( &mut position );
+        assert_eq!( position[ 0 ], 1.5 );
+    }
+}
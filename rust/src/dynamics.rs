@@ -0,0 +1,548 @@
+// Copyright 2024 Bewusstsein Labs
+
+use std::{
+    fmt::Debug,
+    collections::BTreeMap
+};
+
+use linear_algebra::vector::Vector;
+
+use crate::{
+    scalar::Scalar,
+    constraint::Constraint,
+    kinematics::{ Pose, Motion },
+    linkage::{ Linkage, Error },
+    force::Wrench,
+    integrator::Integrator,
+    loops::LoopClosure,
+    spatial::{ self, TreeModel },
+    dense::{ self, DenseMatrix },
+    rotation
+};
+
+/// Root joint and its placement for a linkage advanced with multibody dynamics by `Linkage::update`.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct Multibody<I, T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    root: I,
+    base: Pose<T, DIM>
+}
+
+impl<I, T, const DIM: usize> Multibody<I, T, DIM>
+where
+    T: 'static + Default + Copy + Debug
+{
+    pub fn new( root: I, base: Pose<T, DIM> ) -> Self {
+        Self { root, base }
+    }
+
+    pub fn root( &self ) -> &I { &self.root }
+    pub fn base( &self ) -> &Pose<T, DIM> { &self.base }
+}
+
+/// Wrench on a body given as a force through its world centre of mass and a torque, referred to the
+/// world origin.
+fn origin_wrench<T, const DIM: usize>( wrench: &Wrench<T, DIM>, center: &Vector<T, DIM> ) -> Wrench<T, DIM>
+where
+    T: Scalar
+{
+    Wrench::new( wrench.force, wrench.torque + rotation::moment( center, &wrench.force ) )
+}
+
+/// Bias wrench `v ×* I v - f_ext` of every body of `model`.
+fn bias_forces<I, T, const DIM: usize>( model: &TreeModel<I, T, DIM>, velocities: &[Motion<T, DIM>], external: &BTreeMap<I, Wrench<T, DIM>> ) -> Vec<Wrench<T, DIM>>
+where
+    I: Copy + Ord,
+    T: Scalar
+{
+    model.ids().iter().zip( model.inertias().iter() ).zip( velocities.iter() ).map( |( ( id, inertia ), velocity )| {
+        let force = velocity.cross_force( &inertia.apply( velocity ) );
+        match external.get( id ) {
+            Some( wrench ) => force - origin_wrench( wrench, inertia.center() ),
+            None => force
+        }
+    } ).collect()
+}
+
+/// Recursive Newton-Euler pass over `model` moving with `velocities` and rate dependent accelerations
+/// `biases`, returning the joint forces for the coordinate accelerations `accelerations`.
+fn newton_euler<I, T, const DIM: usize>(
+    model: &TreeModel<I, T, DIM>,
+    velocities: &[Motion<T, DIM>],
+    biases: &[Motion<T, DIM>],
+    accelerations: &BTreeMap<I, Vec<T>>,
+    gravity: Vector<T, DIM>,
+    external: &BTreeMap<I, Wrench<T, DIM>>
+) -> BTreeMap<I, Vec<T>>
+where
+    I: Copy + Ord,
+    T: Scalar
+{
+    // Gravity enters as an upward acceleration of the base.
+    let mut forces = Vec::with_capacity( model.len() );
+    let mut motions: Vec<Motion<T, DIM>> = Vec::with_capacity( model.len() );
+    for ( i, id ) in model.ids().iter().enumerate() {
+        let parent = model.parents()[ i ].map_or( Motion::new( Vector::default(), Vector::default() - gravity ), |parent| motions[ parent ] );
+        let joint_accelerations = accelerations.get( id );
+        let acceleration = model.subspaces()[ i ].iter().enumerate().fold( parent + biases[ i ], |sum, ( k, column )| {
+            sum + *column * joint_accelerations.and_then( |values| values.get( k ) ).copied().unwrap_or( T::zero() )
+        } );
+        motions.push( acceleration );
+        forces.push( model.inertias()[ i ].apply( &acceleration ) );
+    }
+    for ( force, bias ) in forces.iter_mut().zip( bias_forces( model, velocities, external ) ) {
+        *force = *force + bias;
+    }
+
+    let mut result = BTreeMap::new();
+    for i in ( 0..model.len() ).rev() {
+        let force = forces[ i ];
+        result.insert( model.ids()[ i ], model.subspaces()[ i ].iter().map( |column| column.power( &force ) ).collect() );
+        if let Some( parent ) = model.parents()[ i ] {
+            forces[ parent ] = forces[ parent ] + force;
+        }
+    }
+    result
+}
+
+/// Joint space equations of motion `M(q) q̈ + C(q, q̇) q̇ + g(q) = τ` of a tree at its current state, with
+/// the coordinates of its joints stacked in breadth first order from the root.
+#[derive( Clone, Default, Debug, PartialEq )]
+pub struct JointSpace<I, T>
+where
+    T: 'static + Default + Copy + Debug
+{
+    joints: Vec<( I, usize )>,
+    mass_matrix: DenseMatrix<T>,
+    coriolis: Vec<T>,
+    gravity: Vec<T>
+}
+
+impl<I, T> JointSpace<I, T>
+where
+    I: Copy,
+    T: Scalar
+{
+    /// Joints with their number of coordinates, in stacking order.
+    pub fn joints( &self ) -> &[( I, usize )] { &self.joints }
+    /// Joint space inertia matrix `M(q)`.
+    pub fn mass_matrix( &self ) -> &DenseMatrix<T> { &self.mass_matrix }
+    /// Coriolis and centrifugal forces `C(q, q̇) q̇`.
+    pub fn coriolis( &self ) -> &[T] { &self.coriolis }
+    /// Gravity forces `g(q)`.
+    pub fn gravity( &self ) -> &[T] { &self.gravity }
+
+    /// Joint forces producing the stacked coordinate accelerations `accelerations`.
+    pub fn forces( &self, accelerations: &[T] ) -> Vec<T> {
+        dense::multiply_vector( &self.mass_matrix, accelerations ).iter().zip( self.coriolis.iter().zip( self.gravity.iter() ) )
+            .map( |( inertial, ( coriolis, gravity ) )| *inertial + *coriolis + *gravity )
+            .collect()
+    }
+}
+
+impl <I, T, const DIM: usize, const ORD: usize> Linkage<I, T, DIM, ORD>
+where
+    I: 'static + Default + Copy + Debug + Ord,
+    T: Scalar,
+    [Constraint<T, DIM>; (ORD + 1) * 2]: Default,
+    [(); ORD + 1]:,
+    [(); (ORD + 1) * 2]:
+{
+    /// Joint forces producing `accelerations` in the tree rooted at `root`, placed at `base`, by recursive
+    /// Newton-Euler. Missing joints are unaccelerated; `external` acts through the centres of mass.
+    pub fn inverse_dynamics(
+        &self,
+        root: I,
+        base: Pose<T, DIM>,
+        accelerations: &BTreeMap<I, Vec<T>>,
+        gravity: Vector<T, DIM>,
+        external: &BTreeMap<I, Wrench<T, DIM>>
+    ) -> Result<BTreeMap<I, Vec<T>>, Error> {
+        let model = self.tree_model( root, base )?;
+        let ( velocities, biases ) = model.velocities();
+        Ok( newton_euler( &model, &velocities, &biases, accelerations, gravity, external ) )
+    }
+
+    /// Coordinate accelerations under `efforts`, gravity and `external` by the articulated body algorithm.
+    /// Fails with `Error::SingularInertia` when a joint moves a subtree without inertia along an axis.
+    pub fn forward_dynamics(
+        &self,
+        root: I,
+        base: Pose<T, DIM>,
+        efforts: &BTreeMap<I, Vec<T>>,
+        gravity: Vector<T, DIM>,
+        external: &BTreeMap<I, Wrench<T, DIM>>
+    ) -> Result<BTreeMap<I, Vec<T>>, Error> {
+        let model = self.tree_model( root, base )?;
+        let ( velocities, biases ) = model.velocities();
+        let size = spatial::spatial_dimension( DIM );
+        let subspaces: Vec<Vec<Vec<T>>> = model.subspaces().iter().map( |columns| columns.iter().map( |column| column.to_vec() ).collect() ).collect();
+        let biases: Vec<Vec<T>> = biases.iter().map( |bias| bias.to_vec() ).collect();
+
+        // Articulated inertias and bias forces, accumulated from the leaves inwards.
+        let mut inertias: Vec<DenseMatrix<T>> = model.inertias().iter().map( |inertia| inertia.matrix() ).collect();
+        let mut forces: Vec<Vec<T>> = bias_forces( &model, &velocities, external ).iter().map( |force| force.to_vec() ).collect();
+        let mut projections: Vec<( Vec<Vec<T>>, DenseMatrix<T>, Vec<T> )> = vec![ ( Vec::new(), Vec::new(), Vec::new() ); model.len() ];
+        for i in ( 0..model.len() ).rev() {
+            let columns = &subspaces[ i ];
+            let u: Vec<Vec<T>> = columns.iter().map( |column| dense::multiply_vector( &inertias[ i ], column ) ).collect();
+            let d: DenseMatrix<T> = columns.iter().map( |row| u.iter().map( |column| dense::dot( row, column ) ).collect() ).collect();
+            let d_inverse = dense::inverse( &d ).ok_or( Error::SingularInertia )?;
+            let joint_efforts = efforts.get( &model.ids()[ i ] );
+            let residual: Vec<T> = columns.iter().enumerate().map( |( k, column )| {
+                joint_efforts.and_then( |values| values.get( k ) ).copied().unwrap_or( T::zero() ) - dense::dot( column, &forces[ i ] )
+            } ).collect();
+
+            if let Some( parent ) = model.parents()[ i ] {
+                // Articulated inertia and bias force transmitted through the joint.
+                let mut inertia = inertias[ i ].clone();
+                for ( j, row ) in d_inverse.iter().enumerate() {
+                    for ( k, weight ) in row.iter().enumerate() {
+                        for r in 0..size {
+                            for c in 0..size {
+                                inertia[ r ][ c ] -= u[ j ][ r ] * *weight * u[ k ][ c ];
+                            }
+                        }
+                    }
+                }
+                let gain = dense::multiply_vector( &d_inverse, &residual );
+                let mut force = forces[ i ].clone();
+                for ( value, transmitted ) in force.iter_mut().zip( dense::multiply_vector( &inertia, &biases[ i ] ) ) {
+                    *value += transmitted;
+                }
+                for ( column, weight ) in u.iter().zip( gain.iter() ) {
+                    for ( value, component ) in force.iter_mut().zip( column.iter() ) {
+                        *value += *component * *weight;
+                    }
+                }
+                for r in 0..size {
+                    forces[ parent ][ r ] += force[ r ];
+                    for c in 0..size {
+                        inertias[ parent ][ r ][ c ] += inertia[ r ][ c ];
+                    }
+                }
+            }
+            projections[ i ] = ( u, d_inverse, residual );
+        }
+
+        // Accelerations propagated outwards, with gravity as an upward acceleration of the base.
+        let mut accelerations: Vec<Vec<T>> = Vec::with_capacity( model.len() );
+        let mut result = BTreeMap::new();
+        for i in 0..model.len() {
+            let mut acceleration = model.parents()[ i ].map_or_else( || Motion::new( Vector::default(), Vector::default() - gravity ).to_vec(), |parent| accelerations[ parent ].clone() );
+            for ( value, bias ) in acceleration.iter_mut().zip( biases[ i ].iter() ) {
+                *value += *bias;
+            }
+            let ( u, d_inverse, residual ) = &projections[ i ];
+            let rhs: Vec<T> = residual.iter().zip( u.iter() ).map( |( value, column )| *value - dense::dot( column, &acceleration ) ).collect();
+            let joint_accelerations = dense::multiply_vector( d_inverse, &rhs );
+            for ( column, rate ) in subspaces[ i ].iter().zip( joint_accelerations.iter() ) {
+                for ( value, component ) in acceleration.iter_mut().zip( column.iter() ) {
+                    *value += *component * *rate;
+                }
+            }
+            accelerations.push( acceleration );
+            result.insert( model.ids()[ i ], joint_accelerations );
+        }
+        Ok( result )
+    }
+
+    /// Joint space mass matrix by the composite rigid body algorithm, with the Coriolis and gravity
+    /// forces by Newton-Euler passes, of the tree rooted at `root`, placed at `base`.
+    pub fn joint_space_dynamics( &self, root: I, base: Pose<T, DIM>, gravity: Vector<T, DIM> ) -> Result<JointSpace<I, T>, Error> {
+        let model = self.tree_model( root, base )?;
+        let ( offsets, total ) = model.offsets();
+        let subspaces: Vec<Vec<Vec<T>>> = model.subspaces().iter().map( |columns| columns.iter().map( |column| column.to_vec() ).collect() ).collect();
+
+        // Composite inertias of every subtree; all inertias share the world origin, so they simply add.
+        let mut composites: Vec<DenseMatrix<T>> = model.inertias().iter().map( |inertia| inertia.matrix() ).collect();
+        for i in ( 0..model.len() ).rev() {
+            if let Some( parent ) = model.parents()[ i ] {
+                let composite = composites[ i ].clone();
+                for ( row, added ) in composites[ parent ].iter_mut().zip( composite.iter() ) {
+                    for ( value, addition ) in row.iter_mut().zip( added.iter() ) {
+                        *value += *addition;
+                    }
+                }
+            }
+        }
+
+        let mut mass_matrix = dense::zeros( total, total );
+        for i in 0..model.len() {
+            for ( k, column ) in subspaces[ i ].iter().enumerate() {
+                let force = dense::multiply_vector( &composites[ i ], column );
+                let row = offsets[ i ] + k;
+                let mut j = Some( i );
+                while let Some( ancestor ) = j {
+                    for ( l, other ) in subspaces[ ancestor ].iter().enumerate() {
+                        let value = dense::dot( other, &force );
+                        mass_matrix[ row ][ offsets[ ancestor ] + l ] = value;
+                        mass_matrix[ offsets[ ancestor ] + l ][ row ] = value;
+                    }
+                    j = model.parents()[ ancestor ];
+                }
+            }
+        }
+
+        let stack = |forces: BTreeMap<I, Vec<T>>| -> Vec<T> {
+            model.ids().iter().flat_map( |id| forces.get( id ).cloned().unwrap_or_default() ).collect()
+        };
+        let ( velocities, biases ) = model.velocities();
+        let coriolis = stack( newton_euler( &model, &velocities, &biases, &BTreeMap::new(), Vector::default(), &BTreeMap::new() ) );
+        let resting = vec![ Motion::default(); model.len() ];
+        let gravity = stack( newton_euler( &model, &resting, &resting, &BTreeMap::new(), gravity, &BTreeMap::new() ) );
+        let joints = model.ids().iter().zip( model.subspaces().iter() ).map( |( id, columns )| ( *id, columns.len() ) ).collect();
+        Ok( JointSpace { joints, mass_matrix, coriolis, gravity } )
+    }
+
+    /// Steps the tree of `multibody` with the articulated body algorithm and joints outside it on their
+    /// own, then closes its cut links with `close_loops`. Fails with `Error::ForceFieldsUnsupported`
+    /// before moving anything if force fields act on joints with `ORD < 2`, and with
+    /// `Error::LoopNotClosed`, keeping the step, if the loops do not close. Forces are always cleared.
+    pub fn update_multibody<N>( &mut self, multibody: &Multibody<I, T, DIM>, integrator: &N, time_step: T ) -> Result<(), Error>
+    where
+        N: Integrator<T>
+    {
+        let result = self.step_multibody( multibody, integrator, time_step );
+        self.clear_forces();
+        result
+    }
+
+    fn step_multibody<N>( &mut self, multibody: &Multibody<I, T, DIM>, integrator: &N, time_step: T ) -> Result<(), Error>
+    where
+        N: Integrator<T>
+    {
+        self.apply_force_fields();
+        let mut efforts = BTreeMap::new();
+        let mut external = BTreeMap::new();
+        for id in self.joint_ids() {
+            let joint = self.get_joint( id ).ok_or( Error::JointNotFound )?;
+            efforts.insert( id, joint.efforts().to_vec() );
+            external.insert( id, Wrench::new( *joint.force(), *joint.torque() ) + joint.limit_wrench() );
+        }
+        let accelerations = self.forward_dynamics( *multibody.root(), *multibody.base(), &efforts, Vector::default(), &external )?;
+        let outside = self.joint_ids().into_iter().filter( |id| !accelerations.contains_key( id ) ).collect::<Vec<_>>();
+        if ORD < 2 && !outside.is_empty() && !self.force_fields().is_empty() {
+            return Err( Error::ForceFieldsUnsupported );
+        }
+        if ORD >= 2 && outside.iter().any( |id| self.get_joint( *id ).is_some_and( |joint| joint.inverse_world_inertia().is_none() ) ) {
+            return Err( Error::SingularInertia );
+        }
+
+        for ( id, joint_accelerations ) in accelerations.iter() {
+            let Some( joint ) = self.get_joint_mut( *id ) else { continue };
+            let mut amounts = Vec::with_capacity( joint_accelerations.len() );
+            for ( rate, acceleration ) in joint.rates_mut().iter_mut().zip( joint_accelerations.iter() ) {
+                let mut stack = [ Vector::<T, 1>::default(), Vector::from([ *rate ]), Vector::from([ *acceleration ]) ];
+                integrator.integrate( &mut stack, time_step );
+                amounts.push( stack[ 0 ][ 0 ] );
+                *rate = stack[ 1 ][ 0 ];
+            }
+            joint.displace( &amounts );
+        }
+
+        let mut closed = true;
+        if !self.spanning_tree( *multibody.root() )?.cut_links().is_empty() {
+            let solver = self.closed_loops().map_or_else( LoopClosure::default, |closed_loops| *closed_loops.solver() );
+            closed = self.close_loops( &solver, *multibody.root(), *multibody.base(), &[] )?.converged();
+        }
+
+        let kinematics = self.apply_forward_kinematics( *multibody.root(), *multibody.base() )?;
+        let model = self.tree_model( *multibody.root(), *multibody.base() )?;
+        let ( velocities, _ ) = model.velocities();
+        let coordinate_accelerations = model.ids().iter().map( |id| accelerations.get( id ).cloned().unwrap_or_default() ).collect::<Vec<_>>();
+        let body_accelerations = model.accelerations( &coordinate_accelerations );
+        for ( ( id, velocity ), acceleration ) in model.ids().iter().zip( velocities.iter() ).zip( body_accelerations.iter() ) {
+            let position = *kinematics.pose( id ).ok_or( Error::JointNotFound )?.position();
+            let Some( joint ) = self.get_joint_mut( *id ) else { continue };
+            if ORD >= 1 {
+                joint.spatial[ 1 ] = velocity.shift( &position ).linear;
+                joint.angular[ 1 ] = velocity.angular;
+            }
+            if ORD >= 2 {
+                let linear = velocity.shift( &position ).linear;
+                joint.spatial[ 2 ] = acceleration.shift( &position ).linear + rotation::tangent( &velocity.angular, &linear );
+                joint.angular[ 2 ] = acceleration.angular;
+            }
+        }
+
+        for id in self.joint_ids() {
+            let Some( joint ) = self.get_joint_mut( id ) else { continue };
+            if !accelerations.contains_key( &id ) {
+                if ORD >= 2 {
+                    let velocity = joint.angular[ 1 ];
+                    let angular = joint.angular_acceleration_at( &velocity ).unwrap_or_default();
+                    joint.spatial[ 2 ] = joint.linear_acceleration_at( &velocity, &angular );
+                    joint.angular[ 2 ] = angular;
+                }
+                joint.update( integrator, time_step );
+            }
+        }
+        self.constrain_links();
+        self.advance_time( time_step );
+        if closed { Ok( () ) } else { Err( Error::LoopNotClosed ) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+    use crate::{
+        body::Body3D,
+        joint::{ Joint3D, JointKind },
+        link::Link,
+        constraint::Constraint3D,
+        force::ForceField,
+        integrator::SemiImplicitEuler,
+        linkage::Linkage3D
+    };
+
+    #[test]
+    fn pendulum_test() {
+        let ( mass, length, gravity ) = ( 2.0, 0.5, 9.81 );
+        let ( angle, rate, acceleration ) = ( 0.3, 1.7, -0.8 );
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        let mut pendulum = Joint3D::new( Body3D::new( mass, [ Vector3::default(); 2 ], [ Vector3::default(); 2 ] ), [ Constraint3D::default(); 4 ] ).with_kind( JointKind::Revolute { axis: Vector3::from([ 0.0, 0.0, 1.0 ]) } );
+        *pendulum.center_of_mass_mut() = Vector3::from([ length, 0.0, 0.0 ]);
+        linkage.add_joint( 0, pendulum ).unwrap();
+        let pendulum = linkage.get_joint_mut( 0 ).unwrap();
+        pendulum.set_coordinates( &[ angle ] );
+        pendulum.rates_mut()[ 0 ] = rate;
+
+        let torques = linkage.inverse_dynamics(
+            0,
+            Pose::identity(),
+            &BTreeMap::from([ ( 0, vec![ acceleration ] ) ]),
+            Vector3::from([ 0.0, -gravity, 0.0 ]),
+            &BTreeMap::new()
+        ).unwrap();
+        let expected = mass * length * length * acceleration + mass * gravity * length * angle.cos();
+        assert!( ( torques[ &0 ][ 0 ] - expected ).abs() < 1e-12 );
+
+        // An external force through the centre of mass cancelling gravity leaves only the inertial torque.
+        let lift = BTreeMap::from([ ( 0, Wrench::new( Vector3::from([ 0.0, mass * gravity, 0.0 ]), Vector3::default() ) ) ]);
+        let torques = linkage.inverse_dynamics( 0, Pose::identity(), &BTreeMap::from([ ( 0, vec![ acceleration ] ) ]), Vector3::from([ 0.0, -gravity, 0.0 ]), &lift ).unwrap();
+        assert!( ( torques[ &0 ][ 0 ] - mass * length * length * acceleration ).abs() < 1e-12 );
+    }
+
+    fn double_pendulum() -> Linkage3D<usize, f64, 1> {
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        for ( id, mass, center ) in [ ( 0, 1.0, 0.5 ), ( 1, 2.0, 0.25 ) ] {
+            let mut joint = Joint3D::new( Body3D::new( mass, [ Vector3::default(); 2 ], [ Vector3::default(); 2 ] ), [ Constraint3D::default(); 4 ] ).with_kind( JointKind::Revolute { axis: Vector3::from([ 0.0, 0.0, 1.0 ]) } );
+            *joint.center_of_mass_mut() = Vector3::from([ center, 0.0, 0.0 ]);
+            linkage.add_joint( id, joint ).unwrap();
+        }
+        linkage.add_link( 0, 1, Link::default().with_transform( Pose::new( Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::default() ) ) ).unwrap();
+        linkage
+    }
+
+    #[test]
+    fn double_pendulum_test() {
+        let ( gravity, length ) = ( 10.0, 1.0 );
+        let mut linkage = double_pendulum();
+        let torques = linkage.inverse_dynamics( 0, Pose::identity(), &BTreeMap::new(), Vector3::from([ 0.0, -gravity, 0.0 ]), &BTreeMap::new() ).unwrap();
+        assert!( ( torques[ &1 ][ 0 ] - 2.0 * gravity * 0.25 ).abs() < 1e-12 );
+        assert!( ( torques[ &0 ][ 0 ] - ( 1.0 * gravity * 0.5 + 2.0 * gravity * ( length + 0.25 ) ) ).abs() < 1e-12 );
+
+        // Spinning the outer joint pulls the inner one outwards along the arm, adding no torque about it.
+        linkage.get_joint_mut( 1 ).unwrap().rates_mut()[ 0 ] = 3.0;
+        let spinning = linkage.inverse_dynamics( 0, Pose::identity(), &BTreeMap::new(), Vector3::default(), &BTreeMap::new() ).unwrap();
+        assert!( spinning[ &0 ][ 0 ].abs() < 1e-12 );
+        assert!( spinning[ &1 ][ 0 ].abs() < 1e-12 );
+    }
+
+    #[test]
+    fn forward_dynamics_test() {
+        let gravity = Vector3::from([ 0.0, -9.81, 0.0 ]);
+        let mut linkage = double_pendulum();
+        for ( id, angle, rate ) in [ ( 0, 0.4, -1.2 ), ( 1, -0.7, 2.5 ) ] {
+            let joint = linkage.get_joint_mut( id ).unwrap();
+            joint.set_coordinates( &[ angle ] );
+            joint.rates_mut()[ 0 ] = rate;
+        }
+        let efforts = BTreeMap::from([ ( 0, vec![ 3.0 ] ), ( 1, vec![ -1.5 ] ) ]);
+        let external = BTreeMap::from([ ( 1, Wrench::new( Vector3::from([ 0.5, 1.0, 0.0 ]), Vector3::from([ 0.0, 0.0, 0.2 ]) ) ) ]);
+        let accelerations = linkage.forward_dynamics( 0, Pose::identity(), &efforts, gravity, &external ).unwrap();
+        let torques = linkage.inverse_dynamics( 0, Pose::identity(), &accelerations, gravity, &external ).unwrap();
+        for id in 0..2 {
+            assert!( ( torques[ &id ][ 0 ] - efforts[ &id ][ 0 ] ).abs() < 1e-9, "joint {}", id );
+        }
+
+        let mut massless = Linkage3D::<usize, f64, 1>::new();
+        massless.add_joint( 0, Joint3D::new( Body3D::new( 0.0, [ Vector3::default(); 2 ], [ Vector3::default(); 2 ] ), [ Constraint3D::default(); 4 ] ).with_kind( JointKind::Revolute { axis: Vector3::from([ 0.0, 0.0, 1.0 ]) } ) ).unwrap();
+        assert!( massless.forward_dynamics( 0, Pose::identity(), &efforts, gravity, &external ).is_err() );
+
+        // Stepping it fails as well, leaving it in place without the forces applied for the step.
+        massless.set_multibody( Some( Multibody::new( 0, Pose::identity() ) ) );
+        massless.get_joint_mut( 0 ).unwrap().apply_force( Vector3::from([ 1.0, 0.0, 0.0 ]) );
+        assert!( matches!( massless.update( &SemiImplicitEuler, 0.1 ), Err( Error::SingularInertia ) ) );
+        let joint = massless.get_joint( 0 ).unwrap();
+        assert_eq!( *joint.force(), Vector3::default() );
+        assert_eq!( joint.coordinates()[ 0 ], 0.0 );
+        assert_eq!( *massless.time(), 0.0 );
+    }
+
+    #[test]
+    fn update_multibody_test() {
+        let ( mass, length, gravity, time_step ) = ( 2.0, 0.5, 9.81, 0.001 );
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        let mut pendulum = Joint3D::new( Body3D::new( mass, [ Vector3::default(); 2 ], [ Vector3::default(); 2 ] ), [ Constraint3D::default(); 4 ] ).with_kind( JointKind::Revolute { axis: Vector3::from([ 0.0, 0.0, 1.0 ]) } );
+        *pendulum.center_of_mass_mut() = Vector3::from([ length, 0.0, 0.0 ]);
+        linkage.add_joint( 0, pendulum ).unwrap();
+        linkage.add_force_field( ForceField::Gravity { acceleration: Vector3::from([ 0.0, -gravity, 0.0 ]) } );
+        linkage.set_multibody( Some( Multibody::new( 0, Pose::identity() ) ) );
+
+        let ( mut angle, mut rate ) = ( 0.0, 0.0 );
+        for _ in 0..200 {
+            linkage.update( &SemiImplicitEuler, time_step ).unwrap();
+            rate -= gravity * angle.cos() / length * time_step;
+            angle += rate * time_step;
+        }
+        let joint = linkage.get_joint( 0 ).unwrap();
+        assert!( ( joint.coordinates()[ 0 ] - angle ).abs() < 1e-9 );
+        assert!( ( joint.rates()[ 0 ] - rate ).abs() < 1e-9 );
+        assert!( ( joint.angular_velocity()[ 2 ] - rate ).abs() < 1e-9 );
+        assert!( ( joint.rotation()[ 2 ] - angle ).abs() < 1e-9 );
+        assert!( ( *linkage.time() - 0.2 ).abs() < 1e-12 );
+
+        // With accelerations in the state the tree's are stored, and a joint outside it falls freely.
+        let mut pendulum = Joint3D::new( Body3D::new( mass, [ Vector3::default(); 3 ], [ Vector3::default(); 3 ] ), [ Constraint3D::default(); 6 ] )
+            .with_kind( JointKind::Revolute { axis: Vector3::from([ 0.0, 0.0, 1.0 ]) } );
+        *pendulum.center_of_mass_mut() = Vector3::from([ length, 0.0, 0.0 ]);
+        let mut linkage = Linkage3D::<usize, f64, 2>::new();
+        linkage.add_joint( 0, pendulum ).unwrap();
+        linkage.add_joint( 1, Joint3D::new( Body3D::new( 1.0, [ Vector3::from([ 2.0, 0.0, 0.0 ]), Vector3::default(), Vector3::default() ], [ Vector3::default(); 3 ] ), [ Constraint3D::default(); 6 ] ) ).unwrap();
+        linkage.add_force_field( ForceField::Gravity { acceleration: Vector3::from([ 0.0, -gravity, 0.0 ]) } );
+        linkage.set_multibody( Some( Multibody::new( 0, Pose::identity() ) ) );
+        linkage.update( &SemiImplicitEuler, time_step ).unwrap();
+        assert!( ( linkage.get_joint( 0 ).unwrap().angular_acceleration()[ 2 ] + gravity / length ).abs() < 1e-9 );
+        assert!( ( linkage.get_joint( 1 ).unwrap().spatial_acceleration()[ 1 ] + gravity ).abs() < 1e-12 );
+        assert!( ( linkage.get_joint( 1 ).unwrap().spatial_velocity()[ 1 ] + gravity * time_step ).abs() < 1e-12 );
+    }
+
+    #[test]
+    fn joint_space_dynamics_test() {
+        let gravity = Vector3::from([ 0.0, -9.81, 0.0 ]);
+        let mut linkage = double_pendulum();
+        for ( id, angle, rate ) in [ ( 0, 0.4, -1.2 ), ( 1, -0.7, 2.5 ) ] {
+            let joint = linkage.get_joint_mut( id ).unwrap();
+            joint.set_coordinates( &[ angle ] );
+            joint.rates_mut()[ 0 ] = rate;
+        }
+        let dynamics = linkage.joint_space_dynamics( 0, Pose::identity(), gravity ).unwrap();
+        assert_eq!( dynamics.joints(), &[ ( 0, 1 ), ( 1, 1 ) ] );
+        let mass_matrix = dynamics.mass_matrix();
+        assert!( ( mass_matrix[ 0 ][ 1 ] - mass_matrix[ 1 ][ 0 ] ).abs() < 1e-12 );
+        // The outer joint only carries its own point mass.
+        assert!( ( mass_matrix[ 1 ][ 1 ] - 2.0 * 0.25 * 0.25 ).abs() < 1e-12 );
+
+        let accelerations = [ 0.3, -2.0 ];
+        let expected = linkage.inverse_dynamics( 0, Pose::identity(), &BTreeMap::from([ ( 0, vec![ 0.3 ] ), ( 1, vec![ -2.0 ] ) ]), gravity, &BTreeMap::new() ).unwrap();
+        for ( id, force ) in dynamics.forces( &accelerations ).iter().enumerate() {
+            assert!( ( force - expected[ &id ][ 0 ] ).abs() < 1e-9, "joint {}", id );
+        }
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright 2024 Bewusstsein Labs
+
+use std::{
+    fmt::{ self, Debug, Formatter },
+    ops::{ Add, Sub }
+};
+
+use linear_algebra::vector::Vector;
+
+use crate::{
+    scalar::Scalar,
+    body::Body,
+    rotation::norm
+};
+
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct Wrench<T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    pub force: Vector<T, DIM>,
+    pub torque: Vector<T, DIM>
+}
+
+impl<T, const DIM: usize> Wrench<T, DIM>
+where
+    T: 'static + Default + Copy + Debug
+{
+    pub fn new( force: Vector<T, DIM>, torque: Vector<T, DIM> ) -> Self {
+        Self { force, torque }
+    }
+}
+
+impl<T, const DIM: usize> Add for Wrench<T, DIM>
+where
+    T: Scalar
+{
+    type Output = Self;
+
+    fn add( self, rhs: Self ) -> Self::Output {
+        Self::new( self.force + rhs.force, self.torque + rhs.torque )
+    }
+}
+
+impl<T, const DIM: usize> Sub for Wrench<T, DIM>
+where
+    T: Scalar
+{
+    type Output = Self;
+
+    fn sub( self, rhs: Self ) -> Self::Output {
+        Self::new( self.force - rhs.force, self.torque - rhs.torque )
+    }
+}
+
+pub type Field<T, const DIM: usize, const ORD: usize> = Box<dyn Fn( &Body<T, DIM, ORD>, T ) -> Wrench<T, DIM> + Send + Sync>;
+
+/// External influence evaluated on every joint of a `Linkage` by its dynamic updates: `update_dynamics`,
+/// `advance`, and `update` with a multibody set.
+pub enum ForceField<I, T, const DIM: usize, const ORD: usize>
+where
+    T: 'static + Default + Copy + Debug,
+    [(); ORD + 1]:
+{
+    /// Uniform gravitational acceleration acting at each centre of mass.
+    Gravity { acceleration: Vector<T, DIM> },
+    /// Drag proportional to velocity: `F = -linear v`, `τ = -angular ω`.
+    LinearDrag { linear: T, angular: T },
+    /// Drag proportional to the square of velocity: `F = -linear |v| v`, `τ = -angular |ω| ω`.
+    QuadraticDrag { linear: T, angular: T },
+    /// Damped spring between the origin of `joint` and a fixed world `anchor`.
+    Spring { joint: I, anchor: Vector<T, DIM>, stiffness: T, damping: T, rest_length: T },
+    /// User supplied wrench, given the body and the linkage time.
+    Custom( Field<T, DIM, ORD> )
+}
+
+impl<I, T, const DIM: usize, const ORD: usize> ForceField<I, T, DIM, ORD>
+where
+    I: PartialEq,
+    T: Scalar,
+    [(); ORD + 1]:
+{
+    pub fn custom<F>( field: F ) -> Self
+    where
+        F: 'static + Fn( &Body<T, DIM, ORD>, T ) -> Wrench<T, DIM> + Send + Sync
+    {
+        ForceField::Custom( Box::new( field ) )
+    }
+
+    /// Applies the field to the body of joint `id` at linkage time `time`.
+    pub fn apply( &self, id: &I, body: &mut Body<T, DIM, ORD>, time: T ) {
+        let velocity = if ORD >= 1 { body.spatial[ 1 ] } else { Vector::default() };
+        let angular_velocity = if ORD >= 1 { body.angular[ 1 ] } else { Vector::default() };
+        match self {
+            ForceField::Gravity { acceleration } => {
+                let mass = *body.mass();
+                body.apply_force( *acceleration * mass );
+            },
+            ForceField::LinearDrag { linear, angular } => {
+                body.apply_force( velocity * -*linear );
+                body.apply_torque( angular_velocity * -*angular );
+            },
+            ForceField::QuadraticDrag { linear, angular } => {
+                body.apply_force( velocity * ( -*linear * norm( &velocity ) ) );
+                body.apply_torque( angular_velocity * ( -*angular * norm( &angular_velocity ) ) );
+            },
+            ForceField::Spring { joint, anchor, stiffness, damping, rest_length } => {
+                if joint != id { return; }
+                let position = *body.position();
+                let offset = position - *anchor;
+                let length = norm( &offset );
+                if length <= T::epsilon() { return; }
+                let direction = offset / length;
+                let speed = crate::rotation::dot( &velocity, &direction );
+                let magnitude = *stiffness * ( length - *rest_length ) + *damping * speed;
+                body.apply_force_at_point( direction * -magnitude, position );
+            },
+            ForceField::Custom( field ) => {
+                let wrench = field( body, time );
+                body.apply_force( wrench.force );
+                body.apply_torque( wrench.torque );
+            }
+        }
+    }
+}
+
+impl<I, T, const DIM: usize, const ORD: usize> Debug for ForceField<I, T, DIM, ORD>
+where
+    I: Debug,
+    T: 'static + Default + Copy + Debug,
+    [(); ORD + 1]:
+{
+    fn fmt( &self, f: &mut Formatter<'_> ) -> fmt::Result {
+        match self {
+            ForceField::Gravity { acceleration } => f.debug_struct( "Gravity" ).field( "acceleration", acceleration ).finish(),
+            ForceField::LinearDrag { linear, angular } => f.debug_struct( "LinearDrag" ).field( "linear", linear ).field( "angular", angular ).finish(),
+            ForceField::QuadraticDrag { linear, angular } => f.debug_struct( "QuadraticDrag" ).field( "linear", linear ).field( "angular", angular ).finish(),
+            ForceField::Spring { joint, anchor, stiffness, damping, rest_length } => f.debug_struct( "Spring" )
+                .field( "joint", joint )
+                .field( "anchor", anchor )
+                .field( "stiffness", stiffness )
+                .field( "damping", damping )
+                .field( "rest_length", rest_length )
+                .finish(),
+            ForceField::Custom( _ ) => f.write_str( "Custom" )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+    use crate::{
+        body::Body3D,
+        joint::Joint3D,
+        linkage::Linkage3D,
+        constraint::Constraint3D,
+        integrator::DormandPrince
+    };
+
+    fn single( mass: f64, position: Vector3<f64>, fields: Vec<ForceField<usize, f64, 3, 2>> ) -> Linkage3D<usize, f64, 2> {
+        let mut linkage = Linkage3D::<usize, f64, 2>::new();
+        linkage.add_joint( 0,
+            Joint3D::new(
+                Body3D::new( mass, [ position, Vector3::default(), Vector3::default() ], [ Vector3::default(); 3 ] ),
+                [ Constraint3D::default(); 6 ]
+            )
+        ).unwrap();
+        for field in fields {
+            linkage.add_force_field( field );
+        }
+        linkage
+    }
+
+    fn integrator() -> DormandPrince<f64> {
+        DormandPrince::new( 1e-10, 1e-10, 1e-8, 0.1 )
+    }
+
+    #[test]
+    fn linear_drag_test() {
+        let ( mass, drag, gravity ) = ( 2.0, 4.0, 9.81 );
+        let terminal = mass * gravity / drag;
+        let mut linkage = single( mass, Vector3::default(), vec![
+            ForceField::Gravity { acceleration: Vector3::from([ 0.0, 0.0, -gravity ]) },
+            ForceField::LinearDrag { linear: drag, angular: 0.0 }
+        ] );
+        linkage.advance( &integrator(), 1.0 ).unwrap();
+        let expected = -terminal * ( 1.0 - ( -drag / mass ).exp() );
+        assert!( ( linkage.get_joint( 0 ).unwrap().spatial_velocity()[ 2 ] - expected ).abs() < 1e-8 );
+        linkage.advance( &integrator(), 20.0 ).unwrap();
+        assert!( ( linkage.get_joint( 0 ).unwrap().spatial_velocity()[ 2 ] + terminal ).abs() < 1e-8 );
+    }
+
+    #[test]
+    fn quadratic_drag_test() {
+        let ( mass, drag, gravity ) = ( 1.0, 0.5, 9.81 );
+        let terminal = ( mass * gravity / drag ).sqrt();
+        let mut linkage = single( mass, Vector3::default(), vec![
+            ForceField::Gravity { acceleration: Vector3::from([ 0.0, 0.0, -gravity ]) },
+            ForceField::QuadraticDrag { linear: drag, angular: 0.0 }
+        ] );
+        linkage.advance( &integrator(), 0.5 ).unwrap();
+        let expected = -terminal * ( gravity * 0.5 / terminal ).tanh();
+        assert!( ( linkage.get_joint( 0 ).unwrap().spatial_velocity()[ 2 ] - expected ).abs() < 1e-8 );
+        linkage.advance( &integrator(), 20.0 ).unwrap();
+        assert!( ( linkage.get_joint( 0 ).unwrap().spatial_velocity()[ 2 ] + terminal ).abs() < 1e-8 );
+    }
+
+    #[test]
+    fn spring_test() {
+        // Hangs `mass * gravity / stiffness` below its rest length once damped out.
+        let ( mass, stiffness, gravity ) = ( 1.0, 10.0, 10.0 );
+        let mut linkage = single( mass, Vector3::from([ 0.0, 0.0, -1.0 ]), vec![
+            ForceField::Gravity { acceleration: Vector3::from([ 0.0, 0.0, -gravity ]) },
+            ForceField::Spring { joint: 0, anchor: Vector3::default(), stiffness, damping: 2.0, rest_length: 1.0 }
+        ] );
+        linkage.advance( &integrator(), 30.0 ).unwrap();
+        let joint = linkage.get_joint( 0 ).unwrap();
+        assert!( ( joint.position()[ 2 ] + 1.0 + mass * gravity / stiffness ).abs() < 1e-8 );
+        assert!( joint.spatial_velocity()[ 2 ].abs() < 1e-8 );
+
+        // Undamped, it returns to its start after one period `2π sqrt( mass / stiffness )`.
+        let ( mass, stiffness ) = ( 1.0, 4.0 );
+        let start = Vector3::from([ 1.0, 0.0, 0.0 ]);
+        let mut linkage = single( mass, start, vec![
+            ForceField::Spring { joint: 0, anchor: Vector3::default(), stiffness, damping: 0.0, rest_length: 0.0 }
+        ] );
+        linkage.advance( &integrator(), 2.0 * std::f64::consts::PI * ( mass / stiffness ).sqrt() ).unwrap();
+        let joint = linkage.get_joint( 0 ).unwrap();
+        assert!( ( joint.position()[ 0 ] - start[ 0 ] ).abs() < 1e-7 );
+        assert!( joint.spatial_velocity()[ 0 ].abs() < 1e-7 );
+    }
+}
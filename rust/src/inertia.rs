@@ -0,0 +1,200 @@
+// Copyright 2024 Bewusstsein Labs
+
+use std::ops::Add;
+
+use linear_algebra::vector::Vector;
+
+use crate::{
+    scalar::Scalar,
+    rotation::{ Matrix3, dot, identity, transpose, matrix_vector, matrix_matrix }
+};
+
+/// Mass, centre of mass and inertia tensor about the centre of mass, all in the body frame.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct MassProperties<T>
+where
+    T: 'static + Default + Copy + std::fmt::Debug
+{
+    mass: T,
+    center_of_mass: Vector<T, 3>,
+    inertia: Matrix3<T>
+}
+
+impl<T> MassProperties<T>
+where
+    T: Scalar
+{
+    pub fn new( mass: T, center_of_mass: Vector<T, 3>, inertia: Matrix3<T> ) -> Self {
+        Self { mass, center_of_mass, inertia }
+    }
+
+    pub fn point( mass: T ) -> Self {
+        Self::new( mass, Vector::default(), Matrix3::default() )
+    }
+
+    pub fn mass( &self ) -> &T { &self.mass }
+    pub fn center_of_mass( &self ) -> &Vector<T, 3> { &self.center_of_mass }
+    pub fn inertia( &self ) -> &Matrix3<T> { &self.inertia }
+
+    /// Inertia tensor about `point` by the parallel axis theorem.
+    pub fn inertia_about( &self, point: &Vector<T, 3> ) -> Matrix3<T> {
+        let offset = self.center_of_mass - *point;
+        let distance = dot( &offset, &offset );
+        let mut inertia = self.inertia;
+        for i in 0..3 {
+            for j in 0..3 {
+                let diagonal = if i == j { distance } else { T::zero() };
+                inertia[ i ][ j ] += self.mass * ( diagonal - offset[ i ] * offset[ j ] );
+            }
+        }
+        inertia
+    }
+
+    pub fn translated( &self, offset: &Vector<T, 3> ) -> Self {
+        Self::new( self.mass, self.center_of_mass + *offset, self.inertia )
+    }
+
+    /// Expresses the properties in a frame rotated by `rotation` relative to the current one.
+    pub fn rotated( &self, rotation: &Matrix3<T> ) -> Self {
+        Self::new(
+            self.mass,
+            matrix_vector( rotation, &self.center_of_mass ),
+            matrix_matrix( &matrix_matrix( rotation, &self.inertia ), &transpose( rotation ) )
+        )
+    }
+}
+
+impl<T> Add for MassProperties<T>
+where
+    T: Scalar
+{
+    type Output = Self;
+
+    fn add( self, rhs: Self ) -> Self::Output {
+        let mass = self.mass + rhs.mass;
+        if mass == T::zero() {
+            return Self::point( mass );
+        }
+        let center_of_mass = ( self.center_of_mass * self.mass + rhs.center_of_mass * rhs.mass ) / mass;
+        let lhs_inertia = self.inertia_about( &center_of_mass );
+        let rhs_inertia = rhs.inertia_about( &center_of_mass );
+        let mut inertia = Matrix3::default();
+        for i in 0..3 {
+            inertia[ i ] = lhs_inertia[ i ] + rhs_inertia[ i ];
+        }
+        Self::new( mass, center_of_mass, inertia )
+    }
+}
+
+/// Primitive solids centred on the origin with their axis of symmetry along z.
+///
+/// `Box` takes full edge lengths, `Capsule` takes the length of its cylindrical section and `Rod` is a thin
+/// rod whose density is per unit length.
+#[derive( Clone, Copy, Debug, PartialEq )]
+pub enum Shape<T>
+where
+    T: 'static + Default + Copy + std::fmt::Debug
+{
+    Box { extents: Vector<T, 3> },
+    Sphere { radius: T },
+    Cylinder { radius: T, height: T },
+    Capsule { radius: T, height: T },
+    Rod { length: T }
+}
+
+impl<T> Shape<T>
+where
+    T: Scalar
+{
+    /// Volume, or length for a `Rod`.
+    pub fn measure( &self ) -> T {
+        let pi = T::from_f64( std::f64::consts::PI );
+        match *self {
+            Shape::Box { extents } => extents[ 0 ] * extents[ 1 ] * extents[ 2 ],
+            Shape::Sphere { radius } => T::from_usize( 4 ) / T::from_usize( 3 ) * pi * radius.powi( 3 ),
+            Shape::Cylinder { radius, height } => pi * radius * radius * height,
+            Shape::Capsule { radius, height } => pi * radius * radius * height + T::from_usize( 4 ) / T::from_usize( 3 ) * pi * radius.powi( 3 ),
+            Shape::Rod { length } => length
+        }
+    }
+
+    pub fn mass_properties_from_density( &self, density: T ) -> MassProperties<T> {
+        self.mass_properties( density * self.measure() )
+    }
+
+    pub fn mass_properties( &self, mass: T ) -> MassProperties<T> {
+        let twelfth = T::one() / T::from_usize( 12 );
+        let diagonal = |x: T, y: T, z: T| {
+            let mut inertia = identity::<T>();
+            inertia[ 0 ][ 0 ] = x;
+            inertia[ 1 ][ 1 ] = y;
+            inertia[ 2 ][ 2 ] = z;
+            inertia
+        };
+        let inertia = match *self {
+            Shape::Box { extents } => {
+                let [ a, b, c ] = [ extents[ 0 ] * extents[ 0 ], extents[ 1 ] * extents[ 1 ], extents[ 2 ] * extents[ 2 ] ];
+                diagonal( mass * twelfth * ( b + c ), mass * twelfth * ( a + c ), mass * twelfth * ( a + b ) )
+            },
+            Shape::Sphere { radius } => {
+                let moment = T::two() / T::from_usize( 5 ) * mass * radius * radius;
+                diagonal( moment, moment, moment )
+            },
+            Shape::Cylinder { radius, height } => {
+                let lateral = mass * twelfth * ( T::from_usize( 3 ) * radius * radius + height * height );
+                diagonal( lateral, lateral, T::half() * mass * radius * radius )
+            },
+            Shape::Capsule { radius, height } => {
+                let cylinder = Shape::Cylinder { radius, height }.measure();
+                let total = self.measure();
+                let ( body, caps ) = if total == T::zero() {
+                    ( T::zero(), T::zero() )
+                } else {
+                    ( mass * cylinder / total, mass * ( total - cylinder ) / total )
+                };
+                let r2 = radius * radius;
+                let caps_axial = T::two() / T::from_usize( 5 ) * caps * r2;
+                let caps_lateral = caps * (
+                    T::two() / T::from_usize( 5 ) * r2
+                    + height * height / T::from_usize( 4 )
+                    + T::from_usize( 3 ) * height * radius / T::from_usize( 8 )
+                );
+                let body_lateral = body * ( height * height * twelfth + r2 / T::from_usize( 4 ) );
+                diagonal( body_lateral + caps_lateral, body_lateral + caps_lateral, T::half() * body * r2 + caps_axial )
+            },
+            Shape::Rod { length } => {
+                let lateral = mass * twelfth * length * length;
+                diagonal( lateral, lateral, T::zero() )
+            }
+        };
+        MassProperties::new( mass, Vector::default(), inertia )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+
+    #[test]
+    fn parallel_axis_test() {
+        let rod = Shape::Rod { length: 2.0 }.mass_properties( 3.0 );
+        assert!( ( rod.inertia()[ 0 ][ 0 ] - 1.0 ).abs() < 1e-12 );
+        let end = rod.inertia_about( &Vector3::from([ 0.0, 0.0, 1.0 ]) );
+        assert!( ( end[ 0 ][ 0 ] - 4.0 ).abs() < 1e-12 );
+        assert!( end[ 2 ][ 2 ].abs() < 1e-12 );
+
+        let halves = Shape::Box { extents: Vector3::from([ 1.0, 1.0, 1.0 ]) }.mass_properties( 1.0 );
+        let whole = halves.translated( &Vector3::from([ 0.5, 0.0, 0.0 ]) ) + halves.translated( &Vector3::from([ -0.5, 0.0, 0.0 ]) );
+        let expected = Shape::Box { extents: Vector3::from([ 2.0, 1.0, 1.0 ]) }.mass_properties( 2.0 );
+        for i in 0..3 {
+            assert!( ( whole.inertia()[ i ][ i ] - expected.inertia()[ i ][ i ] ).abs() < 1e-12 );
+        }
+    }
+
+    #[test]
+    fn density_test() {
+        let sphere = Shape::Sphere { radius: 1.0 }.mass_properties_from_density( 3.0 );
+        assert!( ( *sphere.mass() - 4.0 * std::f64::consts::PI ).abs() < 1e-12 );
+    }
+}
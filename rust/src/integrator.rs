@@ -0,0 +1,413 @@
+// Copyright 2024 Bewusstsein Labs
+
+use linear_algebra::vector::Vector;
+
+use crate::scalar::Scalar;
+
+/// Advances a derivative stack `[ x, x', x'', .. ]` by one time step.
+///
+/// The highest derivative is supplied by `top`, evaluated on the (intermediate) stack, which lets
+/// force laws feed the integrator. `integrate` holds the highest derivative fixed over the step.
+pub trait Integrator<T>
+where
+    T: Scalar
+{
+    fn integrate_with<const DIM: usize, F>( &self, stack: &mut [Vector<T, DIM>], time_step: T, top: F )
+    where
+        F: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>;
+
+    fn integrate<const DIM: usize>( &self, stack: &mut [Vector<T, DIM>], time_step: T ) {
+        if let Some( held ) = stack.last().copied() {
+            self.integrate_with( stack, time_step, |_| held );
+        }
+    }
+}
+
+/// Explicit (forward) Euler: every level is advanced with the derivative from the start of the step.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct ExplicitEuler;
+
+/// Semi-implicit (symplectic) Euler: the stack is advanced from the highest derivative down, so every
+/// level is advanced with its already updated derivative.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct SemiImplicitEuler;
+
+/// Velocity Verlet on the two lowest levels; higher levels are advanced semi-implicitly.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct VelocityVerlet;
+
+/// Classical fourth order Runge-Kutta.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct RungeKutta4;
+
+/// Taylor series over the whole stack, including every `dt^n / n!` term up to `ORD`.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct Taylor;
+
+pub(crate) fn set_top<T, const DIM: usize, F>( stack: &mut [Vector<T, DIM>], top: &F )
+where
+    T: Scalar,
+    F: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>
+{
+    let last = stack.len() - 1;
+    let value = top( stack );
+    stack[ last ] = value;
+}
+
+pub(crate) fn rate<T, const DIM: usize, F>( state: &mut [Vector<T, DIM>], top: &F ) -> Vec<Vector<T, DIM>>
+where
+    T: Scalar,
+    F: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>
+{
+    set_top( state, top );
+    ( 0..state.len() )
+        .map( |i| if i + 1 < state.len() { state[ i + 1 ] } else { Vector::default() } )
+        .collect()
+}
+
+pub(crate) fn offset<T, const DIM: usize>( state: &[Vector<T, DIM>], increments: &[( T, &[Vector<T, DIM>] )] ) -> Vec<Vector<T, DIM>>
+where
+    T: Scalar
+{
+    let mut result = state.to_vec();
+    for ( weight, rate ) in increments {
+        for ( value, rate ) in result.iter_mut().zip( rate.iter() ) {
+            *value += *rate * *weight;
+        }
+    }
+    result
+}
+
+impl<T> Integrator<T> for ExplicitEuler
+where
+    T: Scalar
+{
+    fn integrate_with<const DIM: usize, F>( &self, stack: &mut [Vector<T, DIM>], time_step: T, top: F )
+    where
+        F: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>
+    {
+        if stack.len() < 2 { return; }
+        set_top( stack, &top );
+        for i in 1..stack.len() {
+            let derivative = stack[ i ];
+            stack[ i - 1 ] += derivative * time_step;
+        }
+    }
+}
+
+impl<T> Integrator<T> for SemiImplicitEuler
+where
+    T: Scalar
+{
+    fn integrate_with<const DIM: usize, F>( &self, stack: &mut [Vector<T, DIM>], time_step: T, top: F )
+    where
+        F: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>
+    {
+        if stack.len() < 2 { return; }
+        set_top( stack, &top );
+        for i in ( 1..stack.len() ).rev() {
+            let derivative = stack[ i ];
+            stack[ i - 1 ] += derivative * time_step;
+        }
+    }
+}
+
+impl<T> Integrator<T> for VelocityVerlet
+where
+    T: Scalar
+{
+    fn integrate_with<const DIM: usize, F>( &self, stack: &mut [Vector<T, DIM>], time_step: T, top: F )
+    where
+        F: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>
+    {
+        let len = stack.len();
+        if len < 2 { return; }
+        set_top( stack, &top );
+        if len == 2 {
+            let velocity = stack[ 1 ];
+            stack[ 0 ] += velocity * time_step;
+            return;
+        }
+        let half = T::half();
+        let velocity = stack[ 1 ];
+        let acceleration = stack[ 2 ];
+        stack[ 0 ] += velocity * time_step + acceleration * ( half * time_step * time_step );
+        set_top( stack, &top );
+        for i in ( 3..len ).rev() {
+            let derivative = stack[ i ];
+            stack[ i - 1 ] += derivative * time_step;
+        }
+        let next_acceleration = stack[ 2 ];
+        stack[ 1 ] += ( acceleration + next_acceleration ) * ( half * time_step );
+    }
+}
+
+impl<T> Integrator<T> for RungeKutta4
+where
+    T: Scalar
+{
+    fn integrate_with<const DIM: usize, F>( &self, stack: &mut [Vector<T, DIM>], time_step: T, top: F )
+    where
+        F: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>
+    {
+        if stack.len() < 2 { return; }
+        let half = T::half() * time_step;
+        let sixth = time_step / T::from_usize( 6 );
+        let third = time_step / T::from_usize( 3 );
+
+        let mut state = stack.to_vec();
+        let k1 = rate( &mut state, &top );
+        let mut stage = offset( &state, &[ ( half, &k1 ) ] );
+        let k2 = rate( &mut stage, &top );
+        let mut stage = offset( &state, &[ ( half, &k2 ) ] );
+        let k3 = rate( &mut stage, &top );
+        let mut stage = offset( &state, &[ ( time_step, &k3 ) ] );
+        let k4 = rate( &mut stage, &top );
+
+        let result = offset( &state, &[ ( sixth, &k1 ), ( third, &k2 ), ( third, &k3 ), ( sixth, &k4 ) ] );
+        stack.copy_from_slice( &result );
+        set_top( stack, &top );
+    }
+}
+
+impl<T> Integrator<T> for Taylor
+where
+    T: Scalar
+{
+    fn integrate_with<const DIM: usize, F>( &self, stack: &mut [Vector<T, DIM>], time_step: T, top: F )
+    where
+        F: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>
+    {
+        let len = stack.len();
+        if len < 2 { return; }
+        set_top( stack, &top );
+        let initial = stack.to_vec();
+        for i in 0..len {
+            let mut factor = T::one();
+            for k in 1..( len - i ) {
+                factor = factor * time_step / T::from_usize( k );
+                stack[ i ] += initial[ i + k ] * factor;
+            }
+        }
+    }
+}
+
+/// Dormand-Prince 5(4) embedded Runge-Kutta pair with local error control.
+///
+/// Used as a plain `Integrator` it takes a single fifth order step; `attempt` additionally returns the
+/// local error relative to the tolerances, where a value at or below one means the step is acceptable.
+#[derive( Clone, Copy, Debug, PartialEq )]
+pub struct DormandPrince<T> {
+    absolute_tolerance: T,
+    relative_tolerance: T,
+    min_step: T,
+    max_step: T,
+    initial_step: T,
+    safety: T,
+}
+
+const DORMAND_PRINCE_A: [ [ f64; 6 ]; 6 ] = [
+    [ 1.0 / 5.0, 0.0, 0.0, 0.0, 0.0, 0.0 ],
+    [ 3.0 / 40.0, 9.0 / 40.0, 0.0, 0.0, 0.0, 0.0 ],
+    [ 44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0, 0.0, 0.0, 0.0 ],
+    [ 19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0, 0.0, 0.0 ],
+    [ 9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0, -5103.0 / 18656.0, 0.0 ],
+    [ 35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0 ],
+];
+
+const DORMAND_PRINCE_B: [ f64; 7 ] = [ 35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0, 0.0 ];
+
+const DORMAND_PRINCE_E: [ f64; 7 ] = [ 71.0 / 57600.0, 0.0, -71.0 / 16695.0, 71.0 / 1920.0, -17253.0 / 339200.0, 22.0 / 525.0, -1.0 / 40.0 ];
+
+fn set_tops<T, const DIM: usize, F>( state: &mut [Vector<T, DIM>], levels: usize, top: &F )
+where
+    T: Scalar,
+    F: Fn( &[Vector<T, DIM>] ) -> Vec<Vector<T, DIM>>
+{
+    let values = top( state );
+    for ( stack, value ) in state.chunks_mut( levels ).zip( values ) {
+        stack[ levels - 1 ] = value;
+    }
+}
+
+fn stacked_rate<T, const DIM: usize, F>( state: &mut [Vector<T, DIM>], levels: usize, top: &F ) -> Vec<Vector<T, DIM>>
+where
+    T: Scalar,
+    F: Fn( &[Vector<T, DIM>] ) -> Vec<Vector<T, DIM>>
+{
+    set_tops( state, levels, top );
+    state.chunks( levels )
+        .flat_map( |stack| ( 0..levels ).map( move |i| if i + 1 < levels { stack[ i + 1 ] } else { Vector::default() } ) )
+        .collect()
+}
+
+fn weighted<'a, T, const DIM: usize>( coefficients: &[ f64 ], rates: &'a [ Vec<Vector<T, DIM>> ], time_step: T ) -> Vec<( T, &'a [Vector<T, DIM>] )>
+where
+    T: Scalar
+{
+    coefficients.iter()
+        .zip( rates.iter() )
+        .map( |( coefficient, rate )| ( T::from_f64( *coefficient ) * time_step, rate.as_slice() ) )
+        .collect()
+}
+
+impl<T> DormandPrince<T>
+where
+    T: Scalar
+{
+    pub fn new( absolute_tolerance: T, relative_tolerance: T, min_step: T, max_step: T ) -> Self {
+        Self {
+            absolute_tolerance,
+            relative_tolerance,
+            min_step,
+            max_step,
+            initial_step: max_step,
+            safety: T::from_f64( 0.9 ),
+        }
+    }
+
+    pub fn with_initial_step( mut self, initial_step: T ) -> Self {
+        self.initial_step = initial_step;
+        self
+    }
+
+    pub fn absolute_tolerance( &self ) -> &T { &self.absolute_tolerance }
+    pub fn relative_tolerance( &self ) -> &T { &self.relative_tolerance }
+    pub fn min_step( &self ) -> &T { &self.min_step }
+    pub fn max_step( &self ) -> &T { &self.max_step }
+    pub fn initial_step( &self ) -> &T { &self.initial_step }
+
+    pub fn attempt<const DIM: usize, F>( &self, stack: &[Vector<T, DIM>], time_step: T, top: F ) -> ( Vec<Vector<T, DIM>>, T )
+    where
+        F: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>
+    {
+        self.attempt_stacks( stack, stack.len(), time_step, |state| vec![ top( state ) ] )
+    }
+
+    /// Steps consecutive stacks of `levels` entries as one state, with one error over all of them.
+    /// `top` returns the highest derivative of every stack.
+    pub fn attempt_stacks<const DIM: usize, F>( &self, state: &[Vector<T, DIM>], levels: usize, time_step: T, top: F ) -> ( Vec<Vector<T, DIM>>, T )
+    where
+        F: Fn( &[Vector<T, DIM>] ) -> Vec<Vector<T, DIM>>
+    {
+        if levels < 2 { return ( state.to_vec(), T::zero() ); }
+        let mut state = state.to_vec();
+        let mut rates = vec![ stacked_rate( &mut state, levels, &top ) ];
+        for ( stage, row ) in DORMAND_PRINCE_A.iter().enumerate() {
+            let next = {
+                let mut stage = offset( &state, &weighted( &row[ ..=stage ], &rates, time_step ) );
+                stacked_rate( &mut stage, levels, &top )
+            };
+            rates.push( next );
+        }
+        let zero = vec![ Vector::default(); state.len() ];
+        let mut solution = offset( &state, &weighted( &DORMAND_PRINCE_B, &rates, time_step ) );
+        let estimate = offset( &zero, &weighted( &DORMAND_PRINCE_E, &rates, time_step ) );
+        set_tops( &mut solution, levels, &top );
+
+        let mut error = T::zero();
+        for level in 0..state.len() {
+            for i in 0..DIM {
+                let scale = self.absolute_tolerance + self.relative_tolerance * state[ level ][ i ].abs().max( solution[ level ][ i ].abs() );
+                error = error.max( estimate[ level ][ i ].abs() / scale );
+            }
+        }
+        ( solution, error )
+    }
+
+    pub fn next_step( &self, time_step: T, error: T ) -> T {
+        let min_factor = T::from_f64( 0.2 );
+        let max_factor = T::from_f64( 5.0 );
+        let factor = if error > T::zero() {
+            ( self.safety * error.powf( -T::one() / T::from_usize( 5 ) ) ).max( min_factor ).min( max_factor )
+        } else {
+            max_factor
+        };
+        ( time_step * factor ).min( self.max_step )
+    }
+}
+
+impl<T> Integrator<T> for DormandPrince<T>
+where
+    T: Scalar
+{
+    fn integrate_with<const DIM: usize, F>( &self, stack: &mut [Vector<T, DIM>], time_step: T, top: F )
+    where
+        F: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>
+    {
+        let ( solution, _ ) = self.attempt( stack, time_step, top );
+        stack.copy_from_slice( &solution );
+    }
+}
+
+#[derive( Clone, Default, Debug, PartialEq )]
+pub struct AdaptiveReport<T> {
+    time: T,
+    steps: Vec<T>,
+    rejected: usize,
+}
+
+impl<T> AdaptiveReport<T> {
+    pub fn time( &self ) -> &T { &self.time }
+    pub fn steps( &self ) -> &[T] { &self.steps }
+    pub fn accepted( &self ) -> usize { self.steps.len() }
+    pub fn rejected( &self ) -> usize { self.rejected }
+
+    pub(crate) fn accept( &mut self, time: T, time_step: T ) {
+        self.time = time;
+        self.steps.push( time_step );
+    }
+
+    pub(crate) fn reject( &mut self ) {
+        self.rejected += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taylor_test() {
+        let mut stack = [ Vector::<f64, 1>::from([ 0.0 ]), Vector::<f64, 1>::from([ 1.0 ]), Vector::<f64, 1>::from([ 2.0 ]), Vector::<f64, 1>::from([ 6.0 ]) ];
+        Taylor.integrate( &mut stack, 2.0 );
+        assert_eq!( stack[ 0 ][ 0 ], 2.0 + 4.0 + 8.0 );
+        assert_eq!( stack[ 1 ][ 0 ], 1.0 + 4.0 + 12.0 );
+        assert_eq!( stack[ 2 ][ 0 ], 2.0 + 12.0 );
+        assert_eq!( stack[ 3 ][ 0 ], 6.0 );
+    }
+
+    #[test]
+    fn oscillator_test() {
+        let spring = |stack: &[Vector<f64, 1>]| stack[ 0 ] * -1.0;
+        let mut euler = [ Vector::<f64, 1>::from([ 1.0 ]), Vector::<f64, 1>::from([ 0.0 ]), Vector::<f64, 1>::from([ -1.0 ]) ];
+        let mut verlet = euler;
+        let mut rk4 = euler;
+        let time_step = 0.01;
+        for _ in 0..628 {
+            SemiImplicitEuler.integrate_with( &mut euler, time_step, spring );
+            VelocityVerlet.integrate_with( &mut verlet, time_step, spring );
+            RungeKutta4.integrate_with( &mut rk4, time_step, spring );
+        }
+        let exact = ( 6.28_f64 ).cos();
+        assert!( ( rk4[ 0 ][ 0 ] - exact ).abs() < 1e-8 );
+        assert!( ( verlet[ 0 ][ 0 ] - exact ).abs() < 1e-4 );
+        assert!( ( euler[ 0 ][ 0 ] - exact ).abs() < 1e-2 );
+    }
+
+    #[test]
+    fn dormand_prince_test() {
+        let integrator = DormandPrince::new( 1e-9, 1e-9, 1e-6, 0.5 );
+        let stack = [ Vector::<f64, 1>::from([ 0.0 ]), Vector::<f64, 1>::from([ 1.0 ]), Vector::<f64, 1>::from([ 2.0 ]) ];
+        let ( solution, error ) = integrator.attempt( &stack, 0.5, |stack: &[Vector<f64, 1>]| stack[ 2 ] );
+        assert!( error < 1.0 );
+        assert!( ( solution[ 0 ][ 0 ] - 0.75 ).abs() < 1e-12 );
+        assert!( ( solution[ 1 ][ 0 ] - 2.0 ).abs() < 1e-12 );
+
+        let spring = |stack: &[Vector<f64, 1>]| stack[ 0 ] * -100.0;
+        let stack = [ Vector::<f64, 1>::from([ 1.0 ]), Vector::<f64, 1>::from([ 0.0 ]), Vector::<f64, 1>::from([ -100.0 ]) ];
+        let ( _, error ) = integrator.attempt( &stack, 0.5, spring );
+        assert!( error > 1.0 );
+        assert!( integrator.next_step( 0.5, error ) < 0.5 );
+    }
+}
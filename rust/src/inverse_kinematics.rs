@@ -0,0 +1,327 @@
+// Copyright 2024 Bewusstsein Labs
+
+use std::fmt::Debug;
+
+use linear_algebra::vector::Vector;
+
+use crate::{
+    scalar::Scalar,
+    constraint::Constraint,
+    kinematics::Pose,
+    linkage::{ Linkage, Error },
+    jacobian::Jacobian,
+    dense::{ self, DenseMatrix },
+    rotation
+};
+
+/// What the end joint of the chain should reach, in world coordinates.
+#[derive( Clone, Copy, Debug, PartialEq )]
+pub enum Target<T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    Position( Vector<T, DIM> ),
+    Pose( Pose<T, DIM> )
+}
+
+impl<T, const DIM: usize> Target<T, DIM>
+where
+    T: Scalar
+{
+    /// Difference between the target and `pose`: the position error, followed by the components of the
+    /// rotation taking `pose` onto the target for full pose targets.
+    pub fn residual( &self, pose: &Pose<T, DIM> ) -> Vec<T> {
+        match self {
+            Target::Position( position ) => {
+                let error = *position - *pose.position();
+                ( 0..DIM ).map( |i| error[ i ] ).collect()
+            },
+            Target::Pose( target ) => {
+                let error = *target.position() - *pose.position();
+                let inverse = Vector::default() - *pose.rotation();
+                let rotation = rotation::compose( &inverse, target.rotation() );
+                ( 0..DIM ).map( |i| error[ i ] ).chain( ( 0..rotation::angular_dimension( DIM ) ).map( |i| rotation[ i ] ) ).collect()
+            }
+        }
+    }
+}
+
+#[derive( Clone, Copy, Debug, PartialEq )]
+pub enum Method<T> {
+    /// Levenberg-Marquardt: `Δq = Jᵀ (J Jᵀ + λ² I)⁻¹ e`, with `λ` halved after each improving step and
+    /// doubled after each rejected one.
+    DampedLeastSquares { damping: T },
+    /// `Δq = α Jᵀ e`, with `α` chosen to minimise the linearised residual.
+    JacobianTranspose
+}
+
+#[derive( Clone, Copy, Debug, PartialEq )]
+pub struct InverseKinematics<T> {
+    method: Method<T>,
+    tolerance: T,
+    max_iterations: usize
+}
+
+impl<T> InverseKinematics<T>
+where
+    T: Scalar
+{
+    pub fn new( method: Method<T>, tolerance: T, max_iterations: usize ) -> Self {
+        Self { method, tolerance, max_iterations }
+    }
+
+    pub fn damped_least_squares( damping: T ) -> Self {
+        Self::new( Method::DampedLeastSquares { damping }, T::from_f64( 1e-9 ), 100 )
+    }
+
+    pub fn jacobian_transpose() -> Self {
+        Self::new( Method::JacobianTranspose, T::from_f64( 1e-9 ), 1000 )
+    }
+
+    pub fn method( &self ) -> &Method<T> { &self.method }
+    pub fn tolerance( &self ) -> &T { &self.tolerance }
+    pub fn max_iterations( &self ) -> &usize { &self.max_iterations }
+}
+
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct Solution<T> {
+    converged: bool,
+    residual: T,
+    iterations: usize
+}
+
+impl<T> Solution<T> {
+    pub fn new( converged: bool, residual: T, iterations: usize ) -> Self {
+        Self { converged, residual, iterations }
+    }
+
+    pub fn converged( &self ) -> bool { self.converged }
+    /// Euclidean norm of the final residual.
+    pub fn residual( &self ) -> &T { &self.residual }
+    pub fn iterations( &self ) -> usize { self.iterations }
+}
+
+impl <I, T, const DIM: usize, const ORD: usize> Linkage<I, T, DIM, ORD>
+where
+    I: 'static + Default + Copy + Debug + Ord,
+    T: Scalar,
+    [Constraint<T, DIM>; (ORD + 1) * 2]: Default,
+    [(); ORD + 1]:,
+    [(); (ORD + 1) * 2]:
+{
+    /// Joints on the spanning tree path from `from` to `to`, in root to tip order.
+    pub fn chain( &self, from: I, to: I ) -> Result<Vec<I>, Error> {
+        let tree = self.spanning_tree( from )?;
+        let mut chain = tree.path( &to, &from ).ok_or( Error::JointNotFound )?;
+        chain.reverse();
+        Ok( chain )
+    }
+
+    fn chain_residual( &self, from: I, to: I, base: Pose<T, DIM>, target: &Target<T, DIM> ) -> Result<Vec<T>, Error> {
+        let kinematics = self.forward_kinematics( from, base )?;
+        let pose = kinematics.pose( &to ).ok_or( Error::JointNotFound )?;
+        Ok( target.residual( pose ) )
+    }
+
+    fn chain_displacements( &self, chain: &[I] ) -> Vec<Pose<T, DIM>> {
+        chain.iter().filter_map( |id| self.get_joint( *id ) ).map( |joint| *joint.displacement() ).collect()
+    }
+
+    fn set_chain_displacements( &mut self, chain: &[I], displacements: &[Pose<T, DIM>] ) {
+        for ( id, displacement ) in chain.iter().zip( displacements.iter() ) {
+            if let Some( joint ) = self.get_joint_mut( *id ) {
+                *joint.displacement_mut() = *displacement;
+            }
+        }
+    }
+
+    /// Jacobian matching the rows of `target.residual`, with the columns of locked axes zeroed.
+    fn chain_jacobian( &self, from: I, to: I, base: Pose<T, DIM>, target: &Target<T, DIM> ) -> Result<( Jacobian<I, T, DIM>, DenseMatrix<T> ), Error> {
+        let jacobian = self.jacobian( from, to, base )?;
+        let mut matrix = match target {
+            Target::Position( _ ) => jacobian.linear_matrix(),
+            Target::Pose( _ ) => jacobian.matrix()
+        };
+        let locked: Vec<bool> = jacobian.joints().iter()
+            .flat_map( |( id, _ )| self.get_joint( *id ).map( |joint| joint.locked_axes() ).unwrap_or_default() )
+            .collect();
+        for row in matrix.iter_mut() {
+            for ( value, locked ) in row.iter_mut().zip( locked.iter() ) {
+                if *locked { *value = T::zero(); }
+            }
+        }
+        Ok( ( jacobian, matrix ) )
+    }
+
+    /// Moves the displacements of the joints between `from` and `to` so that `to` reaches `target`, with
+    /// `from` placed at `base`. Every step is clamped to the joints' constraint ranges, and the final
+    /// configuration is written back with `apply_forward_kinematics`.
+    pub fn inverse_kinematics( &mut self, solver: &InverseKinematics<T>, from: I, to: I, base: Pose<T, DIM>, target: &Target<T, DIM> ) -> Result<Solution<T>, Error> {
+        let chain = self.chain( from, to )?;
+        let mut residual = self.chain_residual( from, to, base, target )?;
+        let mut error = dense::norm( &residual );
+        let mut damping = match solver.method() {
+            Method::DampedLeastSquares { damping } => *damping,
+            Method::JacobianTranspose => T::zero()
+        };
+        let mut solution = Solution::default();
+        while error > *solver.tolerance() && solution.iterations < *solver.max_iterations() {
+            solution.iterations += 1;
+            let ( columns, jacobian ) = self.chain_jacobian( from, to, base, target )?;
+            let transposed = dense::transpose( &jacobian );
+            let step = match solver.method() {
+                Method::DampedLeastSquares { .. } => {
+                    let mut system = dense::multiply( &jacobian, &transposed );
+                    for ( i, row ) in system.iter_mut().enumerate() {
+                        row[ i ] += damping * damping;
+                    }
+                    match dense::solve( &system, &residual ) {
+                        Some( weights ) => dense::multiply_vector( &transposed, &weights ),
+                        None => break
+                    }
+                },
+                Method::JacobianTranspose => {
+                    let gradient = dense::multiply_vector( &transposed, &residual );
+                    let projected = dense::multiply_vector( &jacobian, &gradient );
+                    let denominator = dense::dot( &projected, &projected );
+                    if denominator <= T::epsilon() { break; }
+                    let alpha = dense::dot( &residual, &projected ) / denominator;
+                    gradient.iter().map( |value| *value * alpha ).collect()
+                }
+            };
+
+            let displacements = self.chain_displacements( &chain );
+            let mut offset = 0;
+            for ( id, count ) in columns.joints() {
+                if let Some( joint ) = self.get_joint_mut( *id ) {
+                    joint.displace( &step[ offset..offset + *count ] );
+                }
+                offset += *count;
+            }
+            let trial = self.chain_residual( from, to, base, target )?;
+            let trial_error = dense::norm( &trial );
+            match solver.method() {
+                Method::DampedLeastSquares { .. } if trial_error >= error => {
+                    self.set_chain_displacements( &chain, &displacements );
+                    damping = ( damping * T::two() ).max( T::epsilon().sqrt() );
+                },
+                Method::DampedLeastSquares { .. } => {
+                    damping *= T::half();
+                    residual = trial;
+                    error = trial_error;
+                },
+                Method::JacobianTranspose => {
+                    residual = trial;
+                    error = trial_error;
+                }
+            }
+        }
+        solution.residual = error;
+        solution.converged = error <= *solver.tolerance();
+        self.apply_forward_kinematics( from, base )?;
+        Ok( solution )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+    use crate::{
+        body::Body3D,
+        joint::Joint3D,
+        link::Link,
+        constraint::{ Range, Constraint3D },
+        linkage::Linkage3D
+    };
+
+    /// Planar two link arm in the xy plane whose joints only rotate about z, within three radians.
+    fn arm() -> Linkage3D<usize, f64, 1> {
+        let fixed = Some( Range::new( 0.0, 0.0 ) );
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        for id in 0..3 {
+            let constraints = [ Constraint3D::new([ fixed; 3 ]), Constraint3D::new([ fixed; 3 ]), Constraint3D::new([ fixed, fixed, Some( Range::new( -3.0, 3.0 ) ) ]), Constraint3D::new([ fixed; 3 ]) ];
+            linkage.add_joint( id, Joint3D::new( Body3D::new( 1.0, [ Vector3::default(); 2 ], [ Vector3::default(); 2 ] ), constraints ) ).unwrap();
+        }
+        let offset = Pose::new( Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::default() );
+        linkage.add_link( 0, 1, Link::default().with_transform( offset ) ).unwrap();
+        linkage.add_link( 1, 2, Link::default().with_transform( offset ) ).unwrap();
+        linkage
+    }
+
+    #[test]
+    fn damped_least_squares_test() {
+        let mut linkage = arm();
+        let target = Target::Position( Vector3::from([ 1.0, 1.0, 0.0 ]) );
+        let solution = linkage.inverse_kinematics( &InverseKinematics::damped_least_squares( 0.1 ), 0, 2, Pose::identity(), &target ).unwrap();
+        assert!( solution.converged() );
+        let position = *linkage.get_joint( 2 ).unwrap().position();
+        assert!( ( position[ 0 ] - 1.0 ).abs() < 1e-6 && ( position[ 1 ] - 1.0 ).abs() < 1e-6 );
+        for id in 0..3 {
+            let displacement = linkage.get_joint( id ).unwrap().displacement();
+            assert_eq!( displacement.position()[ 0 ], 0.0 );
+            assert_eq!( displacement.rotation()[ 0 ], 0.0 );
+        }
+
+        let unreachable = Target::Position( Vector3::from([ 3.0, 0.0, 0.0 ]) );
+        let solution = linkage.inverse_kinematics( &InverseKinematics::damped_least_squares( 0.1 ), 0, 2, Pose::identity(), &unreachable ).unwrap();
+        assert!( !solution.converged() );
+        assert!( ( solution.residual() - 1.0 ).abs() < 1e-3 );
+    }
+
+    /// Forward difference of the residual along every chain column.
+    fn difference_jacobian( linkage: &mut Linkage3D<usize, f64, 1>, target: &Target<f64, 3> ) -> DenseMatrix<f64> {
+        let chain = linkage.chain( 0, 2 ).unwrap();
+        let residual = linkage.chain_residual( 0, 2, Pose::identity(), target ).unwrap();
+        let displacements = linkage.chain_displacements( &chain );
+        let step = 1e-7;
+        let mut columns: DenseMatrix<f64> = Vec::new();
+        for id in chain.iter() {
+            let count = linkage.get_joint( *id ).unwrap().degrees_of_freedom();
+            for k in 0..count {
+                let mut amounts = vec![ 0.0; count ];
+                amounts[ k ] = step;
+                linkage.get_joint_mut( *id ).unwrap().displace( &amounts );
+                let shifted = linkage.chain_residual( 0, 2, Pose::identity(), target ).unwrap();
+                columns.push( residual.iter().zip( shifted.iter() ).map( |( value, shifted )| ( value - shifted ) / step ).collect() );
+                linkage.set_chain_displacements( &chain, &displacements );
+            }
+        }
+        dense::transpose( &columns )
+    }
+
+    #[test]
+    fn chain_jacobian_test() {
+        let mut linkage = arm();
+        for ( id, angle ) in [ ( 0, 0.4 ), ( 1, -0.7 ) ] {
+            *linkage.get_joint_mut( id ).unwrap().displacement_mut().rotation_mut() = Vector3::from([ 0.0, 0.0, angle ]);
+        }
+        let targets = [
+            Target::Position( Vector3::from([ 1.0, 1.0, 0.0 ]) ),
+            Target::Pose( Pose::new( Vector3::from([ 1.0, 1.0, 0.0 ]), Vector3::from([ 0.0, 0.0, 0.3 ]) ) )
+        ];
+        for target in targets {
+            let ( _, analytic ) = linkage.chain_jacobian( 0, 2, Pose::identity(), &target ).unwrap();
+            let difference = difference_jacobian( &mut linkage, &target );
+            assert_eq!( analytic.len(), difference.len() );
+            for ( row, expected ) in analytic.iter().zip( difference.iter() ) {
+                for ( value, expected ) in row.iter().zip( expected.iter() ) {
+                    assert!( ( value - expected ).abs() < 1e-5 );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn jacobian_transpose_test() {
+        let mut linkage = arm();
+        let quarter = std::f64::consts::FRAC_PI_2;
+        let target = Target::Pose( Pose::new( Vector3::from([ 1.0, 1.0, 0.0 ]), Vector3::from([ 0.0, 0.0, quarter ]) ) );
+        let solver = InverseKinematics::new( Method::JacobianTranspose, 1e-4, 10000 );
+        let solution = linkage.inverse_kinematics( &solver, 0, 2, Pose::identity(), &target ).unwrap();
+        assert!( solution.converged() );
+        let joint = linkage.get_joint( 2 ).unwrap();
+        assert!( ( joint.position()[ 0 ] - 1.0 ).abs() < 1e-3 && ( joint.position()[ 1 ] - 1.0 ).abs() < 1e-3 );
+        assert!( ( joint.rotation()[ 2 ] - quarter ).abs() < 1e-3 );
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright 2024 Bewusstsein Labs
+
+use std::fmt::Debug;
+
+use linear_algebra::vector::Vector;
+
+use crate::{
+    scalar::Scalar,
+    constraint::Constraint,
+    kinematics::{ Pose, Motion },
+    linkage::{ Linkage, Error },
+    force::Wrench,
+    dense::{ self, DenseMatrix },
+    rotation
+};
+
+/// Jacobian of a chain: one column per degree of freedom of each joint, holding the motion of the end
+/// joint's origin, in world coordinates, for a unit rate of that degree of freedom.
+#[derive( Clone, Default, Debug, PartialEq )]
+pub struct Jacobian<I, T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    joints: Vec<( I, usize )>,
+    columns: Vec<Motion<T, DIM>>,
+    end: Pose<T, DIM>
+}
+
+impl<I, T, const DIM: usize> Jacobian<I, T, DIM>
+where
+    I: Copy,
+    T: Scalar
+{
+    pub fn new( joints: Vec<( I, usize )>, columns: Vec<Motion<T, DIM>>, end: Pose<T, DIM> ) -> Self {
+        Self { joints, columns, end }
+    }
+
+    /// Chain joints with their number of columns, in column order.
+    pub fn joints( &self ) -> &[( I, usize )] { &self.joints }
+    pub fn columns( &self ) -> &[Motion<T, DIM>] { &self.columns }
+    /// World pose of the end joint the columns refer to.
+    pub fn end( &self ) -> &Pose<T, DIM> { &self.end }
+
+    pub fn len( &self ) -> usize { self.columns.len() }
+    pub fn is_empty( &self ) -> bool { self.columns.is_empty() }
+
+    /// End joint velocity for the joint rates `rates`.
+    pub fn velocity( &self, rates: &[T] ) -> Motion<T, DIM> {
+        self.columns.iter().zip( rates.iter() ).fold( Motion::default(), |sum, ( column, rate )| sum + *column * *rate )
+    }
+
+    /// Generalised forces balancing `wrench` applied at the end joint, `Jᵀ w`.
+    pub fn forces( &self, wrench: &Wrench<T, DIM> ) -> Vec<T> {
+        self.columns.iter().map( |column| rotation::dot( &column.linear, &wrench.force ) + rotation::dot( &column.angular, &wrench.torque ) ).collect()
+    }
+
+    /// Dense matrix with the linear rows followed by the rotational rows.
+    pub fn matrix( &self ) -> DenseMatrix<T> {
+        let linear = ( 0..DIM ).map( |i| self.columns.iter().map( |column| column.linear[ i ] ).collect() );
+        let angular = ( 0..rotation::angular_dimension( DIM ) ).map( |i| self.columns.iter().map( |column| column.angular[ i ] ).collect() );
+        linear.chain( angular ).collect()
+    }
+
+    /// Dense matrix of the linear rows only.
+    pub fn linear_matrix( &self ) -> DenseMatrix<T> {
+        ( 0..DIM ).map( |i| self.columns.iter().map( |column| column.linear[ i ] ).collect() ).collect()
+    }
+
+    /// Yoshikawa manipulability `√det( J Jᵀ )`, zero at singular configurations.
+    pub fn manipulability( &self ) -> T {
+        let matrix = self.matrix();
+        dense::determinant( &dense::multiply( &matrix, &dense::transpose( &matrix ) ) ).max( T::zero() ).sqrt()
+    }
+
+    /// Analytic Jacobian, whose angular parts are rates of the end joint's rotation vector rather than
+    /// angular velocities.
+    pub fn analytic( &self ) -> Self {
+        let columns = self.columns.iter().map( |column| {
+            Motion::new( rotation::rotation_rate( self.end.rotation(), &column.angular ), column.linear )
+        } ).collect();
+        Self::new( self.joints.clone(), columns, self.end )
+    }
+}
+
+impl <I, T, const DIM: usize, const ORD: usize> Linkage<I, T, DIM, ORD>
+where
+    I: 'static + Default + Copy + Debug + Ord,
+    T: Scalar,
+    [Constraint<T, DIM>; (ORD + 1) * 2]: Default,
+    [(); ORD + 1]:,
+    [(); (ORD + 1) * 2]:
+{
+    /// Frame at the end of the incoming link of every joint on the chain, with the joint's own origin.
+    fn chain_frames( &self, chain: &[I], from: I, base: Pose<T, DIM> ) -> Result<Vec<( Pose<T, DIM>, Vector<T, DIM> )>, Error> {
+        let kinematics = self.forward_kinematics( from, base )?;
+        chain.iter().map( |id| {
+            let joint = self.get_joint( *id ).ok_or( Error::JointNotFound )?;
+            let pose = kinematics.pose( id ).ok_or( Error::JointNotFound )?;
+            Ok( ( pose.compose( &joint.displacement().inverse() ), *pose.position() ) )
+        } ).collect()
+    }
+
+    /// Geometric Jacobian of joint `to` with respect to the displacements of the joints from `from` to
+    /// `to`, with `from` placed at `base`.
+    pub fn jacobian( &self, from: I, to: I, base: Pose<T, DIM> ) -> Result<Jacobian<I, T, DIM>, Error> {
+        let chain = self.chain( from, to )?;
+        let end = *self.forward_kinematics( from, base )?.pose( &to ).ok_or( Error::JointNotFound )?;
+        let frames = self.chain_frames( &chain, from, base )?;
+        let mut joints = Vec::new();
+        let mut columns = Vec::new();
+        for ( id, ( frame, origin ) ) in chain.iter().zip( frames.iter() ) {
+            let subspace = self.get_joint( *id ).ok_or( Error::JointNotFound )?.motion_subspace();
+            joints.push( ( *id, subspace.len() ) );
+            for motion in subspace {
+                let world = Motion::new( frame.transform_angular( &motion.angular ), frame.transform_vector( &motion.linear ) );
+                columns.push( world.shift( &( *end.position() - *origin ) ) );
+            }
+        }
+        Ok( Jacobian::new( joints, columns, end ) )
+    }
+
+    /// Time derivative of `jacobian( from, to, base )` while the chain moves at the joint rates `rates`.
+    pub fn jacobian_derivative( &self, from: I, to: I, base: Pose<T, DIM>, rates: &[T] ) -> Result<Jacobian<I, T, DIM>, Error> {
+        let jacobian = self.jacobian( from, to, base )?;
+        let chain = self.chain( from, to )?;
+        let frames = self.chain_frames( &chain, from, base )?;
+        let end = *jacobian.end().position();
+        let end_velocity = jacobian.velocity( rates ).linear;
+
+        let mut columns = Vec::new();
+        let mut offset = 0;
+        // Velocity of the incoming link frame of the current joint, referred to the end joint origin; it
+        // moves with every earlier joint of the chain.
+        let mut frame_velocity = Motion::<T, DIM>::default();
+        for ( ( _, count ), ( _, origin ) ) in jacobian.joints().iter().zip( frames.iter() ) {
+            let own = &jacobian.columns()[ offset..offset + *count ];
+            let own_rates = &rates[ offset.min( rates.len() )..( offset + *count ).min( rates.len() ) ];
+            let arm = end - *origin;
+            let sliding = own.iter().zip( own_rates.iter() )
+                .filter( |( column, _ )| column.angular == Vector::default() )
+                .fold( Vector::default(), |sum, ( column, rate )| sum + column.linear * *rate );
+            let origin_velocity = frame_velocity.shift( &( Vector::default() - arm ) ).linear + sliding;
+            for column in own {
+                let axis_rate = rotation::angular_tangent( &frame_velocity.angular, &column.angular );
+                let translation = column.linear - rotation::tangent( &column.angular, &arm );
+                let linear = rotation::tangent( &frame_velocity.angular, &translation )
+                    + rotation::tangent( &axis_rate, &arm )
+                    + rotation::tangent( &column.angular, &( end_velocity - origin_velocity ) );
+                columns.push( Motion::new( axis_rate, linear ) );
+            }
+            frame_velocity = own.iter().zip( own_rates.iter() ).fold( frame_velocity, |sum, ( column, rate )| sum + *column * *rate );
+            offset += *count;
+        }
+        Ok( Jacobian::new( jacobian.joints().to_vec(), columns, *jacobian.end() ) )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+    use crate::{
+        body::Body3D,
+        joint::Joint3D,
+        link::Link,
+        constraint::{ Range, Constraint3D },
+        linkage::Linkage3D
+    };
+
+    /// Planar two link arm in the xy plane whose joints only rotate about z.
+    fn arm() -> Linkage3D<usize, f64, 1> {
+        let fixed = Some( Range::new( 0.0, 0.0 ) );
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        for id in 0..3 {
+            let constraints = [ Constraint3D::new([ fixed; 3 ]), Constraint3D::new([ fixed; 3 ]), Constraint3D::new([ fixed, fixed, None ]), Constraint3D::new([ fixed; 3 ]) ];
+            linkage.add_joint( id, Joint3D::new( Body3D::new( 1.0, [ Vector3::default(); 2 ], [ Vector3::default(); 2 ] ), constraints ) ).unwrap();
+        }
+        let offset = Pose::new( Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::default() );
+        linkage.add_link( 0, 1, Link::default().with_transform( offset ) ).unwrap();
+        linkage.add_link( 1, 2, Link::default().with_transform( offset ) ).unwrap();
+        linkage
+    }
+
+    fn set_angles( linkage: &mut Linkage3D<usize, f64, 1>, angles: [f64; 2] ) {
+        for ( id, angle ) in angles.iter().enumerate() {
+            *linkage.get_joint_mut( id ).unwrap().displacement_mut().rotation_mut() = Vector3::from([ 0.0, 0.0, *angle ]);
+        }
+    }
+
+    /// Rates driving only the z rotation of the first two joints.
+    fn rates( rates: [f64; 2] ) -> Vec<f64> {
+        let mut all = vec![ 0.0; 18 ];
+        all[ 5 ] = rates[ 0 ];
+        all[ 11 ] = rates[ 1 ];
+        all
+    }
+
+    #[test]
+    fn jacobian_test() {
+        let mut linkage = arm();
+        let ( a, b ) = ( 0.4, 0.9 );
+        set_angles( &mut linkage, [ a, b ] );
+        let jacobian = linkage.jacobian( 0, 2, Pose::identity() ).unwrap();
+        assert_eq!( jacobian.len(), 18 );
+        let velocity = jacobian.velocity( &rates([ 1.0, 2.0 ]) );
+        let expected = [
+            -a.sin() * 1.0 - ( a + b ).sin() * 3.0,
+            a.cos() * 1.0 + ( a + b ).cos() * 3.0
+        ];
+        assert!( ( velocity.linear[ 0 ] - expected[ 0 ] ).abs() < 1e-12 );
+        assert!( ( velocity.linear[ 1 ] - expected[ 1 ] ).abs() < 1e-12 );
+        assert!( ( velocity.angular[ 2 ] - 3.0 ).abs() < 1e-12 );
+        assert!( ( jacobian.analytic().velocity( &rates([ 1.0, 2.0 ]) ).angular[ 2 ] - 3.0 ).abs() < 1e-12 );
+
+        let forces = jacobian.forces( &Wrench::new( Vector3::from([ 0.0, 1.0, 0.0 ]), Vector3::default() ) );
+        assert!( ( forces[ 5 ] - ( a.cos() + ( a + b ).cos() ) ).abs() < 1e-12 );
+        assert!( jacobian.manipulability() > 0.0 );
+    }
+
+    #[test]
+    fn jacobian_derivative_test() {
+        let mut linkage = arm();
+        let ( a, b ) = ( 0.4, 0.9 );
+        let ( da, db ) = ( 1.0, 2.0 );
+        let step = 1e-6;
+        set_angles( &mut linkage, [ a, b ] );
+        let derivative = linkage.jacobian_derivative( 0, 2, Pose::identity(), &rates([ da, db ]) ).unwrap();
+        let before = linkage.jacobian( 0, 2, Pose::identity() ).unwrap();
+        set_angles( &mut linkage, [ a + da * step, b + db * step ] );
+        let after = linkage.jacobian( 0, 2, Pose::identity() ).unwrap();
+        for ( i, column ) in derivative.columns().iter().enumerate() {
+            let difference = ( after.columns()[ i ].linear - before.columns()[ i ].linear ) / step;
+            for k in 0..3 {
+                assert!( ( column.linear[ k ] - difference[ k ] ).abs() < 1e-5, "column {} axis {}", i, k );
+            }
+        }
+    }
+}
@@ -2,18 +2,184 @@
 
 use std::{
     fmt::Debug,
-    ops::{ AddAssign, Deref, DerefMut, Mul }
+    ops::{ Deref, DerefMut }
 };
 
 use linear_algebra::vector::Vector;
 use const_expr_bounds::{ Assert, IsTrue };
 
 use crate::{
+    scalar::Scalar,
     body::Body,
-    constraint::Constraint
+    constraint::{ Constraint, Violation },
+    swing_twist::SwingTwist,
+    dynamic_constraint::DynamicConstraint,
+    shape::ConstraintShape,
+    integrator::Integrator,
+    kinematics::{ Pose, Motion },
+    dense::{ self, DenseMatrix },
+    force::Wrench,
+    spatial::RigidInertia,
+    linkage::Error,
+    rotation
 };
 
-#[derive( Clone, Default, Debug, PartialEq )]
+/// Motion a joint allows relative to the end of its incoming link, with axes in that frame.
+/// `Universal` turns about `first`, then about `second` carried by the first rotation.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub enum JointKind<T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    Fixed,
+    Revolute { axis: Vector<T, DIM> },
+    Prismatic { axis: Vector<T, DIM> },
+    Cylindrical { axis: Vector<T, DIM> },
+    Universal { first: Vector<T, DIM>, second: Vector<T, DIM> },
+    Spherical,
+    Planar { normal: Vector<T, DIM> },
+    #[default]
+    Free
+}
+
+impl<T, const DIM: usize> JointKind<T, DIM>
+where
+    T: Scalar
+{
+    pub fn degrees_of_freedom( &self ) -> usize {
+        match self {
+            JointKind::Fixed => 0,
+            JointKind::Revolute { .. } | JointKind::Prismatic { .. } => 1,
+            JointKind::Cylindrical { .. } | JointKind::Universal { .. } => 2,
+            JointKind::Spherical => rotation::angular_dimension( DIM ),
+            JointKind::Planar { .. } if DIM == 3 => 3,
+            JointKind::Planar { .. } | JointKind::Free => DIM + rotation::angular_dimension( DIM )
+        }
+    }
+
+    pub fn displacement( &self, q: &[T] ) -> Pose<T, DIM> {
+        let at = |i: usize| q.get( i ).copied().unwrap_or( T::zero() );
+        let zero = Vector::default();
+        match self {
+            JointKind::Fixed => Pose::identity(),
+            JointKind::Revolute { axis } => Pose::new( zero, *axis * at( 0 ) ),
+            JointKind::Prismatic { axis } => Pose::new( *axis * at( 0 ), zero ),
+            JointKind::Cylindrical { axis } => Pose::new( *axis * at( 0 ), *axis * at( 1 ) ),
+            JointKind::Universal { first, second } => Pose::new( zero, rotation::compose( &( *second * at( 1 ) ), &( *first * at( 0 ) ) ) ),
+            JointKind::Spherical => {
+                let mut rotation = Vector::default();
+                for i in 0..rotation::angular_dimension( DIM ) {
+                    rotation[ i ] = at( i );
+                }
+                Pose::new( zero, rotation )
+            },
+            JointKind::Planar { normal } if DIM == 3 => {
+                let ( u, v ) = plane_basis( normal );
+                Pose::new( u * at( 0 ) + v * at( 1 ), *normal * at( 2 ) )
+            },
+            JointKind::Planar { .. } | JointKind::Free => {
+                let mut pose = Pose::identity();
+                for i in 0..DIM {
+                    pose.position_mut()[ i ] = at( i );
+                }
+                for i in 0..rotation::angular_dimension( DIM ) {
+                    pose.rotation_mut()[ i ] = at( DIM + i );
+                }
+                pose
+            }
+        }
+    }
+
+    /// Joint coordinates of `displacement`, projecting away any motion the joint does not allow.
+    pub fn coordinates( &self, displacement: &Pose<T, DIM> ) -> Vec<T> {
+        let ( position, rotation ) = ( displacement.position(), displacement.rotation() );
+        match self {
+            JointKind::Fixed => Vec::new(),
+            JointKind::Revolute { axis } => vec![ rotation::dot( axis, rotation ) ],
+            JointKind::Prismatic { axis } => vec![ rotation::dot( axis, position ) ],
+            JointKind::Cylindrical { axis } => vec![ rotation::dot( axis, position ), rotation::dot( axis, rotation ) ],
+            JointKind::Universal { first, second } if DIM == 3 => {
+                // R second = exp( first q0 ) second and Rᵀ first = exp( -second q1 ) first.
+                let carried = rotation::rotate( rotation, second );
+                let inverse = Vector::default() - *rotation;
+                let returned = rotation::rotate( &inverse, first );
+                vec![
+                    rotation::dot( first, &rotation::tangent( second, &carried ) ).atan2( rotation::dot( second, &carried ) ),
+                    -rotation::dot( second, &rotation::tangent( first, &returned ) ).atan2( rotation::dot( first, &returned ) )
+                ]
+            },
+            JointKind::Universal { first, .. } => vec![ rotation::dot( first, rotation ), T::zero() ],
+            JointKind::Spherical => ( 0..rotation::angular_dimension( DIM ) ).map( |i| rotation[ i ] ).collect(),
+            JointKind::Planar { normal } if DIM == 3 => {
+                let ( u, v ) = plane_basis( normal );
+                vec![ rotation::dot( &u, position ), rotation::dot( &v, position ), rotation::dot( normal, rotation ) ]
+            },
+            JointKind::Planar { .. } | JointKind::Free => ( 0..DIM ).map( |i| position[ i ] )
+                .chain( ( 0..rotation::angular_dimension( DIM ) ).map( |i| rotation[ i ] ) )
+                .collect()
+        }
+    }
+
+    pub fn motion_subspace( &self, q: &[T] ) -> Vec<Motion<T, DIM>> {
+        let zero = Vector::default();
+        let unit = |i: usize| {
+            let mut unit = Vector::default();
+            unit[ i ] = T::one();
+            unit
+        };
+        match self {
+            JointKind::Fixed => Vec::new(),
+            JointKind::Revolute { axis } => vec![ Motion::new( *axis, zero ) ],
+            JointKind::Prismatic { axis } => vec![ Motion::new( zero, *axis ) ],
+            JointKind::Cylindrical { axis } => vec![ Motion::new( zero, *axis ), Motion::new( *axis, zero ) ],
+            JointKind::Universal { first, second } => {
+                let turned = rotation::rotate_angular( &( *first * q.first().copied().unwrap_or( T::zero() ) ), second );
+                vec![ Motion::new( *first, zero ), Motion::new( turned, zero ) ]
+            },
+            JointKind::Spherical => ( 0..rotation::angular_dimension( DIM ) ).map( |i| Motion::new( unit( i ), zero ) ).collect(),
+            JointKind::Planar { normal } if DIM == 3 => {
+                let ( u, v ) = plane_basis( normal );
+                vec![ Motion::new( zero, u ), Motion::new( zero, v ), Motion::new( *normal, zero ) ]
+            },
+            JointKind::Planar { .. } | JointKind::Free => ( 0..DIM ).map( |i| Motion::new( zero, unit( i ) ) )
+                .chain( ( 0..rotation::angular_dimension( DIM ) ).map( |i| Motion::new( unit( i ), zero ) ) )
+                .collect()
+        }
+    }
+}
+
+fn plane_basis<T, const DIM: usize>( normal: &Vector<T, DIM> ) -> ( Vector<T, DIM>, Vector<T, DIM> )
+where
+    T: Scalar
+{
+    let mut helper = Vector::default();
+    helper[ if normal[ 0 ].abs() < T::half() { 0 } else { 1 } ] = T::one();
+    let u = rotation::tangent( normal, &helper );
+    let u = u / rotation::norm( &u );
+    ( u, rotation::tangent( normal, &u ) )
+}
+
+#[derive( Clone, Copy, Default, Debug, PartialEq, Eq )]
+pub enum Quantity {
+    #[default]
+    Spatial,
+    Angular
+}
+
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct JointViolation<T> {
+    quantity: Quantity,
+    order: usize,
+    violation: Violation<T>
+}
+
+impl<T> JointViolation<T> {
+    pub fn quantity( &self ) -> Quantity { self.quantity }
+    pub fn order( &self ) -> usize { self.order }
+    pub fn violation( &self ) -> &Violation<T> { &self.violation }
+}
+
+#[derive( Clone, Debug, PartialEq )]
 pub struct Joint<T, const DIM: usize, const ORD: usize>
 where
     T: 'static + Default + Copy + Debug,
@@ -23,11 +189,19 @@ where
 {
     body: Body<T, DIM, ORD>,
     constraints: [Constraint<T, DIM>; (ORD + 1) * 2],
+    kind: JointKind<T, DIM>,
+    displacement: Pose<T, DIM>,
+    rates: Vec<T>,
+    efforts: Vec<T>,
+    swing_twist: Option<SwingTwist<T>>,
+    dynamic_constraints: Vec<Option<DynamicConstraint<T, DIM, ORD>>>,
+    shapes: Vec<Option<ConstraintShape<T, DIM>>>,
+    time: T
 }
 
 impl<T, const DIM: usize, const ORD: usize> Joint<T, DIM, ORD>
 where
-    T: 'static + Default + Copy + Debug + PartialOrd,
+    T: 'static + Default + Copy + Debug,
     [Constraint<T, DIM>; (ORD + 1) * 2]: Default,
     [(); ORD + 1]:,
     [(); (ORD + 1) * 2]:
@@ -36,115 +210,617 @@ where
         Self {
             body,
             constraints,
+            kind: JointKind::Free,
+            displacement: Pose::default(),
+            rates: vec![ T::default(); DIM + rotation::angular_dimension( DIM ) ],
+            efforts: vec![ T::default(); DIM + rotation::angular_dimension( DIM ) ],
+            swing_twist: None,
+            dynamic_constraints: Vec::new(),
+            shapes: Vec::new(),
+            time: T::default()
+        }
+    }
+}
+
+#[allow(clippy::needless_lifetimes)]
+impl<T, const DIM: usize, const ORD: usize> Joint<T, DIM, ORD>
+where
+    T: 'static + Default + Copy + Debug + PartialOrd,
+    [Constraint<T, DIM>; (ORD + 1) * 2]: Default,
+    [(); ORD + 1]:,
+    [(); (ORD + 1) * 2]:
+{
+    /// Projects the displacement onto the new kind and zeroes the rates and efforts.
+    pub fn with_kind( mut self, kind: JointKind<T, DIM> ) -> Self
+    where
+        T: Scalar
+    {
+        self.set_kind( kind );
+        self
+    }
+
+    pub fn kind( &self ) -> &JointKind<T, DIM> { &self.kind }
+
+    pub fn set_kind( &mut self, kind: JointKind<T, DIM> )
+    where
+        T: Scalar
+    {
+        self.kind = kind;
+        self.displacement = kind.displacement( &kind.coordinates( &self.displacement ) );
+        self.rates = vec![ T::zero(); kind.degrees_of_freedom() ];
+        self.efforts = vec![ T::zero(); kind.degrees_of_freedom() ];
+    }
+
+    pub fn coordinates( &self ) -> Vec<T>
+    where
+        T: Scalar
+    {
+        self.kind.coordinates( &self.displacement )
+    }
+
+    pub fn set_coordinates( &mut self, q: &[T] )
+    where
+        T: Scalar
+    {
+        self.displacement = self.kind.displacement( q );
+        self.constrain_displacement();
+    }
+
+    pub fn rates( &self ) -> &[T] { &self.rates }
+    pub fn rates_mut( &mut self ) -> &mut [T] { &mut self.rates }
+
+    pub fn efforts( &self ) -> &[T] { &self.efforts }
+    pub fn efforts_mut( &mut self ) -> &mut [T] { &mut self.efforts }
+
+    /// Spatial constraints of orders `0..=ORD`, then the angular ones.
+    pub fn constraints( &self ) -> &[Constraint<T, DIM>; (ORD + 1) * 2] { &self.constraints }
+
+    pub fn displacement( &self ) -> &Pose<T, DIM> { &self.displacement }
+    pub fn displacement_mut( &mut self ) -> &mut Pose<T, DIM> { &mut self.displacement }
+
+    pub fn swing_twist( &self ) -> &Option<SwingTwist<T>> { &self.swing_twist }
+
+    pub fn constrain_displacement( &mut self )
+    where
+        T: Scalar
+    {
+        self.constraint_at( 0 ).constrain( self.displacement.position_mut() );
+        self.constraint_at( ORD + 1 ).constrain( self.displacement.rotation_mut() );
+        *self.displacement.position_mut() = self.shaped( 0, self.displacement.position() );
+        *self.displacement.rotation_mut() = self.swing_twisted( &self.shaped( ORD + 1, self.displacement.rotation() ) );
+    }
+
+    pub(crate) fn swing_twisted( &self, rotation: &Vector<T, DIM> ) -> Vector<T, DIM>
+    where
+        T: Scalar
+    {
+        match &self.swing_twist {
+            Some( limit ) => rotation::resize( &limit.project( &rotation::resize( rotation ) ) ),
+            None => *rotation
+        }
+    }
+
+    pub fn motion_subspace( &self ) -> Vec<Motion<T, DIM>>
+    where
+        T: Scalar
+    {
+        self.kind.motion_subspace( &self.coordinates() )
+    }
+
+    /// Directions held by a range of zero width; only free joints map them onto constraint axes.
+    pub fn locked_axes( &self ) -> Vec<bool>
+    where
+        T: Scalar
+    {
+        if self.kind != JointKind::Free {
+            return vec![ false; self.kind.degrees_of_freedom() ];
+        }
+        let locked = |constraint: &Constraint<T, DIM>, i: usize| constraint[ i ].is_some_and( |range| range.min() == range.max() );
+        ( 0..DIM ).map( |i| locked( self.position_constraint(), i ) )
+            .chain( ( 0..rotation::angular_dimension( DIM ) ).map( |i| locked( self.rotation_constraint(), i ) ) )
+            .collect()
+    }
+
+    pub fn degrees_of_freedom( &self ) -> usize
+    where
+        T: Scalar
+    {
+        self.kind.degrees_of_freedom()
+    }
+
+    /// Moves the displacement along the motion subspace, then clamps it to the constraints.
+    pub fn displace( &mut self, amounts: &[T] )
+    where
+        T: Scalar
+    {
+        match self.kind {
+            JointKind::Free | JointKind::Spherical => {
+                for ( motion, amount ) in self.motion_subspace().iter().zip( amounts.iter() ) {
+                    let position = *self.displacement.position() + motion.linear * *amount;
+                    let rotation = rotation::compose( self.displacement.rotation(), &( motion.angular * *amount ) );
+                    *self.displacement.position_mut() = position;
+                    *self.displacement.rotation_mut() = rotation;
+                }
+            },
+            kind => {
+                let q: Vec<T> = self.coordinates().iter().zip( amounts.iter().chain( std::iter::repeat( &T::zero() ) ) ).map( |( q, dq )| *q + *dq ).collect();
+                self.displacement = kind.displacement( &q );
+            }
+        }
+        self.constrain_displacement();
+    }
+
+    pub fn spatial_constraint( &self, order: usize ) -> Option<&Constraint<T, DIM>> {
+        ( order <= ORD ).then( || &self.constraints[ order ] )
+    }
+
+    pub fn spatial_constraint_mut( &mut self, order: usize ) -> Option<&mut Constraint<T, DIM>> {
+        ( order <= ORD ).then( || &mut self.constraints[ order ] )
+    }
+
+    pub fn angular_constraint( &self, order: usize ) -> Option<&Constraint<T, DIM>> {
+        ( order <= ORD ).then( || &self.constraints[ ORD + 1 + order ] )
+    }
+
+    pub fn angular_constraint_mut( &mut self, order: usize ) -> Option<&mut Constraint<T, DIM>> {
+        ( order <= ORD ).then( || &mut self.constraints[ ORD + 1 + order ] )
+    }
+
+    fn slot( quantity: Quantity, order: usize ) -> Option<usize> {
+        ( order <= ORD ).then( || match quantity {
+            Quantity::Spatial => order,
+            Quantity::Angular => ORD + 1 + order
+        } )
+    }
+
+    pub fn dynamic_constraint( &self, quantity: Quantity, order: usize ) -> Option<&DynamicConstraint<T, DIM, ORD>> {
+        Self::slot( quantity, order ).and_then( |slot| self.dynamic_constraints.get( slot ) ).and_then( Option::as_ref )
+    }
+
+    /// `None` restores the stored constraint; `false` for orders above `ORD`.
+    pub fn set_dynamic_constraint( &mut self, quantity: Quantity, order: usize, dynamic: Option<DynamicConstraint<T, DIM, ORD>> ) -> bool {
+        let Some( slot ) = Self::slot( quantity, order ) else { return false };
+        if self.dynamic_constraints.len() <= slot {
+            self.dynamic_constraints.resize_with( ( ORD + 1 ) * 2, || None );
+        }
+        self.dynamic_constraints[ slot ] = dynamic;
+        true
+    }
+
+    /// Convex set the constrained vector is projected onto once its ranges are applied.
+    pub fn shape( &self, quantity: Quantity, order: usize ) -> Option<&ConstraintShape<T, DIM>> {
+        Self::slot( quantity, order ).and_then( |slot| self.shapes.get( slot ) ).and_then( Option::as_ref )
+    }
+
+    /// `false` for orders above `ORD`.
+    pub fn set_shape( &mut self, quantity: Quantity, order: usize, shape: Option<ConstraintShape<T, DIM>> ) -> bool {
+        let Some( slot ) = Self::slot( quantity, order ) else { return false };
+        if self.shapes.len() <= slot {
+            self.shapes.resize_with( ( ORD + 1 ) * 2, || None );
+        }
+        self.shapes[ slot ] = shape;
+        true
+    }
+
+    pub fn time( &self ) -> &T { &self.time }
+    pub fn set_time( &mut self, time: T ) { self.time = time; }
+
+    fn constraint_at( &self, slot: usize ) -> Constraint<T, DIM>
+    where
+        T: Scalar
+    {
+        match self.dynamic_constraints.get( slot ) {
+            Some( Some( dynamic ) ) => dynamic.evaluate( self.time, &self.body ),
+            _ => self.constraints[ slot ]
+        }
+    }
+
+    fn shaped( &self, slot: usize, value: &Vector<T, DIM> ) -> Vector<T, DIM>
+    where
+        T: Scalar
+    {
+        match self.shapes.get( slot ) {
+            Some( Some( shape ) ) => shape.project( value ),
+            _ => *value
+        }
+    }
+
+    fn bound( &mut self, slot: usize, soft: bool )
+    where
+        T: Scalar
+    {
+        let constraint = self.constraint_at( slot );
+        let mut value = if slot <= ORD { self.body.spatial[ slot ] } else { self.body.angular[ slot - ORD - 1 ] };
+        if soft {
+            constraint.constrain( &mut value );
+        } else {
+            constraint.constrain_rigid( &mut value );
         }
+        value = self.shaped( slot, &value );
+        if slot <= ORD {
+            self.body.spatial[ slot ] = value;
+        } else {
+            self.body.angular[ slot - ORD - 1 ] = value;
+        }
+    }
+
+    /// Constraint in force now, with a dynamic constraint evaluated.
+    pub fn current_constraint( &self, quantity: Quantity, order: usize ) -> Option<Constraint<T, DIM>>
+    where
+        T: Scalar
+    {
+        Self::slot( quantity, order ).map( |slot| self.constraint_at( slot ) )
+    }
+
+    pub fn position_constraint<'a>( &'a self ) -> &'a Constraint<T, DIM> { &self.constraints[0] }
+    pub fn position_constraint_mut<'b>( &'b mut self ) -> &'b mut Constraint<T, DIM> { &mut self.constraints[0] }
+    pub fn rotation_constraint<'a>( &'a self ) -> &'a Constraint<T, DIM> { &self.constraints[ORD + 1] }
+    pub fn rotation_constraint_mut<'b>( &'b mut self ) -> &'b mut Constraint<T, DIM> { &mut self.constraints[ORD + 1] }
+
+    pub fn spatial_velocity_constraint<'a>( &'a self ) -> &'a Constraint<T, DIM> where Assert<{ ORD >= 1 }>: IsTrue { &self.constraints[1] }
+    pub fn spatial_velocity_constraint_mut<'b>( &'b mut self ) -> &'b mut Constraint<T, DIM> where Assert<{ ORD >= 1 }>: IsTrue { &mut self.constraints[1] }
+    pub fn angular_velocity_constraint<'a>( &'a self ) -> &'a Constraint<T, DIM> where Assert<{ ORD >= 1 }>: IsTrue { &self.constraints[ORD + 2] }
+    pub fn angular_velocity_constraint_mut<'b>( &'b mut self ) -> &'b mut Constraint<T, DIM> where Assert<{ ORD >= 1 }>: IsTrue { &mut self.constraints[ORD + 2] }
+
+    pub fn spatial_acceleration_constraint<'a>( &'a self ) -> &'a Constraint<T, DIM> where Assert<{ ORD >= 2 }>: IsTrue { &self.constraints[2] }
+    pub fn spatial_acceleration_constraint_mut<'b>( &'b mut self ) -> &'b mut Constraint<T, DIM> where Assert<{ ORD >= 2 }>: IsTrue { &mut self.constraints[2] }
+    pub fn angular_acceleration_constraint<'a>( &'a self ) -> &'a Constraint<T, DIM> where Assert<{ ORD >= 2 }>: IsTrue { &self.constraints[ORD + 3] }
+    pub fn angular_acceleration_constraint_mut<'b>( &'b mut self ) -> &'b mut Constraint<T, DIM> where Assert<{ ORD >= 2 }>: IsTrue { &mut self.constraints[ORD + 3] }
+
+    pub fn spatial_jerk_constraint<'a>( &'a self ) -> &'a Constraint<T, DIM> where Assert<{ ORD >= 3 }>: IsTrue { &self.constraints[3] }
+    pub fn spatial_jerk_constraint_mut<'b>( &'b mut self ) -> &'b mut Constraint<T, DIM> where Assert<{ ORD >= 3 }>: IsTrue { &mut self.constraints[3] }
+    pub fn angular_jerk_constraint<'a>( &'a self ) -> &'a Constraint<T, DIM> where Assert<{ ORD >= 3 }>: IsTrue { &self.constraints[ORD + 4] }
+    pub fn angular_jerk_constraint_mut<'b>( &'b mut self ) -> &'b mut Constraint<T, DIM> where Assert<{ ORD >= 3 }>: IsTrue { &mut self.constraints[ORD + 4] }
+
+    pub fn spatial_snap_constraint<'a>( &'a self ) -> &'a Constraint<T, DIM> where Assert<{ ORD >= 4 }>: IsTrue { &self.constraints[4] }
+    pub fn spatial_snap_constraint_mut<'b>( &'b mut self ) -> &'b mut Constraint<T, DIM> where Assert<{ ORD >= 4 }>: IsTrue { &mut self.constraints[4] }
+    pub fn angular_snap_constraint<'a>( &'a self ) -> &'a Constraint<T, DIM> where Assert<{ ORD >= 4 }>: IsTrue { &self.constraints[ORD + 5] }
+    pub fn angular_snap_constraint_mut<'b>( &'b mut self ) -> &'b mut Constraint<T, DIM> where Assert<{ ORD >= 4 }>: IsTrue { &mut self.constraints[ORD + 5] }
+
+    pub fn spatial_crackle_constraint<'a>( &'a self ) -> &'a Constraint<T, DIM> where Assert<{ ORD >= 5 }>: IsTrue { &self.constraints[5] }
+    pub fn spatial_crackle_constraint_mut<'b>( &'b mut self ) -> &'b mut Constraint<T, DIM> where Assert<{ ORD >= 5 }>: IsTrue { &mut self.constraints[5] }
+    pub fn angular_crackle_constraint<'a>( &'a self ) -> &'a Constraint<T, DIM> where Assert<{ ORD >= 5 }>: IsTrue { &self.constraints[ORD + 6] }
+    pub fn angular_crackle_constraint_mut<'b>( &'b mut self ) -> &'b mut Constraint<T, DIM> where Assert<{ ORD >= 5 }>: IsTrue { &mut self.constraints[ORD + 6] }
+
+    pub fn spatial_pop_constraint<'a>( &'a self ) -> &'a Constraint<T, DIM> where Assert<{ ORD >= 6 }>: IsTrue { &self.constraints[6] }
+    pub fn spatial_pop_constraint_mut<'b>( &'b mut self ) -> &'b mut Constraint<T, DIM> where Assert<{ ORD >= 6 }>: IsTrue { &mut self.constraints[6] }
+    pub fn angular_pop_constraint<'a>( &'a self ) -> &'a Constraint<T, DIM> where Assert<{ ORD >= 6 }>: IsTrue { &self.constraints[ORD + 7] }
+    pub fn angular_pop_constraint_mut<'b>( &'b mut self ) -> &'b mut Constraint<T, DIM> where Assert<{ ORD >= 6 }>: IsTrue { &mut self.constraints[ORD + 7] }
+
+    /// Nearest pose the position and rotation constraints allow, swing-twist limit included.
+    pub fn nearest( &self ) -> Pose<T, DIM>
+    where
+        T: Scalar
+    {
+        let mut position = *self.body.position();
+        self.constraint_at( 0 ).constrain( &mut position );
+        let mut rotation = *self.body.rotation();
+        self.constraint_at( ORD + 1 ).constrain( &mut rotation );
+        Pose::new( self.shaped( 0, &position ), self.swing_twisted( &self.shaped( ORD + 1, &rotation ) ) )
     }
 
-    pub fn constrain_position( &mut self ) {
-        self.constraints[0].constrain( self.body.position_mut() );
+    pub fn constrain_position( &mut self )
+    where
+        T: Scalar
+    {
+        *self.body.position_mut() = *self.nearest().position();
     }
 
-    pub fn constrain_rotation( &mut self ) {
-        self.constraints[0].constrain( self.body.rotation_mut() );
+    pub fn constrain_rotation( &mut self )
+    where
+        T: Scalar
+    {
+        *self.body.rotation_mut() = *self.nearest().rotation();
     }
 
     pub fn constrain_spatial_velocity( &mut self )
     where
+        T: Scalar,
         Assert<{ ORD >= 1 }>: IsTrue
     {
-        self.constraints[1].constrain( self.body.spatial_velocity_mut() );
+        self.bound( 1, true );
     }
 
     pub fn constrain_angular_velocity( &mut self )
     where
+        T: Scalar,
         Assert<{ ORD >= 1 }>: IsTrue
     {
-        self.constraints[1].constrain( self.body.angular_velocity_mut() );
+        self.bound( ORD + 2, true );
     }
 
     pub fn constrain_spatial_acceleration( &mut self )
     where
+        T: Scalar,
         Assert<{ ORD >= 2 }>: IsTrue
     {
-        self.constraints[1].constrain( self.body.spatial_acceleration_mut() );
+        self.bound( 2, true );
     }
 
     pub fn constrain_angular_acceleration( &mut self )
     where
+        T: Scalar,
         Assert<{ ORD >= 2 }>: IsTrue
     {
-        self.constraints[1].constrain( self.body.angular_acceleration_mut() );
+        self.bound( ORD + 3, true );
     }
 
     pub fn constrain_spatial_jerk( &mut self )
     where
+        T: Scalar,
         Assert<{ ORD >= 3 }>: IsTrue
     {
-        self.constraints[1].constrain( self.body.spatial_jerk_mut() );
+        self.bound( 3, true );
     }
 
     pub fn constrain_angular_jerk( &mut self )
     where
+        T: Scalar,
         Assert<{ ORD >= 3 }>: IsTrue
     {
-        self.constraints[1].constrain( self.body.angular_jerk_mut() );
+        self.bound( ORD + 4, true );
     }
 
     pub fn constrain_spatial_snap( &mut self )
     where
+        T: Scalar,
         Assert<{ ORD >= 4 }>: IsTrue
     {
-        self.constraints[1].constrain( self.body.spatial_snap_mut() );
+        self.bound( 4, true );
     }
 
     pub fn constrain_angular_snap( &mut self )
     where
+        T: Scalar,
         Assert<{ ORD >= 4 }>: IsTrue
     {
-        self.constraints[1].constrain( self.body.angular_snap_mut() );
+        self.bound( ORD + 5, true );
     }
 
     pub fn constrain_spatial_crackle( &mut self )
     where
+        T: Scalar,
         Assert<{ ORD >= 5 }>: IsTrue
     {
-        self.constraints[1].constrain( self.body.spatial_crackle_mut() );
+        self.bound( 5, true );
     }
 
     pub fn constrain_angular_crackle( &mut self )
     where
+        T: Scalar,
         Assert<{ ORD >= 5 }>: IsTrue
     {
-        self.constraints[1].constrain( self.body.angular_crackle_mut() );
+        self.bound( ORD + 6, true );
     }
 
     pub fn constrain_spatial_pop( &mut self )
     where
+        T: Scalar,
         Assert<{ ORD >= 6 }>: IsTrue
     {
-        self.constraints[1].constrain( self.body.spatial_pop_mut() );
+        self.bound( 6, true );
     }
 
     pub fn constrain_angular_pop( &mut self )
     where
+        T: Scalar,
         Assert<{ ORD >= 6 }>: IsTrue
     {
-        self.constraints[1].constrain( self.body.angular_pop_mut() );
+        self.bound( ORD + 7, true );
     }
 
+    /// Where a stop clamps a derivative, the outward parts of the higher ones are zeroed or reflected.
     pub fn constrain( &mut self )
     where
-    T: PartialOrd,
+        T: Scalar
+    {
+        self.clamp( true );
+    }
+
+    /// Like `constrain`, but with `ORD >= 2` leaves soft position and rotation limits to corrections.
+    pub(crate) fn constrain_rigid( &mut self )
+    where
+        T: Scalar
+    {
+        self.clamp( ORD < 2 );
+    }
+
+    fn clamp( &mut self, soft: bool )
+    where
+        T: Scalar
     {
         for i in 0..=ORD {
-            self.constraints[ i ].constrain( &mut self.body.spatial[ i ] );
+            let spatial = self.constraint_at( i );
+            let angular = self.constraint_at( ORD + 1 + i );
+            spatial.stop( &mut self.body.spatial[ i.. ] );
+            angular.stop( &mut self.body.angular[ i.. ] );
+            self.bound( i, soft || i > 0 );
+            self.bound( ORD + 1 + i, soft || i > 0 );
+        }
+        self.body.angular[ 0 ] = self.swing_twisted( &self.body.angular[ 0 ] );
+    }
+
+    /// Axes outside their constraints, leaving the joint unchanged.
+    pub fn check( &self ) -> Vec<JointViolation<T>>
+    where
+        T: Scalar
+    {
+        let mut violations = Vec::new();
+        for order in 0..=ORD {
+            let stacks = [
+                ( Quantity::Spatial, order, &self.body.spatial[ order ] ),
+                ( Quantity::Angular, ORD + 1 + order, &self.body.angular[ order ] )
+            ];
+            for ( quantity, slot, value ) in stacks {
+                let constraint = self.constraint_at( slot );
+                let mut bounded = *value;
+                constraint.constrain( &mut bounded );
+                bounded = self.shaped( slot, &bounded );
+                if quantity == Quantity::Angular && order == 0 {
+                    bounded = self.swing_twisted( &bounded );
+                }
+                violations.extend( Violation::between( value, &bounded, &constraint ).into_iter().map( |violation| JointViolation { quantity, order, violation } ) );
+            }
+        }
+        violations
+    }
+
+    /// Applies `constrain`, returning the violations; soft ones are marked by `Violation::soft`.
+    pub fn enforce( &mut self ) -> Vec<JointViolation<T>>
+    where
+        T: Scalar
+    {
+        let violations = self.check();
+        self.constrain();
+        violations
+    }
+
+    /// Holds the highest derivative; with `ORD >= 2` soft limit corrections act for the step, below
+    /// that soft limits are clamped like hard ones after it. The body is then held to the motion of
+    /// the kind, and the coordinates and rates follow it.
+    pub fn update<I>( &mut self, integrator: &I, time_step: T )
+    where
+        T: Scalar,
+        I: Integrator<T>
+    {
+        let frame = Pose::new( *self.body.position(), *self.body.rotation() ).compose( &self.displacement.inverse() );
+        if ORD < 2 {
+            self.body.update( integrator, time_step );
+            self.clamp( true );
+        } else {
+            let ( linear, angular ) = self.limit_corrections();
+            self.body.spatial[ 2 ] = self.body.spatial[ 2 ] + linear;
+            self.body.angular[ 2 ] = self.body.angular[ 2 ] + angular;
+            self.body.update( integrator, time_step );
+            self.body.spatial[ 2 ] = self.body.spatial[ 2 ] - linear;
+            self.body.angular[ 2 ] = self.body.angular[ 2 ] - angular;
+        }
+        self.follow_kind( &frame );
+        self.time += time_step;
+    }
+
+    /// Projects the body onto the kind's motion relative to the parent `frame`, derivatives by least
+    /// squares on the motion subspace, and sets the displacement and rates to match.
+    fn follow_kind( &mut self, frame: &Pose<T, DIM> )
+    where
+        T: Scalar
+    {
+        let free = matches!( self.kind, JointKind::Free );
+        let relative = frame.inverse().compose( &Pose::new( *self.body.position(), *self.body.rotation() ) );
+        self.displacement = self.kind.displacement( &self.kind.coordinates( &relative ) );
+        if !free {
+            let pose = frame.compose( &self.displacement );
+            *self.body.position_mut() = *pose.position();
+            *self.body.rotation_mut() = *pose.rotation();
+        }
+
+        let columns: Vec<Motion<T, DIM>> = self.motion_subspace().iter()
+            .map( |motion| Motion::new( frame.transform_angular( &motion.angular ), frame.transform_vector( &motion.linear ) ) )
+            .collect();
+        let vectors: Vec<Vec<T>> = columns.iter().map( |column| column.to_vec() ).collect();
+        let normal: DenseMatrix<T> = vectors.iter().map( |row| vectors.iter().map( |column| dense::dot( row, column ) ).collect() ).collect();
+        for order in 1..=ORD {
+            let motion = Motion::new( self.body.angular[ order ], self.body.spatial[ order ] ).to_vec();
+            let projected: Vec<T> = vectors.iter().map( |column| dense::dot( column, &motion ) ).collect();
+            let weights = dense::solve( &normal, &projected ).unwrap_or_else( || vec![ T::zero(); columns.len() ] );
+            if !free {
+                let fitted = columns.iter().zip( weights.iter() ).fold( Motion::default(), |sum, ( column, weight )| sum + *column * *weight );
+                self.body.spatial[ order ] = fitted.linear;
+                self.body.angular[ order ] = fitted.angular;
+            }
+            if order == 1 {
+                self.rates = weights;
+            }
+        }
+    }
+
+    /// Newton-Euler step with the soft limit corrections at every stage, followed by the stops.
+    pub fn update_dynamics<I>( &mut self, integrator: &I, time_step: T ) -> Result<(), Error>
+    where
+        T: Scalar,
+        I: Integrator<T>,
+        Assert<{ ORD >= 2 }>: IsTrue
+    {
+        self.step_dynamics( integrator, time_step )
+    }
+
+    pub(crate) fn step_dynamics<I>( &mut self, integrator: &I, time_step: T ) -> Result<(), Error>
+    where
+        T: Scalar,
+        I: Integrator<T>
+    {
+        let frame = Pose::new( *self.body.position(), *self.body.rotation() ).compose( &self.displacement.inverse() );
+        let ( position, orientation ) = ( self.constraint_at( 0 ), self.constraint_at( ORD + 1 ) );
+        let start = self.body.angular[ 0 ];
+        self.body.update_dynamics_with(
+            integrator,
+            time_step,
+            |stack| position.correction( &stack[ 0 ], &stack[ 1 ] ),
+            |stack| orientation.correction( &rotation::compose( &start, &stack[ 0 ] ), &stack[ 1 ] )
+        )?;
+        self.follow_kind( &frame );
+        self.time += time_step;
+        self.stop_limits();
+        Ok( () )
+    }
+
+    fn limit_corrections( &self ) -> ( Vector<T, DIM>, Vector<T, DIM> )
+    where
+        T: Scalar
+    {
+        (
+            self.constraint_at( 0 ).correction( &self.body.spatial[ 0 ], &self.body.spatial[ 1 ] ),
+            self.constraint_at( ORD + 1 ).correction( &self.body.angular[ 0 ], &self.body.angular[ 1 ] )
+        )
+    }
+
+    /// Wrench giving the corrective accelerations of the soft position and rotation limits.
+    pub(crate) fn limit_wrench( &self ) -> Wrench<T, DIM>
+    where
+        T: Scalar
+    {
+        if ORD < 1 {
+            return Wrench::default();
         }
+        let ( linear, angular ) = self.limit_corrections();
+        let inertia = RigidInertia::of( &self.body, &Pose::new( *self.position(), *self.rotation() ) );
+        let momentum = inertia.apply( &Motion::new( angular, linear - rotation::tangent( &angular, self.position() ) ) );
+        Wrench::new( momentum.force, momentum.torque - rotation::moment( inertia.center(), &momentum.force ) )
     }
 
-    pub fn update( &mut self, time_step: T )
+    fn stop_limits( &mut self )
     where
-        T: Mul<Output = T> + AddAssign
+        T: Scalar
     {
-        self.body.update( time_step );
+        let ( position, rotation ) = ( self.constraint_at( 0 ), self.constraint_at( ORD + 1 ) );
+        position.stop( &mut self.body.spatial );
+        rotation.stop( &mut self.body.angular );
+    }
+}
+
+impl<T, const ORD: usize> Joint<T, 3, ORD>
+where
+    T: Scalar,
+    [Constraint<T, 3>; (ORD + 1) * 2]: Default,
+    [(); ORD + 1]:,
+    [(); (ORD + 1) * 2]:
+{
+    pub fn with_swing_twist( mut self, swing_twist: SwingTwist<T> ) -> Self {
+        self.swing_twist = Some( swing_twist );
+        self.constrain_displacement();
+        self
+    }
+}
+
+impl<T, const DIM: usize, const ORD: usize> Default for Joint<T, DIM, ORD>
+where
+    T: 'static + Default + Copy + Debug,
+    [Constraint<T, DIM>; (ORD + 1) * 2]: Default,
+    [(); ORD + 1]:,
+    [(); (ORD + 1) * 2]:
+{
+    fn default() -> Self {
+        Self::new( Body::default(), Default::default() )
     }
 }
 
@@ -179,3 +855,318 @@ pub type Joint1D<T, const ORD: usize> = Joint<T, 1, ORD>;
 pub type Joint2D<T, const ORD: usize> = Joint<T, 2, ORD>;
 pub type Joint3D<T, const ORD: usize> = Joint<T, 3, ORD>;
 pub type Joint4D<T, const ORD: usize> = Joint<T, 4, ORD>;
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+    use crate::{
+        body::Body3D,
+        constraint::{ Range, Limit, Constraint3D },
+        integrator::SemiImplicitEuler,
+        particle::Particle
+    };
+
+    #[test]
+    fn kind_test() {
+        let x = Vector3::from([ 1.0, 0.0, 0.0 ]);
+        let z = Vector3::from([ 0.0, 0.0, 1.0 ]);
+        let kinds = [
+            ( JointKind::Fixed, vec![] ),
+            ( JointKind::Revolute { axis: z }, vec![ 0.7 ] ),
+            ( JointKind::Prismatic { axis: x }, vec![ -1.5 ] ),
+            ( JointKind::Cylindrical { axis: z }, vec![ 0.2, 1.1 ] ),
+            ( JointKind::Universal { first: z, second: x }, vec![ 0.4, -0.9 ] ),
+            ( JointKind::Spherical, vec![ 0.1, 0.2, 0.3 ] ),
+            ( JointKind::Planar { normal: z }, vec![ 0.5, -0.5, 0.8 ] ),
+            ( JointKind::Free, vec![ 1.0, 2.0, 3.0, 0.1, 0.2, 0.3 ] )
+        ];
+        for ( kind, q ) in kinds {
+            let mut joint = Joint3D::<f64, 1>::default().with_kind( kind );
+            assert_eq!( joint.degrees_of_freedom(), q.len() );
+            assert_eq!( joint.rates().len(), q.len() );
+            assert_eq!( joint.motion_subspace().len(), q.len() );
+            joint.set_coordinates( &q );
+            for ( value, expected ) in joint.coordinates().iter().zip( q.iter() ) {
+                assert!( ( value - expected ).abs() < 1e-12, "{:?}", kind );
+            }
+        }
+    }
+
+    #[test]
+    fn motion_subspace_test() {
+        let x = Vector3::from([ 1.0, 0.0, 0.0 ]);
+        let z = Vector3::from([ 0.0, 0.0, 1.0 ]);
+        let mut joint = Joint3D::<f64, 1>::default().with_kind( JointKind::Universal { first: z, second: x } );
+        joint.set_coordinates( &[ std::f64::consts::FRAC_PI_2, 0.0 ] );
+        let subspace = joint.motion_subspace();
+        assert!( ( subspace[ 1 ].angular[ 1 ] - 1.0 ).abs() < 1e-12 );
+
+        joint.displace( &[ 0.0, 0.3 ] );
+        let q = joint.coordinates();
+        assert!( ( q[ 0 ] - std::f64::consts::FRAC_PI_2 ).abs() < 1e-12 );
+        assert!( ( q[ 1 ] - 0.3 ).abs() < 1e-12 );
+    }
+
+    #[test]
+    fn kind_update_test() {
+        let mut joint = Joint3D::<f64, 1>::default().with_kind( JointKind::Revolute { axis: Vector3::from([ 0.0, 0.0, 1.0 ]) } );
+        joint.spatial[ 1 ] = Vector3::from([ 1.0, 0.0, 0.0 ]);
+        joint.angular[ 1 ] = Vector3::from([ 0.0, 0.0, 2.0 ]);
+        for step in 1..=2 {
+            joint.update( &SemiImplicitEuler, 0.1 );
+            assert!( rotation::norm( joint.position() ) < 1e-12 );
+            assert!( rotation::norm( joint.spatial_velocity() ) < 1e-12 );
+            assert!( ( joint.rates()[ 0 ] - 2.0 ).abs() < 1e-12 );
+            assert!( ( joint.coordinates()[ 0 ] - 0.2 * step as f64 ).abs() < 1e-12 );
+        }
+
+        let joint = Joint3D::<f64, 1>::default();
+        assert_eq!( joint.kind(), &JointKind::Free );
+        assert_eq!( joint.rates().len(), 6 );
+        assert_eq!( joint.efforts().len(), 6 );
+    }
+
+    #[test]
+    fn limit_test() {
+        let floor = |limit| Constraint3D::new([ None, None, Some( Range::new( 0.0, 10.0 ).with_limit( limit ) ) ]);
+        let falling = |limit| {
+            let body = Body3D::new( 1.0, [ Vector3::from([ 0.0, 0.0, 0.05 ]), Vector3::from([ 0.0, 0.0, -1.0 ]), Vector3::default() ], [ Vector3::default(); 3 ] );
+            Joint3D::<f64, 2>::new( body, [ floor( limit ), Constraint3D::default(), Constraint3D::default(), Constraint3D::default(), Constraint3D::default(), Constraint3D::default() ] )
+        };
+
+        // A stop reflects the impact velocity, scaled by the restitution.
+        let mut joint = falling( Limit::Stop { restitution: 0.5 } );
+        for _ in 0..10 {
+            joint.update_dynamics( &SemiImplicitEuler, 0.01 ).unwrap();
+        }
+        assert!( joint.position()[ 2 ] >= 0.0 );
+        assert!( ( joint.spatial_velocity()[ 2 ] - 0.5 ).abs() < 1e-12 );
+
+        // A soft limit lets the joint in and pushes it back out without touching its position directly.
+        let mut joint = falling( Limit::Soft { stiffness: 400.0, damping: 40.0 } );
+        let mut deepest: f64 = 0.0;
+        for _ in 0..500 {
+            joint.update_dynamics( &SemiImplicitEuler, 0.001 ).unwrap();
+            deepest = deepest.min( joint.position()[ 2 ] );
+        }
+        assert!( deepest < 0.0 );
+        assert!( joint.position()[ 2 ].abs() < 1e-3 );
+        assert!( joint.spatial_velocity()[ 2 ].abs() < 1e-2 );
+
+        // Rotation limits act on a body without rotational inertia as well.
+        let twist = Constraint3D::new([ None, None, Some( Range::new( -0.1, 0.1 ).with_limit( Limit::Soft { stiffness: 400.0, damping: 40.0 } ) ) ]);
+        let body = Body3D::new( 1.0, [ Vector3::default(); 3 ], [ Vector3::default(), Vector3::from([ 0.0, 0.0, 1.0 ]), Vector3::default() ] );
+        let mut joint = Joint3D::<f64, 2>::new( body, [ Constraint3D::default(), Constraint3D::default(), Constraint3D::default(), twist, Constraint3D::default(), Constraint3D::default() ] );
+        for _ in 0..500 {
+            joint.update_dynamics( &SemiImplicitEuler, 0.001 ).unwrap();
+        }
+        assert!( ( joint.rotation()[ 2 ] - 0.1 ).abs() < 1e-3 );
+        assert!( joint.angular_velocity()[ 2 ].abs() < 1e-2 );
+
+        // Without an acceleration to correct, constraining holds a soft limit like a hard one.
+        let body = Body3D::new( 1.0, [ Vector3::from([ 0.0, 0.0, -0.5 ]), Vector3::default() ], [ Vector3::default(); 2 ] );
+        let mut joint = Joint3D::<f64, 1>::new( body, [ floor( Limit::Soft { stiffness: 400.0, damping: 40.0 } ), Constraint3D::default(), Constraint3D::default(), Constraint3D::default() ] );
+        joint.constrain();
+        assert_eq!( joint.position()[ 2 ], 0.0 );
+
+        // Without an acceleration slot, stepping clamps a soft limit after the step.
+        let body = Body3D::new( 1.0, [ Vector3::from([ 0.0, 0.0, 0.05 ]), Vector3::from([ 0.0, 0.0, -1.0 ]) ], [ Vector3::default(); 2 ] );
+        let mut joint = Joint3D::<f64, 1>::new( body, [ floor( Limit::Soft { stiffness: 400.0, damping: 40.0 } ), Constraint3D::default(), Constraint3D::default(), Constraint3D::default() ] );
+        joint.update( &SemiImplicitEuler, 0.1 );
+        assert_eq!( joint.position()[ 2 ], 0.0 );
+    }
+
+    #[test]
+    fn stop_test() {
+        let stop = |restitution| Constraint3D::new([ None, None, Some( Range::new( 0.0, 10.0 ).with_limit( Limit::Stop { restitution } ) ) ]);
+        let pushed = |constraints| {
+            let spatial = [ Vector3::from([ 0.0, 0.0, -0.1 ]), Vector3::from([ 1.0, 0.0, -1.0 ]), Vector3::from([ 0.0, 0.0, -10.0 ]) ];
+            Joint3D::<f64, 2>::new( Body3D::new( 1.0, spatial, [ Vector3::default(); 3 ] ), constraints )
+        };
+
+        // An inelastic stop removes the outward velocity and acceleration, so the joint rests on it.
+        let mut joint = pushed( [ stop( 0.0 ), Constraint3D::default(), Constraint3D::default(), Constraint3D::default(), Constraint3D::default(), Constraint3D::default() ] );
+        for _ in 0..10 {
+            joint.constrain();
+            assert_eq!( joint.position()[ 2 ], 0.0 );
+            joint.update( &SemiImplicitEuler, 0.01 );
+        }
+        assert_eq!( joint.spatial_velocity(), &Vector3::from([ 1.0, 0.0, 0.0 ]) );
+        assert_eq!( joint.spatial_acceleration()[ 2 ], 0.0 );
+
+        // Restitution reflects every outward derivative.
+        let mut joint = pushed( [ stop( 0.5 ), Constraint3D::default(), Constraint3D::default(), Constraint3D::default(), Constraint3D::default(), Constraint3D::default() ] );
+        joint.constrain();
+        assert_eq!( joint.spatial_velocity()[ 2 ], 0.5 );
+        assert_eq!( joint.spatial_acceleration()[ 2 ], 5.0 );
+
+        // A stop on the velocity only reaches the derivatives above it.
+        let mut joint = pushed( [ Constraint3D::default(), stop( 0.0 ), Constraint3D::default(), Constraint3D::default(), Constraint3D::default(), Constraint3D::default() ] );
+        joint.constrain();
+        assert_eq!( joint.position()[ 2 ], -0.1 );
+        assert_eq!( joint.spatial_velocity()[ 2 ], 0.0 );
+        assert_eq!( joint.spatial_acceleration()[ 2 ], 0.0 );
+    }
+
+    #[test]
+    fn constraint_layout_test() {
+        let bound = |limit: f64| Constraint3D::new([ Some( Range::new( -limit, limit ) ), None, None ]);
+        let unconstrained = || {
+            let mut joint = Joint3D::<f64, 6>::new( Body3D::new( 1.0, [ Vector3::from([ 100.0, 100.0, 0.0 ]); 7 ], [ Vector3::from([ 100.0, 100.0, 0.0 ]); 7 ] ), Default::default() );
+            for order in 0..=6 {
+                *joint.spatial_constraint_mut( order ).unwrap() = bound( order as f64 + 1.0 );
+                *joint.angular_constraint_mut( order ).unwrap() = bound( order as f64 + 0.5 );
+            }
+            joint
+        };
+
+        let mut joint = unconstrained();
+        assert!( joint.spatial_constraint( 7 ).is_none() && joint.angular_constraint( 7 ).is_none() );
+        assert_eq!( joint.constraints()[ 7 ], bound( 0.5 ) );
+        let named = [
+            ( joint.position_constraint(), joint.rotation_constraint() ),
+            ( joint.spatial_velocity_constraint(), joint.angular_velocity_constraint() ),
+            ( joint.spatial_acceleration_constraint(), joint.angular_acceleration_constraint() ),
+            ( joint.spatial_jerk_constraint(), joint.angular_jerk_constraint() ),
+            ( joint.spatial_snap_constraint(), joint.angular_snap_constraint() ),
+            ( joint.spatial_crackle_constraint(), joint.angular_crackle_constraint() ),
+            ( joint.spatial_pop_constraint(), joint.angular_pop_constraint() )
+        ];
+        for ( order, ( spatial, angular ) ) in named.iter().enumerate() {
+            assert_eq!( **spatial, bound( order as f64 + 1.0 ) );
+            assert_eq!( **angular, bound( order as f64 + 0.5 ) );
+        }
+
+        joint.constrain();
+        for order in 0..=6 {
+            assert_eq!( joint.spatial[ order ], Vector3::from([ order as f64 + 1.0, 100.0, 0.0 ]) );
+            assert_eq!( joint.angular[ order ], Vector3::from([ order as f64 + 0.5, 100.0, 0.0 ]) );
+        }
+
+        // Each named method clamps its own derivative and nothing else.
+        let methods: [( fn( &mut Joint3D<f64, 6> ), bool, usize ); 14] = [
+            ( Joint3D::constrain_position, true, 0 ), ( Joint3D::constrain_rotation, false, 0 ),
+            ( Joint3D::constrain_spatial_velocity, true, 1 ), ( Joint3D::constrain_angular_velocity, false, 1 ),
+            ( Joint3D::constrain_spatial_acceleration, true, 2 ), ( Joint3D::constrain_angular_acceleration, false, 2 ),
+            ( Joint3D::constrain_spatial_jerk, true, 3 ), ( Joint3D::constrain_angular_jerk, false, 3 ),
+            ( Joint3D::constrain_spatial_snap, true, 4 ), ( Joint3D::constrain_angular_snap, false, 4 ),
+            ( Joint3D::constrain_spatial_crackle, true, 5 ), ( Joint3D::constrain_angular_crackle, false, 5 ),
+            ( Joint3D::constrain_spatial_pop, true, 6 ), ( Joint3D::constrain_angular_pop, false, 6 )
+        ];
+        for ( method, spatial, order ) in methods {
+            let mut joint = unconstrained();
+            method( &mut joint );
+            for other in 0..=6 {
+                let expected_spatial = if spatial && other == order { other as f64 + 1.0 } else { 100.0 };
+                let expected_angular = if !spatial && other == order { other as f64 + 0.5 } else { 100.0 };
+                assert_eq!( joint.spatial[ other ][ 0 ], expected_spatial, "spatial {} after {}", other, order );
+                assert_eq!( joint.angular[ other ][ 0 ], expected_angular, "angular {} after {}", other, order );
+            }
+        }
+    }
+
+    #[test]
+    fn swing_twist_test() {
+        let limit = SwingTwist::new( Vector3::from([ 0.0, 0.0, 1.0 ]), Vector3::from([ 1.0, 0.0, 0.0 ]), [ 0.5, 0.2 ], Range::new( -0.3, 0.6 ) ).unwrap();
+        let mut joint = Joint3D::<f64, 1>::default().with_kind( JointKind::Spherical ).with_swing_twist( limit );
+        joint.set_coordinates( &[ 0.1, 0.0, 0.2 ] );
+        assert_eq!( joint.coordinates(), vec![ 0.1, 0.0, 0.2 ] );
+        joint.set_coordinates( &[ 0.0, 0.0, 1.0 ] );
+        assert!( ( joint.coordinates()[ 2 ] - 0.6 ).abs() < 1e-12 );
+        joint.displace( &[ 0.0, -1.0, 0.0 ] );
+        let ( swing, twist ) = limit.decompose( joint.displacement().rotation() );
+        assert!( ( swing[ 0 ] / 0.5 ).powi( 2 ) + ( swing[ 1 ] / 0.2 ).powi( 2 ) < 1.0 + 1e-9 );
+        assert!( twist > -0.3 - 1e-9 && twist < 0.6 + 1e-9 );
+
+        // The body rotation is checked and constrained by the limit as well.
+        *joint.rotation_mut() = Vector3::from([ 0.0, 0.0, 1.0 ]);
+        let violations = joint.check();
+        assert!( violations.iter().all( |violation| ( violation.quantity(), violation.order() ) == ( Quantity::Angular, 0 ) ) );
+        assert!( violations.iter().any( |violation| violation.violation().axis() == 2 && ( *violation.violation().magnitude() - 0.4 ).abs() < 1e-12 ) );
+        joint.constrain();
+        assert!( ( joint.rotation()[ 2 ] - 0.6 ).abs() < 1e-12 );
+        assert!( joint.check().iter().all( |violation| *violation.violation().magnitude() < 1e-12 ) );
+    }
+
+    #[test]
+    fn check_test() {
+        let bound = Constraint3D::new([ None, Some( Range::new( -1.0, 1.0 ) ), None ]);
+        let body = Body3D::new( 1.0, [ Vector3::from([ 0.0, 1.5, 0.0 ]), Vector3::from([ 0.0, -2.0, 0.0 ]) ], [ Vector3::default(); 2 ] );
+        let mut joint = Joint3D::<f64, 1>::new( body, [ bound, Constraint3D::default(), Constraint3D::default(), bound ] );
+        let violations = joint.check();
+        assert_eq!( violations.len(), 1 );
+        assert_eq!( violations[ 0 ].quantity(), Quantity::Spatial );
+        assert_eq!( violations[ 0 ].order(), 0 );
+        let violation = violations[ 0 ].violation();
+        assert_eq!( ( violation.axis(), *violation.value(), *violation.bound(), *violation.magnitude() ), ( 1, 1.5, 1.0, 0.5 ) );
+        assert!( !violation.soft() );
+
+        *joint.angular_velocity_mut() = Vector3::from([ 0.0, -3.0, 0.0 ]);
+        let violations = joint.enforce();
+        assert_eq!( violations.len(), 2 );
+        assert_eq!( ( violations[ 1 ].quantity(), violations[ 1 ].order(), *violations[ 1 ].violation().bound() ), ( Quantity::Angular, 1, -1.0 ) );
+        assert!( joint.check().is_empty() );
+        assert_eq!( joint.position()[ 1 ], 1.0 );
+    }
+
+    #[test]
+    fn shape_test() {
+        let bound = Constraint3D::new([ Some( Range::new( -1.0, 1.0 ) ), None, None ]);
+        let body = Body3D::new( 1.0, [ Vector3::default(), Vector3::from([ 3.0, 0.0, 4.0 ]) ], [ Vector3::default(); 2 ] );
+        let mut joint = Joint3D::<f64, 1>::new( body, [ Constraint3D::default(), bound, Constraint3D::default(), Constraint3D::default() ] );
+        assert!( joint.set_shape( Quantity::Spatial, 1, Some( ConstraintShape::magnitude( 1.0 ) ) ) );
+        assert!( !joint.set_shape( Quantity::Spatial, 2, None ) );
+        assert!( joint.shape( Quantity::Angular, 1 ).is_none() );
+        // Ranges clamp first, then the shape projects.
+        assert_eq!( joint.check().len(), 2 );
+        joint.constrain();
+        let speed = 17f64.sqrt();
+        assert!( ( joint.spatial_velocity()[ 0 ] - 1.0 / speed ).abs() < 1e-12 );
+        assert!( ( joint.spatial_velocity()[ 2 ] - 4.0 / speed ).abs() < 1e-12 );
+    }
+
+    #[test]
+    fn dynamic_constraint_test() {
+        let body = Body3D::new( 1.0, [ Vector3::from([ 2.0, 0.0, 0.0 ]), Vector3::from([ 5.0, 0.0, 0.0 ]) ], [ Vector3::default(); 2 ] );
+        let mut joint = Joint3D::<f64, 1>::new( body, [ Constraint3D::default(); 4 ] );
+        // Speed limit falling as the joint extends.
+        let speed = DynamicConstraint::function( |_, particle: &Particle<f64, 3, 1>| {
+            let limit = 4.0 / ( 1.0 + particle.position()[ 0 ] );
+            Constraint3D::new([ Some( Range::new( -limit, limit ) ), None, None ])
+        } );
+        assert!( joint.set_dynamic_constraint( Quantity::Spatial, 1, Some( speed ) ) );
+        assert!( !joint.set_dynamic_constraint( Quantity::Angular, 2, None ) );
+        joint.constrain();
+        assert!( ( joint.spatial_velocity()[ 0 ] - 4.0 / 3.0 ).abs() < 1e-12 );
+
+        // Travel of a telescoping stage growing over time.
+        let travel = |max: f64| Constraint3D::new([ Some( Range::new( 0.0, max ) ), None, None ]);
+        joint.set_dynamic_constraint( Quantity::Spatial, 0, Some( DynamicConstraint::Schedule( vec![ ( 0.0, travel( 1.0 ) ), ( 10.0, travel( 3.0 ) ) ] ) ) );
+        joint.constrain();
+        assert_eq!( joint.position()[ 0 ], 1.0 );
+        joint.set_time( 5.0 );
+        *joint.position_mut() = Vector3::from([ 2.5, 0.0, 0.0 ]);
+        assert_eq!( *joint.check()[ 0 ].violation().bound(), 2.0 );
+        joint.constrain();
+        assert_eq!( joint.position()[ 0 ], 2.0 );
+        // The stored constraint is kept, so clearing the dynamic one restores it.
+        assert_eq!( *joint.position_constraint(), Constraint3D::default() );
+        assert_eq!( joint.current_constraint( Quantity::Spatial, 0 ), Some( travel( 2.0 ) ) );
+        joint.set_time( 10.0 );
+        *joint.position_mut() = Vector3::from([ 5.0, 0.0, 0.0 ]);
+        joint.constrain_position();
+        assert_eq!( joint.position()[ 0 ], 3.0 );
+        joint.set_dynamic_constraint( Quantity::Spatial, 0, None );
+        *joint.position_mut() = Vector3::from([ 5.0, 0.0, 0.0 ]);
+        joint.constrain();
+        assert_eq!( joint.position()[ 0 ], 5.0 );
+    }
+
+    #[test]
+    fn send_sync_test() {
+        fn send_sync<S: Send + Sync>() {}
+        send_sync::<Joint3D<f64, 2>>();
+        send_sync::<DynamicConstraint<f64, 3, 2>>();
+    }
+}
@@ -0,0 +1,299 @@
+// Copyright 2024 Bewusstsein Labs
+
+use std::{
+    fmt::Debug,
+    collections::BTreeMap,
+    ops::{ Add, Mul }
+};
+
+use linear_algebra::vector::Vector;
+
+use crate::{
+    scalar::Scalar,
+    constraint::Constraint,
+    linkage::{ Linkage, Error },
+    rotation
+};
+
+/// Rigid transform: a rotation (see `rotation::compose`) followed by a translation.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct Pose<T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    position: Vector<T, DIM>,
+    rotation: Vector<T, DIM>
+}
+
+#[allow(clippy::needless_lifetimes)]
+impl<T, const DIM: usize> Pose<T, DIM>
+where
+    T: 'static + Default + Copy + Debug
+{
+    pub fn new( position: Vector<T, DIM>, rotation: Vector<T, DIM> ) -> Self {
+        Self { position, rotation }
+    }
+
+    pub fn position<'a>( &'a self ) -> &'a Vector<T, DIM> { &self.position }
+    pub fn position_mut<'b>( &'b mut self ) -> &'b mut Vector<T, DIM> { &mut self.position }
+    pub fn rotation<'a>( &'a self ) -> &'a Vector<T, DIM> { &self.rotation }
+    pub fn rotation_mut<'b>( &'b mut self ) -> &'b mut Vector<T, DIM> { &mut self.rotation }
+}
+
+impl<T, const DIM: usize> Pose<T, DIM>
+where
+    T: Scalar
+{
+    pub fn identity() -> Self {
+        Self::default()
+    }
+
+    /// Returns `self * other`, i.e. `other` expressed in the frame of `self`.
+    pub fn compose( &self, other: &Self ) -> Self {
+        Self {
+            position: self.position + rotation::rotate( &self.rotation, &other.position ),
+            rotation: rotation::compose( &other.rotation, &self.rotation )
+        }
+    }
+
+    pub fn inverse( &self ) -> Self {
+        let rotation = Vector::default() - self.rotation;
+        Self {
+            position: Vector::default() - rotation::rotate( &rotation, &self.position ),
+            rotation
+        }
+    }
+
+    pub fn transform_point( &self, point: &Vector<T, DIM> ) -> Vector<T, DIM> {
+        self.position + rotation::rotate( &self.rotation, point )
+    }
+
+    pub fn transform_vector( &self, vector: &Vector<T, DIM> ) -> Vector<T, DIM> {
+        rotation::rotate( &self.rotation, vector )
+    }
+
+    pub fn transform_angular( &self, angular: &Vector<T, DIM> ) -> Vector<T, DIM> {
+        rotation::rotate_angular( &self.rotation, angular )
+    }
+}
+
+/// Rigid body velocity, or a direction of motion: an angular part and the linear velocity of a
+/// reference point.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct Motion<T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    pub angular: Vector<T, DIM>,
+    pub linear: Vector<T, DIM>
+}
+
+impl<T, const DIM: usize> Motion<T, DIM>
+where
+    T: 'static + Default + Copy + Debug
+{
+    pub fn new( angular: Vector<T, DIM>, linear: Vector<T, DIM> ) -> Self {
+        Self { angular, linear }
+    }
+}
+
+impl<T, const DIM: usize> Motion<T, DIM>
+where
+    T: Scalar
+{
+    /// Moves the reference point by `offset`.
+    pub fn shift( &self, offset: &Vector<T, DIM> ) -> Self {
+        Self::new( self.angular, self.linear + rotation::tangent( &self.angular, offset ) )
+    }
+}
+
+impl<T, const DIM: usize> Add for Motion<T, DIM>
+where
+    T: Scalar
+{
+    type Output = Self;
+
+    fn add( self, rhs: Self ) -> Self::Output {
+        Self::new( self.angular + rhs.angular, self.linear + rhs.linear )
+    }
+}
+
+impl<T, const DIM: usize> Mul<T> for Motion<T, DIM>
+where
+    T: Scalar
+{
+    type Output = Self;
+
+    fn mul( self, rhs: T ) -> Self::Output {
+        Self::new( self.angular * rhs, self.linear * rhs )
+    }
+}
+
+/// Breadth first spanning tree over the joints reachable from `root`; links that would close a loop
+/// are listed in `cut_links`.
+#[derive( Clone, Default, Debug, PartialEq )]
+pub struct SpanningTree<I> {
+    root: I,
+    order: Vec<I>,
+    parents: BTreeMap<I, I>,
+    cut_links: Vec<( I, I )>
+}
+
+impl<I> SpanningTree<I>
+where
+    I: Copy + Ord
+{
+    pub fn new( root: I, order: Vec<I>, parents: BTreeMap<I, I>, cut_links: Vec<( I, I )> ) -> Self {
+        Self { root, order, parents, cut_links }
+    }
+
+    pub fn root( &self ) -> &I { &self.root }
+    pub fn order( &self ) -> &[I] { &self.order }
+    pub fn cut_links( &self ) -> &[( I, I )] { &self.cut_links }
+
+    pub fn contains( &self, id: &I ) -> bool {
+        *id == self.root || self.parents.contains_key( id )
+    }
+
+    pub fn parent( &self, id: &I ) -> Option<&I> {
+        self.parents.get( id )
+    }
+
+    pub fn children( &self, id: &I ) -> Vec<I> {
+        self.order.iter().filter( |child| self.parents.get( child ) == Some( id ) ).copied().collect()
+    }
+
+    pub fn leaves( &self ) -> Vec<I> {
+        self.order.iter().filter( |id| self.children( id ).is_empty() ).copied().collect()
+    }
+
+    /// Joints from `id` up to and including the root.
+    pub fn ancestors( &self, id: &I ) -> Vec<I> {
+        let mut path = Vec::new();
+        if !self.contains( id ) {
+            return path;
+        }
+        let mut current = *id;
+        path.push( current );
+        while let Some( parent ) = self.parents.get( &current ) {
+            current = *parent;
+            path.push( current );
+        }
+        path
+    }
+
+    /// Joints along the tree from `from` to `to`, both included.
+    pub fn path( &self, from: &I, to: &I ) -> Option<Vec<I>> {
+        let up = self.ancestors( from );
+        let down = self.ancestors( to );
+        if up.is_empty() || down.is_empty() {
+            return None;
+        }
+        let common = up.iter().position( |id| down.contains( id ) )?;
+        let meet = down.iter().position( |id| *id == up[ common ] )?;
+        let mut path: Vec<I> = up[ ..=common ].to_vec();
+        path.extend( down[ ..meet ].iter().rev() );
+        Some( path )
+    }
+}
+
+#[derive( Clone, Default, Debug, PartialEq )]
+pub struct ForwardKinematics<I, T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    poses: BTreeMap<I, Pose<T, DIM>>,
+    tree: SpanningTree<I>
+}
+
+impl<I, T, const DIM: usize> ForwardKinematics<I, T, DIM>
+where
+    I: Copy + Ord,
+    T: 'static + Default + Copy + Debug
+{
+    pub fn pose( &self, id: &I ) -> Option<&Pose<T, DIM>> { self.poses.get( id ) }
+    pub fn poses( &self ) -> &BTreeMap<I, Pose<T, DIM>> { &self.poses }
+    pub fn tree( &self ) -> &SpanningTree<I> { &self.tree }
+
+    /// Leaves of the spanning tree, i.e. joints with no outgoing links.
+    pub fn end_effectors( &self ) -> Vec<I> {
+        self.tree.leaves()
+    }
+}
+
+impl <I, T, const DIM: usize, const ORD: usize> Linkage<I, T, DIM, ORD>
+where
+    I: 'static + Default + Copy + Debug + Ord,
+    T: Scalar,
+    [Constraint<T, DIM>; (ORD + 1) * 2]: Default,
+    [(); ORD + 1]:,
+    [(); (ORD + 1) * 2]:
+{
+    /// World frame poses of every joint reachable from `root`.
+    ///
+    /// The frame of a joint is the frame of its parent, followed by the transform of the link between
+    /// them, followed by the joint's own displacement. The root starts from `base`.
+    pub fn forward_kinematics( &self, root: I, base: Pose<T, DIM> ) -> Result<ForwardKinematics<I, T, DIM>, Error> {
+        let tree = self.spanning_tree( root )?;
+        let mut poses = BTreeMap::new();
+        for id in tree.order() {
+            let joint = self.get_joint( *id ).ok_or( Error::JointNotFound )?;
+            let frame = match tree.parent( id ) {
+                Some( parent ) => poses[ parent ].compose( &self.link_transform( *parent, *id )? ),
+                None => base
+            };
+            poses.insert( *id, frame.compose( joint.displacement() ) );
+        }
+        Ok( ForwardKinematics { poses, tree } )
+    }
+
+    /// Runs `forward_kinematics` and writes the resulting poses into the joints' positions and rotations.
+    pub fn apply_forward_kinematics( &mut self, root: I, base: Pose<T, DIM> ) -> Result<ForwardKinematics<I, T, DIM>, Error> {
+        let kinematics = self.forward_kinematics( root, base )?;
+        for ( id, pose ) in kinematics.poses() {
+            if let Some( joint ) = self.get_joint_mut( *id ) {
+                *joint.position_mut() = *pose.position();
+                *joint.rotation_mut() = *pose.rotation();
+            }
+        }
+        Ok( kinematics )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+    use crate::{
+        body::Body3D,
+        joint::Joint3D,
+        link::Link,
+        constraint::Constraint3D,
+        linkage::Linkage3D
+    };
+
+    #[test]
+    fn forward_kinematics_test() {
+        let quarter = std::f64::consts::FRAC_PI_2;
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        for id in 0..3 {
+            let mut joint = Joint3D::new( Body3D::new( 1.0, [ Vector3::default(); 2 ], [ Vector3::default(); 2 ] ), [ Constraint3D::default(); 4 ] );
+            if id < 2 {
+                *joint.displacement_mut().rotation_mut() = Vector3::from([ 0.0, 0.0, quarter ]);
+            }
+            linkage.add_joint( id, joint ).unwrap();
+        }
+        let offset = Pose::new( Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::default() );
+        linkage.add_link( 0, 1, Link::default().with_transform( offset ) ).unwrap();
+        linkage.add_link( 2, 1, Link::default().with_transform( offset.inverse() ) ).unwrap();
+
+        let kinematics = linkage.apply_forward_kinematics( 0, Pose::identity() ).unwrap();
+        assert_eq!( kinematics.end_effectors(), vec![ 2 ] );
+        let position = *linkage.get_joint( 2 ).unwrap().position();
+        let expected = Vector3::from([ -1.0, 1.0, 0.0 ]);
+        for i in 0..3 {
+            assert!( ( position[ i ] - expected[ i ] ).abs() < 1e-9 );
+        }
+        assert_eq!( kinematics.tree().path( &2, &0 ), Some( vec![ 2, 1, 0 ] ) );
+    }
+}
@@ -4,9 +4,27 @@
 #![feature(adt_const_params)]
 #![feature(generic_const_exprs)]
 
+pub mod scalar;
+pub mod integrator;
+pub mod rotation;
+pub(crate) mod dense;
 pub mod particle;
+pub mod planar;
+pub mod inertia;
 pub mod body;
+pub mod force;
 pub mod joint;
 pub mod link;
 pub mod constraint;
+pub mod shape;
+pub mod swing_twist;
+pub mod dynamic_constraint;
 pub mod linkage;
+pub mod kinematics;
+pub mod jacobian;
+pub mod inverse_kinematics;
+pub mod xpbd;
+pub mod spatial;
+pub mod dynamics;
+pub mod loops;
+pub mod assembly;
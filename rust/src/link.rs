@@ -1,10 +1,27 @@
 // Copyright 2024 Bewusstsein Labs
 
 use std::fmt::Debug;
-use std::marker::PhantomData;
 
-use crate::constraint::Constraint;
-use crate::joint::Joint;
+use linear_algebra::vector::Vector;
+
+use crate::constraint::{ Range, Constraint, Violation };
+use crate::joint::{ Joint, Quantity };
+use crate::kinematics::Pose;
+use crate::scalar::Scalar;
+use crate::shape::ConstraintShape;
+use crate::rotation;
+
+/// Violation of a link, measured in the first joint's frame relative to the link transform.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct LinkViolation<T> {
+    quantity: Quantity,
+    violation: Violation<T>
+}
+
+impl<T> LinkViolation<T> {
+    pub fn quantity( &self ) -> Quantity { self.quantity }
+    pub fn violation( &self ) -> &Violation<T> { &self.violation }
+}
 
 #[derive( Clone, Default, Debug, PartialEq )]
 pub struct Link<T, const DIM: usize>
@@ -12,7 +29,11 @@ where
     T: 'static + Default + Copy + Debug
 {
     mass: T,
-    constraint: Constraint<T, DIM>
+    constraint: Constraint<T, DIM>,
+    angular_constraint: Constraint<T, DIM>,
+    shape: Option<ConstraintShape<T, DIM>>,
+    angular_shape: Option<ConstraintShape<T, DIM>>,
+    transform: Pose<T, DIM>
 }
 
 impl<T, const DIM: usize> Link<T, DIM>
@@ -22,36 +43,145 @@ where
     pub fn new( mass: T, constraint: Constraint<T, DIM> ) -> Self {
         Self {
             mass,
-            constraint
+            constraint,
+            angular_constraint: Constraint::default(),
+            shape: None,
+            angular_shape: None,
+            transform: Pose::default()
         }
     }
 
-    /*
-    pub fn constrain<const ORD: usize>( &self, joint1: &Joint<T, DIM, ORD>, joint2: &mut Joint<T, DIM, ORD>  )
+    /// Link holding the second joint at exactly `transform` from the first.
+    pub fn rigid( mass: T, transform: Pose<T, DIM> ) -> Self
+    where
+        T: PartialOrd
+    {
+        let fixed = Constraint::new( [ Some( Range::new( T::default(), T::default() ) ); DIM ] );
+        Self::new( mass, fixed ).with_angular_constraint( fixed ).with_transform( transform )
+    }
+
+    pub fn with_angular_constraint( mut self, angular_constraint: Constraint<T, DIM> ) -> Self {
+        self.angular_constraint = angular_constraint;
+        self
+    }
+
+    pub fn with_transform( mut self, transform: Pose<T, DIM> ) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn with_shape( mut self, shape: ConstraintShape<T, DIM> ) -> Self {
+        self.shape = Some( shape );
+        self
+    }
+
+    pub fn with_angular_shape( mut self, angular_shape: ConstraintShape<T, DIM> ) -> Self {
+        self.angular_shape = Some( angular_shape );
+        self
+    }
+
+    pub fn mass( &self ) -> &T { &self.mass }
+    pub fn constraint( &self ) -> &Constraint<T, DIM> { &self.constraint }
+    pub fn angular_constraint( &self ) -> &Constraint<T, DIM> { &self.angular_constraint }
+    pub fn shape( &self ) -> &Option<ConstraintShape<T, DIM>> { &self.shape }
+    pub fn angular_shape( &self ) -> &Option<ConstraintShape<T, DIM>> { &self.angular_shape }
+    pub fn transform( &self ) -> &Pose<T, DIM> { &self.transform }
+    pub fn transform_mut( &mut self ) -> &mut Pose<T, DIM> { &mut self.transform }
+
+    /// Nearest pose to `second` whose offset from `transform`, in the frame of `first`, the link allows.
+    pub fn nearest( &self, first: &Pose<T, DIM>, second: &Pose<T, DIM> ) -> Pose<T, DIM>
     where
-        T: 'static + Default + Copy + Debug + PartialEq + PartialOrd,
+        T: Scalar
+    {
+        let mut nearest = *second;
+        let ( offset, deviation ) = self.deviations( first, second );
+        let constrained = self.bound( Quantity::Spatial, &offset );
+        if constrained != offset {
+            *nearest.position_mut() = *first.position() + rotation::rotate( first.rotation(), &( *self.transform.position() + constrained ) );
+        }
+
+        let constrained = self.bound( Quantity::Angular, &deviation );
+        if constrained != deviation {
+            let relative_rotation = rotation::compose( &constrained, self.transform.rotation() );
+            *nearest.rotation_mut() = rotation::compose( &relative_rotation, first.rotation() );
+        }
+        nearest
+    }
+
+    pub fn check( &self, first: &Pose<T, DIM>, second: &Pose<T, DIM> ) -> Vec<LinkViolation<T>>
+    where
+        T: Scalar
+    {
+        let ( offset, deviation ) = self.deviations( first, second );
+        let spatial = Violation::between( &offset, &self.bound( Quantity::Spatial, &offset ), &self.constraint );
+        let angular = Violation::between( &deviation, &self.bound( Quantity::Angular, &deviation ), &self.angular_constraint );
+        spatial.into_iter().map( |violation| LinkViolation { quantity: Quantity::Spatial, violation } )
+            .chain( angular.into_iter().map( |violation| LinkViolation { quantity: Quantity::Angular, violation } ) )
+            .collect()
+    }
+
+    /// Offset or deviation nearest to `value` that the ranges and then the shape allow.
+    pub(crate) fn bound( &self, quantity: Quantity, value: &Vector<T, DIM> ) -> Vector<T, DIM>
+    where
+        T: Scalar
+    {
+        let ( constraint, shape ) = match quantity {
+            Quantity::Spatial => ( &self.constraint, &self.shape ),
+            Quantity::Angular => ( &self.angular_constraint, &self.angular_shape )
+        };
+        let mut bounded = *value;
+        constraint.constrain( &mut bounded );
+        match shape {
+            Some( shape ) => shape.project( &bounded ),
+            None => bounded
+        }
+    }
+
+    fn deviations( &self, first: &Pose<T, DIM>, second: &Pose<T, DIM> ) -> ( Vector<T, DIM>, Vector<T, DIM> )
+    where
+        T: Scalar
+    {
+        let inverse = Vector::default() - *first.rotation();
+        let relative_position = rotation::rotate( &inverse, &( *second.position() - *first.position() ) );
+        let relative_rotation = rotation::compose( second.rotation(), &inverse );
+        let nominal = Vector::default() - *self.transform.rotation();
+        ( relative_position - *self.transform.position(), rotation::compose( &relative_rotation, &nominal ) )
+    }
+
+    /// Moves the second joint back onto the nearest pose the link allows relative to the first and
+    /// removes the parts of its derivatives, relative to the first, that leave along the corrections.
+    pub fn constrain<const ORD: usize>( &self, joint1: &Joint<T, DIM, ORD>, joint2: &mut Joint<T, DIM, ORD> )
+    where
+        T: Scalar,
+        [Constraint<T, DIM>; (ORD + 1) * 2]: Default,
         [(); ORD + 1]:,
-        [(); (ORD + 1) * 2]: Default
+        [(); (ORD + 1) * 2]:
     {
-        // Transform the position of joint2 into the coordinate system of joint1
-        let relative_position = *joint2.position() - *joint1.position();
-        let rotation_matrix = joint1.rotation().to_rotation_matrix(); // Assuming you have a method to get the rotation matrix
-        let transformed_position = rotation_matrix * relative_position;
-
-        // Create a mutable copy of the transformed position
-        let mut constrained_position = transformed_position;
-
-        // Apply the constraint to the transformed position
-        self.constraint.constrain(&mut constrained_position);
-
-        // If the position was clamped, adjust joint2's position
-        if constrained_position != transformed_position {
-            // Transform the constrained position back to the original coordinate system
-            let corrected_position = joint1.position() + rotation_matrix.transpose() * constrained_position;
-            *joint2.position_mut() = corrected_position;
+        let first = Pose::new( *joint1.position(), *joint1.rotation() );
+        let nearest = self.nearest( &first, &Pose::new( *joint2.position(), *joint2.rotation() ) );
+        let correction = *nearest.position() - *joint2.position();
+        let angular_correction = rotation::compose( &( Vector::default() - *joint2.rotation() ), nearest.rotation() );
+        *joint2.position_mut() = *nearest.position();
+        *joint2.rotation_mut() = *nearest.rotation();
+        for order in 1..=ORD {
+            joint2.spatial[ order ] = hold( &correction, &joint1.spatial[ order ], &joint2.spatial[ order ] );
+            joint2.angular[ order ] = hold( &angular_correction, &joint1.angular[ order ], &joint2.angular[ order ] );
         }
     }
-    */
+}
+
+/// `value` without the part of its difference from `reference` that points against `correction`.
+fn hold<T, const DIM: usize>( correction: &Vector<T, DIM>, reference: &Vector<T, DIM>, value: &Vector<T, DIM> ) -> Vector<T, DIM>
+where
+    T: Scalar
+{
+    let length = rotation::norm( correction );
+    if length <= T::epsilon() {
+        return *value;
+    }
+    let direction = *correction / length;
+    let outward = rotation::dot( &direction, &( *value - *reference ) );
+    if outward < T::zero() { *value - direction * outward } else { *value }
 }
 
 pub type Link1D<T> = Link<T, 1>;
@@ -1,8 +1,11 @@
 // Copyright 2024 Bewusstsein Labs
 
-use std::fmt::Debug;
-use std::ops::{AddAssign, Mul};
+use std::{
+    fmt::Debug,
+    collections::{ BTreeMap, BTreeSet, VecDeque }
+};
 
+use linear_algebra::vector::Vector;
 use graphs::{
     graph::GraphTraits,
     undirected_graph::UnGraph
@@ -10,9 +13,16 @@ use graphs::{
 use const_expr_bounds::{ Assert, IsTrue };
 
 use crate::{
-    joint::Joint,
-    link::Link,
-    constraint::Constraint
+    scalar::Scalar,
+    joint::{ Joint, JointViolation, Quantity },
+    link::{ Link, LinkViolation },
+    constraint::Constraint,
+    integrator::{ Integrator, DormandPrince, AdaptiveReport },
+    force::ForceField,
+    kinematics::{ Pose, SpanningTree },
+    dynamics::Multibody,
+    loops::ClosedLoops,
+    rotation
 };
 
 #[derive(Debug)]
@@ -20,17 +30,32 @@ pub enum Error {
     FailedToAddJoint,
     FailedToAddLink,
     FailedToRemoveJoint,
-    FailedToRemoveLink
+    FailedToRemoveLink,
+    JointNotFound,
+    LinkNotFound,
+    SingularInertia,
+    StepSizeUnderflow,
+    ForceFieldsUnsupported,
+    MultibodyUnsupported,
+    LoopNotClosed
 }
 
 #[derive( Default, Debug )]
-pub struct Linkage<I, T, const DIM: usize, const ORD: usize>( UnGraph<I, Joint<T, DIM, ORD>, Link<T, DIM>> )
+pub struct Linkage<I, T, const DIM: usize, const ORD: usize>
 where
     I: 'static + Default + Debug,
     T: 'static + Default + Copy + Debug,
     [Constraint<T, DIM>; (ORD + 1) * 2]: Default,
     [(); ORD + 1]:,
-    [(); (ORD + 1) * 2]:;
+    [(); (ORD + 1) * 2]:
+{
+    graph: UnGraph<I, Joint<T, DIM, ORD>, Link<T, DIM>>,
+    links: Vec<( I, I )>,
+    fields: Vec<ForceField<I, T, DIM, ORD>>,
+    multibody: Option<Multibody<I, T, DIM>>,
+    closed_loops: Option<ClosedLoops<I, T, DIM>>,
+    time: T
+}
 
 impl <I, T, const DIM: usize, const ORD: usize> Linkage<I, T, DIM, ORD>
 where
@@ -41,194 +66,556 @@ where
     [(); (ORD + 1) * 2]:
 {
     pub fn new() -> Self {
-        Self( UnGraph::<I, Joint<T, DIM, ORD>, Link<T, DIM>>::new() )
+        Self {
+            graph: UnGraph::<I, Joint<T, DIM, ORD>, Link<T, DIM>>::new(),
+            links: Vec::new(),
+            fields: Vec::new(),
+            multibody: None,
+            closed_loops: None,
+            time: T::default()
+        }
     }
 
-    pub fn add_joint( &mut self, id: I, joint: Joint<T, DIM, ORD> ) -> Result<(), Error> {
-        self.0.add_node( id, joint ).map_err( |_| Error::FailedToAddJoint )
+    pub fn time( &self ) -> &T {
+        &self.time
+    }
+
+    /// Advances the linkage time and the clock of every joint with it.
+    pub(crate) fn advance_time( &mut self, time_step: T )
+    where
+        T: Scalar
+    {
+        self.time += time_step;
+        for node in self.graph.nodes_mut().iter_mut() {
+            node.1.data_mut().set_time( self.time );
+        }
+    }
+
+    /// Makes `update` advance the tree from the given root with multibody dynamics rather than stepping
+    /// each joint on its own; `None` restores independent stepping.
+    pub fn set_multibody( &mut self, multibody: Option<Multibody<I, T, DIM>> ) {
+        self.multibody = multibody;
+    }
+
+    pub fn multibody( &self ) -> Option<&Multibody<I, T, DIM>> {
+        self.multibody.as_ref()
+    }
+
+    /// Makes stepping re-close the loops of the linkage after every step; `None` leaves cut links to
+    /// `constrain_links`.
+    pub fn set_closed_loops( &mut self, closed_loops: Option<ClosedLoops<I, T, DIM>> ) {
+        self.closed_loops = closed_loops;
+    }
+
+    pub fn closed_loops( &self ) -> Option<&ClosedLoops<I, T, DIM>> {
+        self.closed_loops.as_ref()
+    }
+
+    pub fn add_force_field( &mut self, field: ForceField<I, T, DIM, ORD> ) {
+        self.fields.push( field );
+    }
+
+    pub fn force_fields( &self ) -> &[ForceField<I, T, DIM, ORD>] {
+        &self.fields
+    }
+
+    pub fn clear_force_fields( &mut self ) {
+        self.fields.clear();
+    }
+
+    pub fn add_joint( &mut self, id: I, mut joint: Joint<T, DIM, ORD> ) -> Result<(), Error>
+    where
+        T: PartialOrd
+    {
+        joint.set_time( self.time );
+        self.graph.add_node( id, joint ).map_err( |_| Error::FailedToAddJoint )
     }
 
     pub fn add_link( &mut self, nodeid1: I, nodeid2: I, link: Link<T, DIM> ) -> Result<(), Error> {
-        self.0.add_edge( nodeid1, nodeid2, link ).map_err( |_| Error::FailedToAddLink )
+        self.graph.add_edge( nodeid1, nodeid2, link ).map_err( |_| Error::FailedToAddLink )?;
+        self.links.push( ( nodeid1, nodeid2 ) );
+        Ok( () )
     }
 
     pub fn get_joint( &self, id: I ) -> Option<&Joint<T, DIM, ORD>> {
-        self.0.get_node( id )
+        self.graph.get_node( id )
     }
 
     pub fn get_joint_mut( &mut self, id: I ) -> Option<&mut Joint<T, DIM, ORD>> {
-        self.0.get_node_mut( id )
+        self.graph.get_node_mut( id )
     }
 
     pub fn get_link( &self, nodeid1: I, nodeid2: I ) -> Option<&Link<T, DIM>> {
-        self.0.get_edge( nodeid1, nodeid2 )
+        self.graph.get_edge( nodeid1, nodeid2 )
     }
 
     pub fn get_link_mut( &mut self, nodeid1: I, nodeid2: I ) -> Option<&mut Link<T, DIM>> {
-        self.0.get_edge_mut( nodeid1, nodeid2 )
+        self.graph.get_edge_mut( nodeid1, nodeid2 )
     }
 
     pub fn remove_joint( &mut self, id: I ) -> Result<Joint<T, DIM, ORD>, Error> {
-        self.0.remove_node( id ).map( |data| data.data().to_owned() ).map_err( |_| Error::FailedToRemoveJoint )
+        let joint = self.graph.remove_node( id ).map( |data| data.data().to_owned() ).map_err( |_| Error::FailedToRemoveJoint )?;
+        self.links.retain( |( a, b )| *a != id && *b != id );
+        Ok( joint )
     }
 
     pub fn remove_link( &mut self, nodeid1: I, nodeid2: I ) -> Result<Link<T, DIM>, Error> {
-        self.0.remove_edge( nodeid1, nodeid2 ).map_err( |_| Error::FailedToRemoveLink )
+        let link = self.graph.remove_edge( nodeid1, nodeid2 ).map_err( |_| Error::FailedToRemoveLink )?;
+        self.links.retain( |link| *link != ( nodeid1, nodeid2 ) && *link != ( nodeid2, nodeid1 ) );
+        Ok( link )
+    }
+
+    pub fn links( &self ) -> &[( I, I )] {
+        &self.links
+    }
+
+    pub fn joint_ids( &self ) -> Vec<I> {
+        self.graph.nodes().iter().map( |node| *node.0 ).collect()
+    }
+
+    pub fn neighbours( &self, id: I ) -> Vec<I> {
+        self.links.iter().filter_map( |( a, b )| {
+            if *a == id { Some( *b ) } else if *b == id { Some( *a ) } else { None }
+        } ).collect()
+    }
+
+    /// Breadth first spanning tree of the joints connected to `root`.
+    pub fn spanning_tree( &self, root: I ) -> Result<SpanningTree<I>, Error> {
+        if self.get_joint( root ).is_none() {
+            return Err( Error::JointNotFound );
+        }
+        let mut order = Vec::new();
+        let mut parents = BTreeMap::new();
+        let mut visited = BTreeSet::from([ root ]);
+        let mut tree_links = BTreeSet::new();
+        let mut queue = VecDeque::from([ root ]);
+        while let Some( id ) = queue.pop_front() {
+            order.push( id );
+            for neighbour in self.neighbours( id ) {
+                if visited.insert( neighbour ) {
+                    parents.insert( neighbour, id );
+                    tree_links.insert( ( id, neighbour ) );
+                    queue.push_back( neighbour );
+                }
+            }
+        }
+        let cut_links = self.links.iter()
+            .filter( |( a, b )| visited.contains( a ) && !tree_links.contains( &( *a, *b ) ) && !tree_links.contains( &( *b, *a ) ) )
+            .copied()
+            .collect();
+        Ok( SpanningTree::new( root, order, parents, cut_links ) )
+    }
+
+    /// Transform of the link between `from` and `to`, seen from `from`.
+    pub fn link_transform( &self, from: I, to: I ) -> Result<Pose<T, DIM>, Error>
+    where
+        T: Scalar
+    {
+        if self.links.contains( &( from, to ) ) {
+            self.get_link( from, to ).map( |link| *link.transform() ).ok_or( Error::LinkNotFound )
+        } else if self.links.contains( &( to, from ) ) {
+            self.get_link( to, from ).map( |link| link.transform().inverse() ).ok_or( Error::LinkNotFound )
+        } else {
+            Err( Error::LinkNotFound )
+        }
     }
 
     pub fn constrain_joint_positions( &mut self )
     where
-        T: PartialOrd
+        T: Scalar
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain_position();
         }
     }
 
     pub fn constrain_joint_rotations( &mut self )
     where
-        T: PartialOrd
+        T: Scalar
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain_rotation();
         }
     }
 
     pub fn constrain_joint_spatial_velocities( &mut self )
     where
-        T: PartialOrd,
+        T: Scalar,
         Assert<{ ORD >= 1 }>: IsTrue
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain_spatial_velocity();
         }
     }
 
     pub fn constrain_joint_angular_velocities( &mut self )
     where
-        T: PartialOrd,
+        T: Scalar,
         Assert<{ ORD >= 1 }>: IsTrue
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain_angular_velocity();
         }
     }
 
     pub fn constrain_joint_spatial_accelerations( &mut self )
     where
-        T: PartialOrd,
+        T: Scalar,
         Assert<{ ORD >= 2 }>: IsTrue
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain_spatial_acceleration();
         }
     }
 
     pub fn constrain_joint_angular_accelerations( &mut self )
     where
-        T: PartialOrd,
+        T: Scalar,
         Assert<{ ORD >= 2 }>: IsTrue
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain_angular_acceleration();
         }
     }
 
     pub fn constrain_joint_spatial_jerks( &mut self )
     where
-        T: PartialOrd,
+        T: Scalar,
         Assert<{ ORD >= 3 }>: IsTrue
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain_spatial_jerk();
         }
     }
 
     pub fn constrain_joint_angular_jerks( &mut self )
     where
-        T: PartialOrd,
+        T: Scalar,
         Assert<{ ORD >= 3 }>: IsTrue
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain_angular_jerk();
         }
     }
 
     pub fn constrain_joint_spatial_snaps( &mut self )
     where
-        T: PartialOrd,
+        T: Scalar,
         Assert<{ ORD >= 4 }>: IsTrue
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain_spatial_snap();
         }
     }
 
     pub fn constrain_joint_angular_snaps( &mut self )
     where
-        T: PartialOrd,
+        T: Scalar,
         Assert<{ ORD >= 4 }>: IsTrue
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain_angular_snap();
         }
     }
 
     pub fn constrain_joint_spatial_crackles( &mut self )
     where
-        T: PartialOrd,
+        T: Scalar,
         Assert<{ ORD >= 5 }>: IsTrue
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain_spatial_crackle();
         }
     }
 
     pub fn constrain_joint_angular_crackles( &mut self )
     where
-        T: PartialOrd,
+        T: Scalar,
         Assert<{ ORD >= 5 }>: IsTrue
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain_angular_crackle();
         }
     }
 
     pub fn constrain_joint_spatial_pops( &mut self )
     where
-        T: PartialOrd,
+        T: Scalar,
         Assert<{ ORD >= 6 }>: IsTrue
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain_spatial_pop();
         }
     }
 
     pub fn constrain_joint_angular_pops( &mut self )
     where
-        T: PartialOrd,
+        T: Scalar,
         Assert<{ ORD >= 6 }>: IsTrue
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain_angular_pop();
         }
     }
 
     pub fn constrain_joints( &mut self )
     where
-        T: PartialOrd
+        T: Scalar
     {
-        for node in self.0.nodes_mut().iter_mut() {
+        for node in self.graph.nodes_mut().iter_mut() {
             node.1.data_mut().constrain();
         }
     }
 
-    pub fn update( &mut self, time_step: T )
+    /// Violations of every joint outside its constraints.
+    pub fn check_joints( &self ) -> BTreeMap<I, Vec<JointViolation<T>>>
+    where
+        T: Scalar
+    {
+        self.graph.nodes().iter()
+            .map( |node| ( *node.0, node.1.data().check() ) )
+            .filter( |( _, violations )| !violations.is_empty() )
+            .collect()
+    }
+
+    /// Applies `constrain_joints`, returning the violations it corrected.
+    pub fn enforce_joints( &mut self ) -> BTreeMap<I, Vec<JointViolation<T>>>
+    where
+        T: Scalar
+    {
+        let violations = self.check_joints();
+        self.constrain_joints();
+        violations
+    }
+
+    /// Violations of every link outside its constraints.
+    pub fn check_links( &self ) -> BTreeMap<( I, I ), Vec<LinkViolation<T>>>
+    where
+        T: Scalar
+    {
+        self.links.iter().filter_map( |( first, second )| {
+            let joint1 = self.graph.get_node( *first )?;
+            let joint2 = self.graph.get_node( *second )?;
+            let link = self.graph.get_edge( *first, *second )?;
+            let violations = link.check( &Pose::new( *joint1.position(), *joint1.rotation() ), &Pose::new( *joint2.position(), *joint2.rotation() ) );
+            ( !violations.is_empty() ).then_some( ( ( *first, *second ), violations ) )
+        } ).collect()
+    }
+
+    /// Applies `constrain_links`, returning the violations found before it.
+    pub fn enforce_links( &mut self ) -> BTreeMap<( I, I ), Vec<LinkViolation<T>>>
+    where
+        T: Scalar
+    {
+        let violations = self.check_links();
+        self.constrain_links();
+        violations
+    }
+
+    /// Moves the second joint of every link onto the nearest pose it allows, in the order added.
+    pub fn constrain_links( &mut self )
+    where
+        T: Scalar
+    {
+        for ( first, second ) in self.links.clone() {
+            let Some( joint1 ) = self.graph.get_node( first ).cloned() else { continue };
+            let Some( link ) = self.graph.get_edge( first, second ).cloned() else { continue };
+            if let Some( joint2 ) = self.graph.get_node_mut( second ) {
+                link.constrain( &joint1, joint2 );
+            }
+        }
+    }
+
+    /// Steps every joint on its own, or with `update_multibody` when a multibody is set. Force fields
+    /// make independent stepping take the `update_dynamics` step, and are rejected with `ORD < 2`.
+    /// Accumulated forces are cleared either way.
+    pub fn update<N>( &mut self, integrator: &N, time_step: T ) -> Result<(), Error>
+    where
+        T: Scalar,
+        N: Integrator<T>
+    {
+        if let Some( multibody ) = self.multibody {
+            return self.update_multibody( &multibody, integrator, time_step );
+        }
+        if !self.fields.is_empty() {
+            if ORD < 2 {
+                self.clear_forces();
+                return Err( Error::ForceFieldsUnsupported );
+            }
+            return self.step_dynamics( integrator, time_step );
+        }
+        for node in self.graph.nodes_mut().iter_mut() {
+            let joint = node.1.data_mut();
+            joint.update( integrator, time_step );
+            joint.constrain_rigid();
+        }
+        self.constrain_links();
+        let closed = self.reclose_loops();
+        self.advance_time( time_step );
+        closed
+    }
+
+    pub fn apply_force_fields( &mut self )
     where
-        T: 'static + Default + Copy + Debug + PartialEq + PartialOrd + Mul<Output = T> + AddAssign
+        T: Scalar
     {
-        for node in self.0.nodes_mut().iter_mut() {
-            node.1.data_mut().update( time_step );
+        let time = self.time;
+        for node in self.graph.nodes_mut().iter_mut() {
+            let id = *node.0;
+            let joint = node.1.data_mut();
+            for field in self.fields.iter() {
+                field.apply( &id, joint, time );
+            }
+        }
+    }
+
+    /// Newton-Euler step of every joint under the force fields, then re-closes the loops when closed
+    /// loops are set. Fails with `Error::SingularInertia` before any joint moves.
+    pub fn update_dynamics<N>( &mut self, integrator: &N, time_step: T ) -> Result<(), Error>
+    where
+        T: Scalar,
+        N: Integrator<T>,
+        Assert<{ ORD >= 2 }>: IsTrue
+    {
+        self.step_dynamics( integrator, time_step )
+    }
+
+    fn step_dynamics<N>( &mut self, integrator: &N, time_step: T ) -> Result<(), Error>
+    where
+        T: Scalar,
+        N: Integrator<T>
+    {
+        if self.graph.nodes().iter().any( |node| node.1.data().inverse_world_inertia().is_none() ) {
+            self.clear_forces();
+            return Err( Error::SingularInertia );
+        }
+        self.apply_force_fields();
+        for node in self.graph.nodes_mut().iter_mut() {
+            node.1.data_mut().step_dynamics( integrator, time_step )?;
+        }
+        self.constrain_links();
+        let closed = self.reclose_loops();
+        self.advance_time( time_step );
+        closed
+    }
+
+    /// Acceleration of `joint` at a stage of a step starting at `time`, the angular stack holding the
+    /// rotation increment: its wrench, the force fields and its soft limit corrections.
+    fn stage_acceleration( &self, id: I, joint: &Joint<T, DIM, ORD>, spatial: &[Vector<T, DIM>], angular: &[Vector<T, DIM>], time: T ) -> ( Vector<T, DIM>, Vector<T, DIM> )
+    where
+        T: Scalar
+    {
+        let mut body = ( **joint ).clone();
+        let rotation = body.angular[ 0 ];
+        body.spatial[ ..spatial.len() ].copy_from_slice( spatial );
+        body.angular[ ..angular.len() ].copy_from_slice( angular );
+        body.angular[ 0 ] = rotation::compose( &rotation, &angular[ 0 ] );
+        for field in self.fields.iter() {
+            field.apply( &id, &mut body, time );
+        }
+        let velocity = body.angular[ 1 ];
+        // Singular inertia is rejected before stepping.
+        let angular = body.angular_acceleration_at( &velocity ).unwrap_or_default();
+        let linear = body.linear_acceleration_at( &velocity, &angular );
+        let position = joint.current_constraint( Quantity::Spatial, 0 ).unwrap_or_default().correction( &body.spatial[ 0 ], &body.spatial[ 1 ] );
+        let rotation = joint.current_constraint( Quantity::Angular, 0 ).unwrap_or_default().correction( &body.angular[ 0 ], &body.angular[ 1 ] );
+        ( linear + position, angular + rotation )
+    }
+
+    /// Advances the linkage by `duration` with adaptive Dormand-Prince steps, with Newton-Euler
+    /// accelerations when `ORD >= 2` and the highest derivative held otherwise. Accumulated forces act
+    /// over the whole duration. Every joint is stepped in one state with a single error estimate. A
+    /// set multibody is rejected, as the multibody path has no error control. On an error the linkage
+    /// is left at its last accepted step.
+    pub fn advance( &mut self, integrator: &DormandPrince<T>, duration: T ) -> Result<AdaptiveReport<T>, Error>
+    where
+        T: Scalar
+    {
+        if self.multibody.is_some() {
+            return Err( Error::MultibodyUnsupported );
+        }
+        if ORD < 2 && !self.fields.is_empty() {
+            return Err( Error::ForceFieldsUnsupported );
+        }
+        if ORD >= 2 && self.graph.nodes().iter().any( |node| node.1.data().inverse_world_inertia().is_none() ) {
+            self.clear_forces();
+            return Err( Error::SingularInertia );
+        }
+        let levels = ORD.min( 2 );
+        let mut report = AdaptiveReport::default();
+        let mut result = Ok( () );
+        let mut time = T::zero();
+        let mut time_step = *integrator.initial_step();
+        while time < duration {
+            let remaining = duration - time;
+            let last = time_step >= remaining;
+            let step = if last { remaining } else { time_step };
+
+            // Spatial then angular increment stack of every joint, in node order.
+            let length = levels + 1;
+            let ( ids, state, error ) = {
+                let joints: Vec<( I, &Joint<T, DIM, ORD> )> = self.graph.nodes().iter().map( |node| ( *node.0, node.1.data() ) ).collect();
+                let mut start = Vec::new();
+                for ( _, joint ) in joints.iter() {
+                    start.extend_from_slice( &joint.spatial[ ..=levels ] );
+                    start.push( Vector::default() );
+                    start.extend_from_slice( &joint.angular[ 1..=levels ] );
+                }
+                let ( state, error ) = integrator.attempt_stacks( &start, length, step, |state| {
+                    joints.iter().enumerate().flat_map( |( k, ( id, joint ) )| {
+                        let ( spatial, angular ) = state[ 2 * k * length..( 2 * k + 2 ) * length ].split_at( length );
+                        let ( linear, rotational ) = if ORD >= 2 {
+                            self.stage_acceleration( *id, joint, spatial, angular, self.time + time )
+                        } else {
+                            ( spatial[ levels ], angular[ levels ] )
+                        };
+                        [ linear, rotational ]
+                    } ).collect()
+                } );
+                ( joints.iter().map( |( id, _ )| *id ).collect::<Vec<I>>(), state, error )
+            };
+
+            if error <= T::one() {
+                time = if last { duration } else { time + step };
+                for ( id, stacks ) in ids.into_iter().zip( state.chunks( 2 * length ) ) {
+                    let ( spatial, angular ) = stacks.split_at( length );
+                    if let Some( joint ) = self.graph.get_node_mut( id ) {
+                        let ( spatial_before, angular_before ) = ( joint.spatial, joint.angular );
+                        joint.spatial[ ..=levels ].copy_from_slice( spatial );
+                        joint.angular[ ..=levels ].copy_from_slice( angular );
+                        joint.angular[ 0 ] = angular_before[ 0 ];
+                        joint.turn( &angular[ 0 ] );
+                        for i in ( levels + 1 )..=ORD {
+                            joint.spatial[ i ] = ( joint.spatial[ i - 1 ] - spatial_before[ i - 1 ] ) / step;
+                            joint.angular[ i ] = ( joint.angular[ i - 1 ] - angular_before[ i - 1 ] ) / step;
+                        }
+                        joint.set_time( self.time + time );
+                        joint.constrain_rigid();
+                    }
+                }
+                self.constrain_links();
+                report.accept( time, step );
+                if let Err( error ) = self.reclose_loops() {
+                    result = Err( error );
+                    break;
+                }
+            } else {
+                report.reject();
+                if step <= *integrator.min_step() {
+                    result = Err( Error::StepSizeUnderflow );
+                    break;
+                }
+            }
+            time_step = integrator.next_step( step, error ).max( *integrator.min_step() );
+        }
+        self.clear_forces();
+        self.advance_time( time );
+        result.map( |_| report )
+    }
+
+    pub(crate) fn clear_forces( &mut self ) {
+        for node in self.graph.nodes_mut().iter_mut() {
+            node.1.data_mut().clear_forces();
         }
     }
 }
@@ -245,7 +632,10 @@ mod tests {
     use crate::{
         body::Body3D,
         joint::Joint3D,
-        constraint::{ Range, Constraint3D }
+        constraint::{ Range, Limit, Constraint3D },
+        shape::ConstraintShape,
+        force::Wrench,
+        integrator::SemiImplicitEuler
     };
 
     #[test]
@@ -274,4 +664,241 @@ mod tests {
         linkage.constrain_joints();
         dbg!( &linkage );
     }
+
+    #[test]
+    fn check_joints_test() {
+        let bound = Constraint3D::new([ Some( Range::new( -1.0, 1.0 ) ), None, None ]);
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        for ( id, x ) in [ ( 0, 0.5 ), ( 1, 2.0 ) ] {
+            linkage.add_joint( id, Joint3D::new( Body3D::new( 1.0, [ Vector3::from([ x, 0.0, 0.0 ]), Vector3::default() ], [ Vector3::default(); 2 ] ), [ bound; 4 ] ) ).unwrap();
+        }
+        let report = linkage.check_joints();
+        assert_eq!( report.keys().copied().collect::<Vec<_>>(), vec![ 1 ] );
+        assert_eq!( *report[ &1 ][ 0 ].violation().magnitude(), 1.0 );
+        assert_eq!( linkage.enforce_joints(), report );
+        assert!( linkage.check_joints().is_empty() );
+        assert_eq!( linkage.get_joint( 1 ).unwrap().position()[ 0 ], 1.0 );
+    }
+
+    #[test]
+    fn check_links_test() {
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        for ( id, x ) in [ ( 0, 0.0 ), ( 1, 2.0 ) ] {
+            linkage.add_joint( id, Joint3D::new( Body3D::new( 1.0, [ Vector3::from([ x, 0.0, 0.0 ]), Vector3::default() ], [ Vector3::default(); 2 ] ), [ Constraint3D::default(); 4 ] ) ).unwrap();
+        }
+        let slack = Constraint3D::new([ Some( Range::new( -0.5, 0.5 ).with_limit( Limit::Soft { stiffness: 1.0, damping: 1.0 } ) ), None, None ]);
+        let offset = Pose::new( Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::default() );
+        linkage.add_link( 0, 1, Link::new( 1.0, slack ).with_transform( offset ) ).unwrap();
+        let report = linkage.check_links();
+        assert_eq!( report.keys().copied().collect::<Vec<_>>(), vec![ ( 0, 1 ) ] );
+        let violation = report[ &( 0, 1 ) ][ 0 ];
+        assert_eq!( violation.quantity(), Quantity::Spatial );
+        assert_eq!( ( violation.violation().axis(), *violation.violation().bound(), *violation.violation().magnitude() ), ( 0, 0.5, 0.5 ) );
+        assert!( violation.violation().soft() );
+        assert_eq!( linkage.enforce_links(), report );
+        assert!( linkage.check_links().is_empty() );
+        assert_eq!( linkage.get_joint( 1 ).unwrap().position()[ 0 ], 1.5 );
+    }
+
+    #[test]
+    fn link_constraint_test() {
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        let velocities = [ Vector3::default(), Vector3::from([ 0.0, 1.0, 0.0 ]) ];
+        for ( id, velocity ) in velocities.iter().enumerate() {
+            linkage.add_joint( id,
+                Joint3D::new(
+                    Body3D::new( 1.0, [ Vector3::from([ id as f64, 0.0, 0.0 ]), *velocity ], [ Vector3::default(), Vector3::from([ 0.0, 0.0, 1.0 ]) ] ),
+                    [ Constraint3D::default(); 4 ]
+                )
+            ).unwrap();
+        }
+        let offset = Pose::new( Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::default() );
+        let slack = Constraint3D::new([ Some( Range::new( -0.5, 0.5 ) ), Some( Range::new( 0.0, 0.0 ) ), Some( Range::new( 0.0, 0.0 ) ) ]);
+        linkage.add_link( 0, 1, Link::rigid( 1.0, offset ) ).unwrap();
+        linkage.update( &SemiImplicitEuler, 0.5 ).unwrap();
+        let ( joint0, joint1 ) = ( linkage.get_joint( 0 ).unwrap(), linkage.get_joint( 1 ).unwrap() );
+        let expected = *joint0.position() + rotation::rotate( joint0.rotation(), &Vector3::from([ 1.0, 0.0, 0.0 ]) );
+        for i in 0..3 {
+            assert!( ( joint1.position()[ i ] - expected[ i ] ).abs() < 1e-12 );
+            assert!( ( joint1.rotation()[ i ] - joint0.rotation()[ i ] ).abs() < 1e-12 );
+        }
+
+        *linkage.get_link_mut( 0, 1 ).unwrap() = Link::new( 1.0, slack ).with_transform( offset );
+        *linkage.get_joint_mut( 1 ).unwrap().position_mut() = *linkage.get_joint( 0 ).unwrap().position() + Vector3::from([ 0.0, 0.0, 3.0 ]);
+        linkage.constrain_links();
+        let joint0 = linkage.get_joint( 0 ).unwrap();
+        let relative = rotation::rotate( &( Vector3::default() - *joint0.rotation() ), &( *linkage.get_joint( 1 ).unwrap().position() - *joint0.position() ) );
+        assert!( ( relative[ 0 ] - 0.5 ).abs() < 1e-12 );
+        assert!( relative[ 1 ].abs() < 1e-12 && relative[ 2 ].abs() < 1e-12 );
+
+        // A shape projects the offset once the ranges are applied.
+        *linkage.get_link_mut( 0, 1 ).unwrap() = Link::new( 1.0, slack ).with_transform( offset ).with_shape( ConstraintShape::magnitude( 0.25 ) );
+        *linkage.get_joint_mut( 1 ).unwrap().position_mut() = *linkage.get_joint( 0 ).unwrap().position() + Vector3::from([ 0.0, 0.0, 3.0 ]);
+        assert_eq!( linkage.check_links().len(), 1 );
+        linkage.constrain_links();
+        let joint0 = linkage.get_joint( 0 ).unwrap();
+        let relative = rotation::rotate( &( Vector3::default() - *joint0.rotation() ), &( *linkage.get_joint( 1 ).unwrap().position() - *joint0.position() ) );
+        assert!( ( relative[ 0 ] - 0.75 ).abs() < 1e-12 );
+
+        // Velocity leaving along the correction is removed, velocity returning into the range is kept.
+        for ( velocity, expected ) in [ ( [ 1.0, 1.0, 0.0 ], [ 0.0, 1.0, 0.0 ] ), ( [ -1.0, 1.0, 0.0 ], [ -1.0, 1.0, 0.0 ] ) ] {
+            let mut linkage = Linkage3D::<usize, f64, 1>::new();
+            for ( id, spatial ) in [ [ Vector3::default(); 2 ], [ Vector3::from([ 3.0, 0.0, 0.0 ]), Vector3::from( velocity ) ] ].into_iter().enumerate() {
+                linkage.add_joint( id, Joint3D::new( Body3D::new( 1.0, spatial, [ Vector3::default(); 2 ] ), [ Constraint3D::default(); 4 ] ) ).unwrap();
+            }
+            linkage.add_link( 0, 1, Link::new( 1.0, slack ).with_transform( offset ) ).unwrap();
+            linkage.constrain_links();
+            let joint1 = linkage.get_joint( 1 ).unwrap();
+            assert_eq!( joint1.position()[ 0 ], 1.5 );
+            assert_eq!( joint1.spatial_velocity(), &Vector3::from( expected ) );
+        }
+    }
+
+    #[test]
+    fn advance_test() {
+        let mut linkage = Linkage3D::<usize, f64, 2>::new();
+        linkage.add_joint( 0,
+            Joint3D::new(
+                Body3D::new( 1.0, [ Vector3::default(), Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::default() ], [ Vector3::default(); 3 ] ),
+                [ Constraint3D::default(); 6 ]
+            )
+        ).unwrap();
+        linkage.add_force_field( ForceField::Gravity { acceleration: Vector3::from([ 0.0, 0.0, -2.0 ]) } );
+        let integrator = DormandPrince::new( 1e-9, 1e-9, 1e-6, 0.25 );
+        let report = linkage.advance( &integrator, 1.0 ).unwrap();
+        assert_eq!( *report.time(), 1.0 );
+        assert_eq!( report.accepted(), 4 );
+        let position = *linkage.get_joint( 0 ).unwrap().position();
+        assert!( ( position[ 0 ] - 1.0 ).abs() < 1e-12 );
+        assert!( ( position[ 2 ] + 1.0 ).abs() < 1e-12 );
+
+        // A spring is evaluated at every stage, so the step adapts to its period.
+        let mut linkage = Linkage3D::<usize, f64, 2>::new();
+        linkage.add_joint( 0,
+            Joint3D::new(
+                Body3D::new( 1.0, [ Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::default(), Vector3::default() ], [ Vector3::default(); 3 ] ),
+                [ Constraint3D::default(); 6 ]
+            )
+        ).unwrap();
+        // A free joint shares the steps of the spring.
+        linkage.add_joint( 1,
+            Joint3D::new(
+                Body3D::new( 1.0, [ Vector3::default(), Vector3::from([ 0.0, 1.0, 0.0 ]), Vector3::default() ], [ Vector3::default(); 3 ] ),
+                [ Constraint3D::default(); 6 ]
+            )
+        ).unwrap();
+        linkage.add_force_field( ForceField::Spring { joint: 0, anchor: Vector3::default(), stiffness: 4.0, damping: 0.0, rest_length: 0.0 } );
+        let report = linkage.advance( &DormandPrince::new( 1e-10, 1e-10, 1e-6, 1.0 ), 1.0 ).unwrap();
+        assert!( report.rejected() > 0 && report.accepted() > 1 );
+        let joint = linkage.get_joint( 0 ).unwrap();
+        assert!( ( joint.position()[ 0 ] - 2.0f64.cos() ).abs() < 1e-6 );
+        assert!( ( joint.spatial_velocity()[ 0 ] + 2.0 * 2.0f64.sin() ).abs() < 1e-6 );
+        let free = linkage.get_joint( 1 ).unwrap();
+        assert!( ( free.position()[ 1 ] - 1.0 ).abs() < 1e-12 );
+        assert_eq!( free.time(), joint.time() );
+
+        linkage.set_multibody( Some( Multibody::new( 0, Pose::identity() ) ) );
+        assert!( matches!( linkage.advance( &integrator, 1.0 ), Err( Error::MultibodyUnsupported ) ) );
+    }
+
+    #[test]
+    fn soft_limit_test() {
+        let wall = Constraint3D::new([ Some( Range::new( -1.0, 1.0 ).with_limit( Limit::Soft { stiffness: 100.0, damping: 20.0 } ) ), None, None ]);
+        let mut linkage = Linkage3D::<usize, f64, 2>::new();
+        linkage.add_joint( 0,
+            Joint3D::new(
+                Body3D::new( 1.0, [ Vector3::default(), Vector3::from([ 2.0, 0.0, 0.0 ]), Vector3::default() ], [ Vector3::default(); 3 ] ),
+                [ wall, Constraint3D::default(), Constraint3D::default(), Constraint3D::default(), Constraint3D::default(), Constraint3D::default() ]
+            )
+        ).unwrap();
+        let mut furthest: f64 = 0.0;
+        for _ in 0..3000 {
+            linkage.update( &SemiImplicitEuler, 0.001 ).unwrap();
+            furthest = furthest.max( linkage.get_joint( 0 ).unwrap().position()[ 0 ] );
+        }
+        let joint = linkage.get_joint( 0 ).unwrap();
+        assert!( furthest > 1.0 );
+        assert!( ( joint.position()[ 0 ] - 1.0 ).abs() < 1e-2 );
+        assert!( joint.spatial_velocity()[ 0 ].abs() < 1e-2 );
+        // The correction only acts for the step, leaving the held acceleration as it was.
+        assert_eq!( *joint.spatial_acceleration(), Vector3::default() );
+    }
+
+    #[test]
+    fn stop_test() {
+        let floor = Constraint3D::new([ None, None, Some( Range::new( 0.0, 10.0 ).with_limit( Limit::Stop { restitution: 0.5 } ) ) ]);
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        linkage.add_joint( 0,
+            Joint3D::new(
+                Body3D::new( 1.0, [ Vector3::from([ 0.0, 0.0, 0.05 ]), Vector3::from([ 0.0, 0.0, -1.0 ]) ], [ Vector3::default(); 2 ] ),
+                [ floor, Constraint3D::default(), Constraint3D::default(), Constraint3D::default() ]
+            )
+        ).unwrap();
+        linkage.update( &SemiImplicitEuler, 0.1 ).unwrap();
+        let joint = linkage.get_joint( 0 ).unwrap();
+        assert_eq!( joint.position()[ 2 ], 0.0 );
+        assert!( ( joint.spatial_velocity()[ 2 ] - 0.5 ).abs() < 1e-12 );
+    }
+
+    #[test]
+    fn step_size_underflow_test() {
+        let mut linkage = Linkage3D::<usize, f64, 2>::new();
+        linkage.add_joint( 0,
+            Joint3D::new(
+                Body3D::new( 1.0, [ Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::default(), Vector3::default() ], [ Vector3::default(); 3 ] ),
+                [ Constraint3D::default(); 6 ]
+            )
+        ).unwrap();
+        // Turns stiff halfway through, beyond what the smallest step can resolve.
+        linkage.add_force_field( ForceField::custom( |body: &Body3D<f64, 2>, time| {
+            Wrench::new( if time < 0.5 { Vector3::default() } else { *body.position() * -1e6 }, Vector3::default() )
+        } ) );
+        let integrator = DormandPrince::new( 1e-9, 1e-9, 0.25, 0.25 );
+        assert!( matches!( linkage.advance( &integrator, 1.0 ), Err( Error::StepSizeUnderflow ) ) );
+        assert_eq!( *linkage.time(), 0.5 );
+        let joint = linkage.get_joint( 0 ).unwrap();
+        assert_eq!( *joint.time(), 0.5 );
+        assert_eq!( *joint.position(), Vector3::from([ 1.0, 0.0, 0.0 ]) );
+
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        linkage.add_force_field( ForceField::Gravity { acceleration: Vector3::from([ 0.0, 0.0, -2.0 ]) } );
+        assert!( matches!( linkage.advance( &integrator, 1.0 ), Err( Error::ForceFieldsUnsupported ) ) );
+    }
+
+    #[test]
+    fn force_field_test() {
+        let mut linkage = Linkage3D::<usize, f64, 2>::new();
+        for id in 0..2 {
+            linkage.add_joint( id,
+                Joint3D::new(
+                    Body3D::new( 2.0, [ Vector3::default(); 3 ], [ Vector3::default(); 3 ] ),
+                    [ Constraint3D::default(); 6 ]
+                )
+            ).unwrap();
+        }
+        linkage.add_force_field( ForceField::Gravity { acceleration: Vector3::from([ 0.0, 0.0, -10.0 ]) } );
+        linkage.add_force_field( ForceField::custom( |body: &Body3D<f64, 2>, _| Wrench::new( Vector3::from([ *body.mass(), 0.0, 0.0 ]), Vector3::default() ) ) );
+        linkage.update_dynamics( &SemiImplicitEuler, 0.1 ).unwrap();
+        for id in 0..2 {
+            let joint = linkage.get_joint( id ).unwrap();
+            assert!( ( joint.spatial_velocity()[ 0 ] - 0.1 ).abs() < 1e-12 );
+            assert!( ( joint.spatial_velocity()[ 2 ] + 1.0 ).abs() < 1e-12 );
+            assert_eq!( *joint.force(), Vector3::default() );
+        }
+        assert!( ( *linkage.time() - 0.1 ).abs() < 1e-12 );
+        assert_eq!( linkage.get_joint( 1 ).unwrap().time(), linkage.time() );
+
+        // `update` takes the same step once fields are registered.
+        linkage.update( &SemiImplicitEuler, 0.1 ).unwrap();
+        for id in 0..2 {
+            let joint = linkage.get_joint( id ).unwrap();
+            assert!( ( joint.spatial_velocity()[ 0 ] - 0.2 ).abs() < 1e-12 );
+            assert!( ( joint.spatial_velocity()[ 2 ] + 2.0 ).abs() < 1e-12 );
+        }
+
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        linkage.add_joint( 0, Joint3D::new( Body3D::new( 1.0, [ Vector3::default(); 2 ], [ Vector3::default(); 2 ] ), [ Constraint3D::default(); 4 ] ) ).unwrap();
+        linkage.add_force_field( ForceField::Gravity { acceleration: Vector3::from([ 0.0, 0.0, -10.0 ]) } );
+        assert!( matches!( linkage.update( &SemiImplicitEuler, 0.1 ), Err( Error::ForceFieldsUnsupported ) ) );
+    }
 }
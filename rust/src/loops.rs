@@ -0,0 +1,401 @@
+// Copyright 2024 Bewusstsein Labs
+
+//! Closed kinematic loops. The breadth first spanning tree from a root joint decides which links are
+//! cut; every cut link becomes a loop closure constraint on the tree's joint coordinates, which are
+//! solved by damped Newton iteration on the constraint manifold.
+
+use std::{
+    fmt::Debug,
+    collections::BTreeMap
+};
+
+use linear_algebra::vector::Vector;
+
+use crate::{
+    scalar::Scalar,
+    constraint::Constraint,
+    kinematics::{ Pose, Motion },
+    linkage::{ Linkage, Error },
+    inverse_kinematics::{ Target, Solution },
+    dense::{ self, DenseMatrix }
+};
+
+/// Cycle closed by the cut link `cut`, with the tree path between its two joints.
+#[derive( Clone, Default, Debug, PartialEq )]
+pub struct Loop<I> {
+    cut: ( I, I ),
+    joints: Vec<I>
+}
+
+impl<I> Loop<I> {
+    pub fn new( cut: ( I, I ), joints: Vec<I> ) -> Self {
+        Self { cut, joints }
+    }
+
+    pub fn cut( &self ) -> &( I, I ) { &self.cut }
+    /// Joints of the cycle, along the tree from the first joint of the cut link to the second.
+    pub fn joints( &self ) -> &[I] { &self.joints }
+}
+
+#[derive( Clone, Copy, Debug, PartialEq )]
+pub struct LoopClosure<T> {
+    damping: T,
+    tolerance: T,
+    max_iterations: usize
+}
+
+impl<T> LoopClosure<T>
+where
+    T: Scalar
+{
+    /// Levenberg-Marquardt with initial damping `damping`, halved after each improving step and doubled
+    /// after each rejected one.
+    pub fn new( damping: T, tolerance: T, max_iterations: usize ) -> Self {
+        Self { damping, tolerance, max_iterations }
+    }
+
+    pub fn damping( &self ) -> &T { &self.damping }
+    pub fn tolerance( &self ) -> &T { &self.tolerance }
+    pub fn max_iterations( &self ) -> &usize { &self.max_iterations }
+}
+
+impl<T> Default for LoopClosure<T>
+where
+    T: Scalar
+{
+    fn default() -> Self {
+        Self::new( T::from_f64( 1e-3 ), T::from_f64( 1e-9 ), 100 )
+    }
+}
+
+/// Root joint, placement and solver with which stepping re-closes the loops of a linkage.
+#[derive( Clone, Copy, Debug, PartialEq )]
+pub struct ClosedLoops<I, T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    root: I,
+    base: Pose<T, DIM>,
+    solver: LoopClosure<T>
+}
+
+impl<I, T, const DIM: usize> ClosedLoops<I, T, DIM>
+where
+    T: Scalar
+{
+    pub fn new( root: I, base: Pose<T, DIM> ) -> Self {
+        Self { root, base, solver: LoopClosure::default() }
+    }
+
+    pub fn with_solver( mut self, solver: LoopClosure<T> ) -> Self {
+        self.solver = solver;
+        self
+    }
+
+    pub fn root( &self ) -> &I { &self.root }
+    pub fn base( &self ) -> &Pose<T, DIM> { &self.base }
+    pub fn solver( &self ) -> &LoopClosure<T> { &self.solver }
+}
+
+impl <I, T, const DIM: usize, const ORD: usize> Linkage<I, T, DIM, ORD>
+where
+    I: 'static + Default + Copy + Debug + Ord,
+    T: Scalar,
+    [Constraint<T, DIM>; (ORD + 1) * 2]: Default,
+    [(); ORD + 1]:,
+    [(); (ORD + 1) * 2]:
+{
+    /// Independent loops of the joints connected to `root`, one per link cut from its spanning tree.
+    pub fn loops( &self, root: I ) -> Result<Vec<Loop<I>>, Error> {
+        let tree = self.spanning_tree( root )?;
+        tree.cut_links().iter().map( |( first, second )| {
+            let joints = tree.path( first, second ).ok_or( Error::JointNotFound )?;
+            Ok( Loop::new( ( *first, *second ), joints ) )
+        } ).collect()
+    }
+
+    /// Stacked position and rotation errors of every cut link of the tree from `root`, placed at `base`.
+    pub fn loop_residual( &self, root: I, base: Pose<T, DIM> ) -> Result<Vec<T>, Error> {
+        let kinematics = self.forward_kinematics( root, base )?;
+        let mut residual = Vec::new();
+        for ( first, second ) in kinematics.tree().cut_links() {
+            let link = self.get_link( *first, *second ).ok_or( Error::LinkNotFound )?;
+            let pose1 = kinematics.pose( first ).ok_or( Error::JointNotFound )?;
+            let pose2 = kinematics.pose( second ).ok_or( Error::JointNotFound )?;
+            residual.extend( Target::Pose( link.nearest( pose1, pose2 ) ).residual( pose2 ) );
+        }
+        Ok( residual )
+    }
+
+    /// Moves the coordinates of the tree joints from `root`, except those in `fixed`, until every loop
+    /// closes, then projects their rates onto the closed loops.
+    pub fn close_loops( &mut self, solver: &LoopClosure<T>, root: I, base: Pose<T, DIM>, fixed: &[I] ) -> Result<Solution<T>, Error> {
+        let tree = self.spanning_tree( root )?;
+        // Free columns of the moving joints.
+        let mut columns = Vec::new();
+        for id in tree.order().iter().filter( |id| !fixed.contains( id ) ) {
+            let joint = self.get_joint( *id ).ok_or( Error::JointNotFound )?;
+            let locked = joint.locked_axes();
+            columns.extend( ( 0..joint.degrees_of_freedom() ).filter( |k| !locked[ *k ] ).map( |k| ( *id, k, joint.degrees_of_freedom() ) ) );
+        }
+
+        let mut residual = self.loop_residual( root, base )?;
+        let mut error = dense::norm( &residual );
+        let mut damping = *solver.damping();
+        let mut iterations = 0;
+        while error > *solver.tolerance() && iterations < *solver.max_iterations() && !columns.is_empty() {
+            iterations += 1;
+            let saved = self.saved_displacements( tree.order() );
+            let transposed = self.closure_jacobian( root, base, &columns, &residual )?;
+            // The residual is driven towards zero, hence the negated right hand side.
+            let negated: Vec<T> = residual.iter().map( |value| -*value ).collect();
+            let Some( delta ) = damped_step( &transposed, &negated, damping ) else { break };
+
+            for ( ( id, k, count ), amount ) in columns.iter().zip( delta.iter() ) {
+                let mut amounts = vec![ T::zero(); *count ];
+                amounts[ *k ] = *amount;
+                if let Some( joint ) = self.get_joint_mut( *id ) {
+                    joint.displace( &amounts );
+                }
+            }
+            let trial = self.loop_residual( root, base )?;
+            let trial_error = dense::norm( &trial );
+            if trial_error >= error {
+                self.restore_displacements( &saved );
+                damping = ( damping * T::two() ).max( T::epsilon().sqrt() );
+            } else {
+                damping *= T::half();
+                residual = trial;
+                error = trial_error;
+            }
+        }
+
+        if !columns.is_empty() && !residual.is_empty() {
+            // Rate of change of the residual along the current rates, fixed joints included, removed by
+            // the smallest change of the free rates.
+            let saved = self.saved_displacements( tree.order() );
+            let step = T::epsilon().sqrt();
+            for id in tree.order() {
+                if let Some( joint ) = self.get_joint_mut( *id ) {
+                    let amounts: Vec<T> = joint.rates().iter().map( |rate| *rate * step ).collect();
+                    joint.displace( &amounts );
+                }
+            }
+            let moved = self.loop_residual( root, base )?;
+            self.restore_displacements( &saved );
+            let drift: Vec<T> = moved.iter().zip( residual.iter() ).map( |( after, before )| -( *after - *before ) / step ).collect();
+            let transposed = self.closure_jacobian( root, base, &columns, &residual )?;
+            // Barely damped, only so that redundant residual rows stay solvable.
+            if let Some( delta ) = damped_step( &transposed, &drift, T::epsilon().sqrt().sqrt() ) {
+                for ( ( id, k, _ ), amount ) in columns.iter().zip( delta.iter() ) {
+                    if let Some( rate ) = self.get_joint_mut( *id ).and_then( |joint| joint.rates_mut().get_mut( *k ) ) {
+                        *rate += *amount;
+                    }
+                }
+            }
+        }
+        self.apply_forward_kinematics( root, base )?;
+        self.apply_tree_velocities( root, base )?;
+        Ok( Solution::new( error <= *solver.tolerance(), error, iterations ) )
+    }
+
+    /// Inverse of `apply_forward_kinematics` and `apply_tree_velocities` for the tree from `root`.
+    pub fn sync_coordinates( &mut self, root: I, base: Pose<T, DIM> ) -> Result<(), Error> {
+        let tree = self.spanning_tree( root )?;
+        let mut frames = BTreeMap::new();
+        for id in tree.order() {
+            let frame = match tree.parent( id ) {
+                Some( parent ) => frames[ parent ].compose( &self.link_transform( *parent, *id )? ),
+                None => base
+            };
+            let joint = self.get_joint_mut( *id ).ok_or( Error::JointNotFound )?;
+            let displacement = frame.inverse().compose( &Pose::new( *joint.position(), *joint.rotation() ) );
+            let q = joint.kind().coordinates( &displacement );
+            joint.set_coordinates( &q );
+            frames.insert( *id, frame.compose( joint.displacement() ) );
+        }
+        if ORD == 0 {
+            return Ok( () );
+        }
+
+        // Rates fitting each joint's velocity relative to its parent, by least squares on its subspace.
+        let model = self.tree_model( root, base )?;
+        let mut velocities: Vec<Motion<T, DIM>> = Vec::with_capacity( model.len() );
+        for ( i, id ) in model.ids().iter().enumerate() {
+            let joint = self.get_joint_mut( *id ).ok_or( Error::JointNotFound )?;
+            let origin = Vector::default() - *joint.position();
+            let velocity = Motion::new( joint.angular[ 1 ], joint.spatial[ 1 ] ).shift( &origin );
+            let parent = model.parents()[ i ].map_or( Motion::default(), |parent| velocities[ parent ] );
+            let relative = ( velocity + parent * -T::one() ).to_vec();
+            let columns: Vec<Vec<T>> = model.subspaces()[ i ].iter().map( |column| column.to_vec() ).collect();
+            let normal: DenseMatrix<T> = columns.iter().map( |row| columns.iter().map( |column| dense::dot( row, column ) ).collect() ).collect();
+            let projected: Vec<T> = columns.iter().map( |column| dense::dot( column, &relative ) ).collect();
+            let rates = dense::solve( &normal, &projected ).unwrap_or_else( || vec![ T::zero(); columns.len() ] );
+            let fitted = model.subspaces()[ i ].iter().zip( rates.iter() ).fold( parent, |sum, ( column, rate )| sum + *column * *rate );
+            joint.rates_mut().copy_from_slice( &rates );
+            velocities.push( fitted );
+        }
+        Ok( () )
+    }
+
+    /// Does nothing without closed loops; fails with `Error::LoopNotClosed` if the solver does not converge.
+    pub(crate) fn reclose_loops( &mut self ) -> Result<(), Error> {
+        let Some( closed ) = self.closed_loops().copied() else { return Ok( () ) };
+        self.sync_coordinates( *closed.root(), *closed.base() )?;
+        let solution = self.close_loops( closed.solver(), *closed.root(), *closed.base(), &[] )?;
+        if solution.converged() { Ok( () ) } else { Err( Error::LoopNotClosed ) }
+    }
+
+    pub(crate) fn saved_displacements( &self, ids: &[I] ) -> Vec<( I, Pose<T, DIM> )> {
+        ids.iter().filter_map( |id| self.get_joint( *id ).map( |joint| ( *id, *joint.displacement() ) ) ).collect()
+    }
+
+    pub(crate) fn restore_displacements( &mut self, saved: &[( I, Pose<T, DIM> )] ) {
+        for ( id, displacement ) in saved.iter() {
+            if let Some( joint ) = self.get_joint_mut( *id ) {
+                *joint.displacement_mut() = *displacement;
+            }
+        }
+    }
+
+    /// Forward difference Jacobian of the loop residual at `residual` with respect to `columns`.
+    fn closure_jacobian( &mut self, root: I, base: Pose<T, DIM>, columns: &[( I, usize, usize )], residual: &[T] ) -> Result<DenseMatrix<T>, Error> {
+        let step = T::epsilon().sqrt();
+        let mut transposed = Vec::with_capacity( columns.len() );
+        for ( id, k, count ) in columns.iter() {
+            let joint = self.get_joint_mut( *id ).ok_or( Error::JointNotFound )?;
+            let saved = *joint.displacement();
+            let mut amounts = vec![ T::zero(); *count ];
+            amounts[ *k ] = step;
+            joint.displace( &amounts );
+            let trial = self.loop_residual( root, base );
+            if let Some( joint ) = self.get_joint_mut( *id ) {
+                *joint.displacement_mut() = saved;
+            }
+            transposed.push( trial?.iter().zip( residual.iter() ).map( |( after, before )| ( *after - *before ) / step ).collect::<Vec<T>>() );
+        }
+        Ok( transposed )
+    }
+}
+
+/// `Jᵀ ( J Jᵀ + λ² I )⁻¹ target` for the Jacobian given by its columns `transposed`.
+pub(crate) fn damped_step<T>( transposed: &DenseMatrix<T>, target: &[T], damping: T ) -> Option<Vec<T>>
+where
+    T: Scalar
+{
+    let jacobian = dense::transpose( transposed );
+    let mut system = dense::multiply( &jacobian, transposed );
+    for ( i, row ) in system.iter_mut().enumerate() {
+        row[ i ] += damping * damping;
+    }
+    let weights = dense::solve( &system, target )?;
+    Some( dense::multiply_vector( transposed, &weights ) )
+}
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+    use crate::{
+        linkage::Linkage3D,
+        force::ForceField,
+        integrator::SemiImplicitEuler,
+        dynamics::Multibody,
+        body::Body3D,
+        joint::{ Joint3D, JointKind },
+        link::Link,
+        constraint::{ Range, Constraint3D },
+        rotation
+    };
+
+    /// Four bar in the xy plane: ground joint `4`, crank `0 - 1`, coupler `1 - 2` and rocker `3 - 2`.
+    fn four_bar() -> Linkage3D<usize, f64, 1> {
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        for id in 0..5 {
+            let kind = if id < 4 { JointKind::Revolute { axis: Vector3::from([ 0.0, 0.0, 1.0 ]) } } else { JointKind::Fixed };
+            let joint = Joint3D::new( Body3D::new( 1.0, [ Vector3::default(); 2 ], [ Vector3::default(); 2 ] ), [ Constraint3D::default(); 4 ] );
+            linkage.add_joint( id, joint.with_kind( kind ) ).unwrap();
+        }
+        let offset = |x| Pose::new( Vector3::from([ x, 0.0, 0.0 ]), Vector3::default() );
+        let pin = Constraint3D::new([ Some( Range::new( 0.0, 0.0 ) ), Some( Range::new( 0.0, 0.0 ) ), None ]);
+        linkage.add_link( 4, 0, Link::rigid( 0.0, Pose::identity() ) ).unwrap();
+        linkage.add_link( 4, 3, Link::rigid( 0.0, offset( 2.5 ) ) ).unwrap();
+        linkage.add_link( 0, 1, Link::rigid( 0.0, offset( 1.0 ) ) ).unwrap();
+        linkage.add_link( 3, 2, Link::rigid( 0.0, offset( 1.5 ) ) ).unwrap();
+        linkage.add_link( 1, 2, Link::rigid( 0.0, offset( 2.0 ) ).with_angular_constraint( pin ) ).unwrap();
+        linkage
+    }
+
+    #[test]
+    fn loops_test() {
+        let linkage = four_bar();
+        let loops = linkage.loops( 4 ).unwrap();
+        assert_eq!( loops.len(), 1 );
+        assert_eq!( loops[ 0 ].cut(), &( 1, 2 ) );
+        assert_eq!( loops[ 0 ].joints(), &[ 1, 0, 4, 3, 2 ] );
+        assert_eq!( linkage.loop_residual( 4, Pose::identity() ).unwrap().len(), 6 );
+    }
+
+    #[test]
+    fn four_bar_test() {
+        let mut linkage = four_bar();
+        // Start on the elbow up branch.
+        linkage.get_joint_mut( 3 ).unwrap().set_coordinates( &[ 1.5 ] );
+        for crank in [ 0.5, 1.0, std::f64::consts::FRAC_PI_2 ] {
+            linkage.get_joint_mut( 0 ).unwrap().set_coordinates( &[ crank ] );
+            let solution = linkage.close_loops( &LoopClosure::default(), 4, Pose::identity(), &[ 0 ] ).unwrap();
+            assert!( solution.converged(), "crank {}", crank );
+            assert!( ( linkage.get_joint( 0 ).unwrap().coordinates()[ 0 ] - crank ).abs() < 1e-12 );
+            let position = |id: usize| *linkage.get_joint( id ).unwrap().position();
+            assert!( ( rotation::norm( &( position( 2 ) - position( 1 ) ) ) - 2.0 ).abs() < 1e-6 );
+            assert!( ( rotation::norm( &( position( 2 ) - position( 3 ) ) ) - 1.5 ).abs() < 1e-6 );
+            assert!( position( 2 )[ 1 ] > 0.0 );
+        }
+    }
+
+    /// Four bar with its links' masses off their joints, closed at a crank angle of one.
+    fn closed_four_bar() -> Linkage3D<usize, f64, 1> {
+        let mut linkage = four_bar();
+        for id in 0..4 {
+            *linkage.get_joint_mut( id ).unwrap().center_of_mass_mut() = Vector3::from([ 0.5, 0.0, 0.0 ]);
+        }
+        linkage.get_joint_mut( 3 ).unwrap().set_coordinates( &[ 1.5 ] );
+        linkage.get_joint_mut( 0 ).unwrap().set_coordinates( &[ 1.0 ] );
+        assert!( linkage.close_loops( &LoopClosure::default(), 4, Pose::identity(), &[ 0 ] ).unwrap().converged() );
+        linkage
+    }
+
+    /// Coupler and rocker lengths held, and not changing at the current velocities.
+    fn assert_closed( linkage: &Linkage3D<usize, f64, 1> ) {
+        let joint = |id: usize| linkage.get_joint( id ).unwrap();
+        for ( id, length ) in [ ( 1, 2.0 ), ( 3, 1.5 ) ] {
+            let offset = *joint( 2 ).position() - *joint( id ).position();
+            let rate = *joint( 2 ).spatial_velocity() - *joint( id ).spatial_velocity();
+            assert!( ( rotation::norm( &offset ) - length ).abs() < 1e-6 );
+            assert!( rotation::dot( &offset, &rate ).abs() < 1e-5 );
+        }
+    }
+
+    #[test]
+    fn stepping_test() {
+        // Falling under gravity as a multibody, whose tree leaves the coupler to rocker link cut.
+        let mut linkage = closed_four_bar();
+        linkage.add_force_field( ForceField::Gravity { acceleration: Vector3::from([ 0.0, -9.81, 0.0 ]) } );
+        linkage.set_multibody( Some( Multibody::new( 4, Pose::identity() ) ) );
+        for _ in 0..100 {
+            linkage.update( &SemiImplicitEuler, 0.005 ).unwrap();
+            assert_closed( &linkage );
+        }
+        assert!( ( linkage.get_joint( 0 ).unwrap().coordinates()[ 0 ] - 1.0 ).abs() > 1e-3 );
+
+        // Stepped joint by joint from a spinning crank, with the loop re-closed after every step.
+        let mut linkage = closed_four_bar();
+        linkage.set_closed_loops( Some( ClosedLoops::new( 4, Pose::identity() ) ) );
+        *linkage.get_joint_mut( 0 ).unwrap().angular_velocity_mut() = Vector3::from([ 0.0, 0.0, 1.0 ]);
+        for _ in 0..20 {
+            linkage.update( &SemiImplicitEuler, 0.01 ).unwrap();
+            assert_closed( &linkage );
+        }
+        assert!( ( linkage.get_joint( 0 ).unwrap().coordinates()[ 0 ] - 1.0 ).abs() > 1e-3 );
+    }
+}
@@ -9,7 +9,13 @@ use linear_algebra::vector::Vector;
 
 use const_expr_bounds::{ Assert, IsTrue };
 
-#[derive( Clone, Debug, PartialEq )]
+use crate::{
+    scalar::Scalar,
+    integrator::Integrator,
+    rotation::{ self, Quaternion, Matrix3 }
+};
+
+#[derive( Clone, Debug )]
 pub struct Particle<T, const DIM: usize, const ORD: usize>
 where
     T: 'static + Default + Copy + Debug,
@@ -17,6 +23,9 @@ where
 {
     pub(crate) spatial: [Vector<T, DIM>; ORD + 1],
     pub(crate) angular: [Vector<T, DIM>; ORD + 1],
+    /// Unit quaternion of a three dimensional orientation with the rotation vector it was last written
+    /// to `angular[0]` as, which stops it applying once `angular[0]` is written directly.
+    orientation: Option<( Quaternion<T>, Vector<T, DIM> )>
 }
 
 #[allow(clippy::needless_lifetimes)]
@@ -29,6 +38,7 @@ where
         Self {
             spatial,
             angular,
+            orientation: None
         }
     }
 
@@ -295,14 +305,117 @@ where
         *self.angular_pop_mut() = pop;
     }
 
-    pub fn update( &mut self, time_step: T )
+    pub fn update<I>( &mut self, integrator: &I, time_step: T )
+    where
+        T: Scalar,
+        I: Integrator<T>
+    {
+        let spatial = self.spatial[ ORD ];
+        let angular = self.angular[ ORD ];
+        self.update_with( integrator, time_step, |_| spatial, |_| angular );
+    }
+
+    pub fn update_with<I, F, G>( &mut self, integrator: &I, time_step: T, spatial: F, angular: G )
+    where
+        T: Scalar,
+        I: Integrator<T>,
+        F: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>,
+        G: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>
+    {
+        self.update_to_with( integrator, time_step, ORD, spatial, angular );
+    }
+
+    /// Integrates the derivatives up to order `top`, which `spatial` and `angular` evaluate on the
+    /// intermediate stacks, and sets every derivative above `top` to the finite difference over the step
+    /// of the one below it, the rotation differenced by composing with the inverse of its start.
+    pub fn update_to_with<I, F, G>( &mut self, integrator: &I, time_step: T, top: usize, spatial: F, angular: G )
+    where
+        T: Scalar,
+        I: Integrator<T>,
+        F: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>,
+        G: Fn( &[Vector<T, DIM>] ) -> Vector<T, DIM>
+    {
+        let top = top.min( ORD );
+        let ( spatial_before, angular_before ) = ( self.spatial, self.angular );
+        integrator.integrate_with( &mut self.spatial[ ..=top ], time_step, spatial );
+        self.angular[ 0 ] = Vector::default();
+        integrator.integrate_with( &mut self.angular[ ..=top ], time_step, angular );
+        let delta = self.angular[ 0 ];
+        self.angular[ 0 ] = angular_before[ 0 ];
+        self.turn( &delta );
+        if time_step != T::zero() {
+            for i in ( top + 1 )..=ORD {
+                self.spatial[ i ] = ( self.spatial[ i - 1 ] - spatial_before[ i - 1 ] ) / time_step;
+                self.angular[ i ] = if i == 1 {
+                    rotation::compose( &( Vector::default() - angular_before[ 0 ] ), &self.angular[ 0 ] ) / time_step
+                } else {
+                    ( self.angular[ i - 1 ] - angular_before[ i - 1 ] ) / time_step
+                };
+            }
+        }
+        self.hold_planar();
+    }
+
+    /// Composes the world frame rotation `delta` onto the orientation, in three dimensions on the unit
+    /// quaternion, `angular[0]` following as its logarithm.
+    pub(crate) fn turn( &mut self, delta: &Vector<T, DIM> )
     where
-        T: 'static + Default + Copy + Debug + Mul<Output = T> + AddAssign,
+        T: Scalar
     {
-        for i in ( 1..=ORD ).rev() {
-            self.spatial[ i - 1 ] += self.spatial[ i ] * time_step;
-            self.angular[ i - 1 ] += self.angular[ i ] * time_step;
+        if DIM != 3 {
+            self.angular[ 0 ] = rotation::compose( &self.angular[ 0 ], delta );
+            self.hold_planar();
+            return;
         }
+        let orientation = ( Quaternion::from_rotation_vector( &rotation::resize( delta ) ) * self.quaternion() ).normalize();
+        self.angular[ 0 ] = rotation::resize( &orientation.to_rotation_vector() );
+        self.orientation = Some( ( orientation, self.angular[ 0 ] ) );
+    }
+
+    /// Clears the second angular component in two dimensions, where rotation is the first alone.
+    fn hold_planar( &mut self )
+    where
+        T: Scalar
+    {
+        if DIM == 2 {
+            for angular in self.angular.iter_mut() {
+                angular[ 1 ] = T::zero();
+            }
+        }
+    }
+
+    fn quaternion( &self ) -> Quaternion<T>
+    where
+        T: Scalar
+    {
+        match self.orientation {
+            Some( ( orientation, rotation ) ) if rotation == self.angular[ 0 ] => orientation,
+            _ => Quaternion::from_rotation_vector( &rotation::resize( &self.angular[ 0 ] ) )
+        }
+    }
+}
+
+impl<T, const ORD: usize> Particle<T, 3, ORD>
+where
+    T: Scalar,
+    [(); ORD + 1]:
+{
+    pub fn orientation( &self ) -> Quaternion<T> {
+        self.quaternion()
+    }
+
+    pub fn set_orientation( &mut self, orientation: Quaternion<T> ) {
+        let orientation = orientation.normalize();
+        self.angular[ 0 ] = orientation.to_rotation_vector();
+        self.orientation = Some( ( orientation, self.angular[ 0 ] ) );
+    }
+
+    pub fn rotation_matrix( &self ) -> Matrix3<T> {
+        self.orientation().to_rotation_matrix()
+    }
+
+    pub fn euler_angles( &self ) -> Vector<T, 3> {
+        self.orientation().to_euler()
     }
 }
 
@@ -315,10 +428,21 @@ where
         Self {
             spatial: [Vector::<T, DIM>::default(); ORD + 1],
             angular: [Vector::<T, DIM>::default(); ORD + 1],
+            orientation: None
         }
     }
 }
 
+impl<T, const DIM: usize, const ORD: usize> PartialEq for Particle<T, DIM, ORD>
+where
+    T: 'static + Default + Copy + Debug + PartialEq,
+    [(); ORD + 1]:
+{
+    fn eq( &self, other: &Self ) -> bool {
+        self.spatial == other.spatial && self.angular == other.angular
+    }
+}
+
 pub type Particle1D<T, const ORD: usize> = Particle<T, 1, ORD>;
 pub type Particle2D<T, const ORD: usize> = Particle<T, 2, ORD>;
 pub type Particle3D<T, const ORD: usize> = Particle<T, 3, ORD>;
@@ -328,6 +452,7 @@ pub type Particle4D<T, const ORD: usize> = Particle<T, 4, ORD>;
 mod tests {
     use linear_algebra::vector::Vector3;
     use super::*;
+    use crate::integrator::{ Taylor, SemiImplicitEuler };
 
     #[test]
     fn new_test() {
@@ -341,4 +466,63 @@ mod tests {
         assert_eq!( *body.spatial_velocity(), spatial_velocity );
         assert_eq!( *body.angular_velocity(), angular_velocity );
     }
+
+    #[test]
+    fn update_test() {
+        let mut particle = Particle3D::<f64, 2>::new(
+            [ Vector3::default(), Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::from([ 0.0, 2.0, 0.0 ]) ],
+            [ Vector3::default(); 3 ]
+        );
+        particle.update( &Taylor, 1.0 );
+        assert_eq!( *particle.position(), Vector3::from([ 1.0, 1.0, 0.0 ]) );
+        assert_eq!( *particle.spatial_velocity(), Vector3::from([ 1.0, 2.0, 0.0 ]) );
+    }
+
+    #[test]
+    fn update_to_test() {
+        let angular = [ Vector::from([ 3.1, 0.0 ]), Vector::from([ 1.0, 0.5 ]), Vector::from([ 0.0, 0.5 ]) ];
+        let mut particle = Particle::<f64, 2, 2>::new( [ Vector::default(); 3 ], angular );
+        particle.update_to_with( &SemiImplicitEuler, 0.0, 1, |_| Vector::default(), |_| Vector::from([ 1.0, 0.5 ]) );
+        assert_eq!( particle.angular_acceleration()[ 1 ], 0.0 );
+
+        // The rotation wraps past the half turn and the differenced acceleration stays planar.
+        particle.update_to_with( &SemiImplicitEuler, 0.1, 1, |_| Vector::default(), |_| Vector::from([ 1.0, 0.5 ]) );
+        assert!( ( particle.rotation()[ 0 ] - ( 3.2 - 2.0 * std::f64::consts::PI ) ).abs() < 1e-12 );
+        assert_eq!( particle.angular_velocity()[ 1 ], 0.0 );
+        assert_eq!( particle.angular_acceleration()[ 1 ], 0.0 );
+    }
+
+    #[test]
+    fn orientation_test() {
+        let quarter = std::f64::consts::FRAC_PI_2;
+        let mut particle = Particle3D::<f64, 1>::default();
+        particle.set_orientation( Quaternion::from_euler( quarter, 0.0, 0.0 ) );
+        *particle.angular_velocity_mut() = Vector3::from([ 0.0, quarter, 0.0 ]);
+        for _ in 0..10 {
+            particle.update( &SemiImplicitEuler, 0.1 );
+        }
+        let expected = Quaternion::from_euler( 0.0, quarter, 0.0 ) * Quaternion::from_euler( quarter, 0.0, 0.0 );
+        let rotation = *particle.rotation();
+        for i in 0..3 {
+            assert!( ( rotation[ i ] - expected.to_rotation_vector()[ i ] ).abs() < 1e-9 );
+        }
+    }
+
+    #[test]
+    fn quaternion_state_test() {
+        let mut particle = Particle3D::<f64, 1>::default();
+        *particle.angular_velocity_mut() = Vector3::from([ 0.0, 0.0, 1.0 ]);
+        for _ in 0..100 {
+            particle.update( &SemiImplicitEuler, 0.1 );
+        }
+        let orientation = particle.orientation();
+        assert!( ( orientation.norm() - 1.0 ).abs() < 1e-12 );
+        // Ten radians, past the half turn where the rotation vector flips, with the quaternion continuous.
+        assert!( ( *orientation.w() - 5.0f64.cos() ).abs() < 1e-9 && ( *orientation.z() - 5.0f64.sin() ).abs() < 1e-9 );
+        let x = orientation.rotate( &Vector3::from([ 1.0, 0.0, 0.0 ]) );
+        assert!( ( x[ 0 ] - 10.0f64.cos() ).abs() < 1e-9 && ( x[ 1 ] - 10.0f64.sin() ).abs() < 1e-9 );
+
+        *particle.rotation_mut() = Vector3::from([ 0.0, 0.0, 0.5 ]);
+        assert_eq!( particle.orientation(), Quaternion::from_rotation_vector( &Vector3::from([ 0.0, 0.0, 0.5 ]) ) );
+    }
 }
@@ -0,0 +1,142 @@
+// Copyright 2024 Bewusstsein Labs
+
+use std::{
+    fmt::Debug,
+    ops::Mul
+};
+
+use linear_algebra::vector::Vector;
+
+use crate::{
+    scalar::Scalar,
+    particle::Particle
+};
+
+/// Wraps an angle into `[-π, π)`.
+pub fn wrap<T>( angle: T ) -> T
+where
+    T: Scalar
+{
+    let pi = T::from_f64( std::f64::consts::PI );
+    let turn = pi * T::two();
+    angle - turn * ( ( angle + pi ) / turn ).floor()
+}
+
+/// Planar orientation as a unit complex number `cos + i sin`.
+#[derive( Clone, Copy, Debug, PartialEq )]
+pub struct Angle<T> {
+    cos: T,
+    sin: T
+}
+
+impl<T> Angle<T>
+where
+    T: Scalar
+{
+    pub fn new( radians: T ) -> Self {
+        let ( sin, cos ) = radians.sin_cos();
+        Self { cos, sin }
+    }
+
+    pub fn identity() -> Self {
+        Self { cos: T::one(), sin: T::zero() }
+    }
+
+    pub fn cos( &self ) -> &T { &self.cos }
+    pub fn sin( &self ) -> &T { &self.sin }
+
+    pub fn radians( &self ) -> T {
+        self.sin.atan2( self.cos )
+    }
+
+    pub fn inverse( &self ) -> Self {
+        Self { cos: self.cos, sin: -self.sin }
+    }
+
+    pub fn rotate( &self, vector: &Vector<T, 2> ) -> Vector<T, 2> {
+        Vector::from([
+            self.cos * vector[ 0 ] - self.sin * vector[ 1 ],
+            self.sin * vector[ 0 ] + self.cos * vector[ 1 ]
+        ])
+    }
+}
+
+impl<T> Default for Angle<T>
+where
+    T: Scalar
+{
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl<T> Mul for Angle<T>
+where
+    T: Scalar
+{
+    type Output = Self;
+
+    fn mul( self, rhs: Self ) -> Self::Output {
+        let cos = self.cos * rhs.cos - self.sin * rhs.sin;
+        let sin = self.sin * rhs.cos + self.cos * rhs.sin;
+        let norm = ( cos * cos + sin * sin ).sqrt();
+        Self { cos: cos / norm, sin: sin / norm }
+    }
+}
+
+/// Two dimensional particles, and so `Body2D` and `Joint2D`, rotate by the first angular component
+/// alone; the second is held at zero by every update.
+impl<T, const ORD: usize> Particle<T, 2, ORD>
+where
+    T: 'static + Default + Copy + Debug,
+    [(); ORD + 1]:
+{
+    pub fn orientation( &self ) -> Angle<T>
+    where
+        T: Scalar
+    {
+        Angle::new( self.angular[0][0] )
+    }
+
+    pub fn set_orientation( &mut self, orientation: Angle<T> )
+    where
+        T: Scalar
+    {
+        self.angular[0][0] = orientation.radians();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        body::Body2D,
+        joint::Joint2D,
+        integrator::SemiImplicitEuler
+    };
+
+    #[test]
+    fn update_test() {
+        let pi = std::f64::consts::PI;
+        let mut particle = Particle::<f64, 2, 1>::new( [ Vector::default(); 2 ], [ Vector::default(), Vector::from([ pi, 0.0 ]) ] );
+        for _ in 0..15 {
+            particle.update( &SemiImplicitEuler, 0.1 );
+        }
+        assert!( ( particle.rotation()[ 0 ] + 0.5 * pi ).abs() < 1e-9 );
+        assert!( ( particle.orientation().rotate( &Vector::<f64, 2>::from([ 1.0, 0.0 ]) )[ 1 ] + 1.0 ).abs() < 1e-9 );
+        assert!( ( ( Angle::new( 0.75 * pi ) * Angle::new( 0.5 * pi ) ).radians() + 0.75 * pi ).abs() < 1e-9 );
+    }
+
+    #[test]
+    fn joint_test() {
+        let pi = std::f64::consts::PI;
+        let angular = [ Vector::<f64, 2>::default(), Vector::from([ pi, 5.0 ]) ];
+        let mut joint = Joint2D::<f64, 1>::new( Body2D::new( 1.0, [ Vector::default(); 2 ], angular ), Default::default() );
+        for _ in 0..15 {
+            joint.update( &SemiImplicitEuler, 0.1 );
+        }
+        assert!( ( joint.orientation().radians() + 0.5 * pi ).abs() < 1e-9 );
+        assert_eq!( joint.rotation()[ 1 ], 0.0 );
+        assert_eq!( joint.angular_velocity()[ 1 ], 0.0 );
+    }
+}
@@ -0,0 +1,500 @@
+// Copyright 2024 Bewusstsein Labs
+
+//! Orientation in three dimensions is a unit `Quaternion` on `Particle3D`, composed on SO(3) by the
+//! exponential map; its rotation vector (axis times angle) is what constraints and poses work on.
+
+use std::ops::Mul;
+
+use linear_algebra::vector::Vector;
+
+use crate::{
+    scalar::Scalar,
+    planar::{ self, Angle }
+};
+
+pub type Matrix3<T> = Vector<Vector<T, 3>, 3>;
+
+pub fn dot<T, const DIM: usize>( a: &Vector<T, DIM>, b: &Vector<T, DIM> ) -> T
+where
+    T: Scalar
+{
+    let mut sum = T::zero();
+    for i in 0..DIM {
+        sum += a[ i ] * b[ i ];
+    }
+    sum
+}
+
+pub fn norm<T, const DIM: usize>( a: &Vector<T, DIM> ) -> T
+where
+    T: Scalar
+{
+    dot( a, a ).sqrt()
+}
+
+pub fn cross<T>( a: &Vector<T, 3>, b: &Vector<T, 3> ) -> Vector<T, 3>
+where
+    T: Scalar
+{
+    Vector::from([
+        a[ 1 ] * b[ 2 ] - a[ 2 ] * b[ 1 ],
+        a[ 2 ] * b[ 0 ] - a[ 0 ] * b[ 2 ],
+        a[ 0 ] * b[ 1 ] - a[ 1 ] * b[ 0 ]
+    ])
+}
+
+pub fn resize<T, const FROM: usize, const TO: usize>( a: &Vector<T, FROM> ) -> Vector<T, TO>
+where
+    T: Scalar
+{
+    let mut result = Vector::<T, TO>::default();
+    for i in 0..FROM.min( TO ) {
+        result[ i ] = a[ i ];
+    }
+    result
+}
+
+pub fn identity<T>() -> Matrix3<T>
+where
+    T: Scalar
+{
+    let mut result = Matrix3::<T>::default();
+    for i in 0..3 {
+        result[ i ][ i ] = T::one();
+    }
+    result
+}
+
+pub fn transpose<T>( m: &Matrix3<T> ) -> Matrix3<T>
+where
+    T: Scalar
+{
+    let mut result = Matrix3::<T>::default();
+    for i in 0..3 {
+        for j in 0..3 {
+            result[ i ][ j ] = m[ j ][ i ];
+        }
+    }
+    result
+}
+
+pub fn matrix_vector<T>( m: &Matrix3<T>, v: &Vector<T, 3> ) -> Vector<T, 3>
+where
+    T: Scalar
+{
+    Vector::from([ dot( &m[ 0 ], v ), dot( &m[ 1 ], v ), dot( &m[ 2 ], v ) ])
+}
+
+pub fn matrix_matrix<T>( a: &Matrix3<T>, b: &Matrix3<T> ) -> Matrix3<T>
+where
+    T: Scalar
+{
+    let mut result = Matrix3::<T>::default();
+    for i in 0..3 {
+        for j in 0..3 {
+            for k in 0..3 {
+                result[ i ][ j ] += a[ i ][ k ] * b[ k ][ j ];
+            }
+        }
+    }
+    result
+}
+
+pub fn resize_matrix<T, const FROM: usize, const TO: usize>( m: &Vector<Vector<T, FROM>, FROM> ) -> Vector<Vector<T, TO>, TO>
+where
+    T: Scalar
+{
+    let mut result = Vector::<Vector<T, TO>, TO>::default();
+    for i in 0..FROM.min( TO ) {
+        result[ i ] = resize( &m[ i ] );
+    }
+    result
+}
+
+/// Inverse of `m`, `None` if its determinant vanishes relative to the cube of its Frobenius norm.
+pub fn inverse<T>( m: &Matrix3<T> ) -> Option<Matrix3<T>>
+where
+    T: Scalar
+{
+    let mut adjugate = Matrix3::<T>::default();
+    for i in 0..3 {
+        for j in 0..3 {
+            let ( r0, r1 ) = ( ( j + 1 ) % 3, ( j + 2 ) % 3 );
+            let ( c0, c1 ) = ( ( i + 1 ) % 3, ( i + 2 ) % 3 );
+            adjugate[ i ][ j ] = m[ r0 ][ c0 ] * m[ r1 ][ c1 ] - m[ r0 ][ c1 ] * m[ r1 ][ c0 ];
+        }
+    }
+    let determinant = m[ 0 ][ 0 ] * adjugate[ 0 ][ 0 ] + m[ 0 ][ 1 ] * adjugate[ 1 ][ 0 ] + m[ 0 ][ 2 ] * adjugate[ 2 ][ 0 ];
+    // Singular relative to the size of the entries, so that small but well conditioned matrices invert.
+    let scale = ( 0..3 ).fold( T::zero(), |sum, i| sum + dot( &m[ i ], &m[ i ] ) ).sqrt();
+    if scale == T::zero() || determinant.abs() <= T::epsilon() * scale * scale * scale {
+        return None;
+    }
+    for i in 0..3 {
+        adjugate[ i ] = adjugate[ i ] / determinant;
+    }
+    Some( adjugate )
+}
+
+/// Moment of `force` applied at `arm`: the cross product in three dimensions and the planar moment,
+/// stored in the first component, in two.
+pub fn moment<T, const DIM: usize>( arm: &Vector<T, DIM>, force: &Vector<T, DIM> ) -> Vector<T, DIM>
+where
+    T: Scalar
+{
+    if DIM == 3 {
+        resize( &cross( &resize( arm ), &resize( force ) ) )
+    } else if DIM == 2 {
+        let mut result = Vector::<T, DIM>::default();
+        result[ 0 ] = arm[ 0 ] * force[ 1 ] - arm[ 1 ] * force[ 0 ];
+        result
+    } else {
+        Vector::default()
+    }
+}
+
+/// Velocity of the point at `arm` on a body turning at `angular`, i.e. `angular × arm`, with the planar
+/// angular velocity stored in the first component as for `moment`.
+pub fn tangent<T, const DIM: usize>( angular: &Vector<T, DIM>, arm: &Vector<T, DIM> ) -> Vector<T, DIM>
+where
+    T: Scalar
+{
+    if DIM == 3 {
+        resize( &cross( &resize( angular ), &resize( arm ) ) )
+    } else if DIM == 2 {
+        let mut result = Vector::<T, DIM>::default();
+        result[ 0 ] = -angular[ 0 ] * arm[ 1 ];
+        result[ 1 ] = angular[ 0 ] * arm[ 0 ];
+        result
+    } else {
+        Vector::default()
+    }
+}
+
+/// Rate of change of one angular quantity turning at `angular`, which only exists in three dimensions.
+pub fn angular_tangent<T, const DIM: usize>( angular: &Vector<T, DIM>, quantity: &Vector<T, DIM> ) -> Vector<T, DIM>
+where
+    T: Scalar
+{
+    if DIM == 3 { tangent( angular, quantity ) } else { Vector::default() }
+}
+
+/// Number of rotational degrees of freedom in `dim` dimensions, as stored in a rotation vector.
+pub const fn angular_dimension( dim: usize ) -> usize {
+    match dim {
+        3 => 3,
+        2 => 1,
+        _ => 0
+    }
+}
+
+/// Expresses an angular quantity given in the frame of `rotation` in the outer frame; planar angular
+/// quantities are unchanged.
+pub fn rotate_angular<T, const DIM: usize>( rotation: &Vector<T, DIM>, angular: &Vector<T, DIM> ) -> Vector<T, DIM>
+where
+    T: Scalar
+{
+    if DIM == 3 { rotate( rotation, angular ) } else { *angular }
+}
+
+/// Time derivative of the rotation vector `rotation` while turning at the world frame `angular_velocity`,
+/// through the inverse of the left Jacobian of SO(3).
+pub fn rotation_rate<T, const DIM: usize>( rotation: &Vector<T, DIM>, angular_velocity: &Vector<T, DIM> ) -> Vector<T, DIM>
+where
+    T: Scalar
+{
+    if DIM != 3 {
+        return *angular_velocity;
+    }
+    let angle = norm( rotation );
+    let coefficient = if angle < T::epsilon().sqrt() {
+        T::one() / T::from_usize( 12 )
+    } else {
+        T::one() / ( angle * angle ) - ( T::one() + angle.cos() ) / ( T::two() * angle * angle.sin() )
+    };
+    let first = tangent( rotation, angular_velocity );
+    *angular_velocity - first * T::half() + tangent( rotation, &first ) * coefficient
+}
+
+/// Composes the rotation `delta`, expressed in the world frame, onto `rotation`.
+///
+/// In three dimensions both are rotation vectors and are composed on SO(3); in two dimensions the
+/// first component is the planar angle and is wrapped; in every other dimension rotations are added.
+pub fn compose<T, const DIM: usize>( rotation: &Vector<T, DIM>, delta: &Vector<T, DIM> ) -> Vector<T, DIM>
+where
+    T: Scalar
+{
+    if DIM == 3 {
+        let combined = Quaternion::from_rotation_vector( &resize( delta ) ) * Quaternion::from_rotation_vector( &resize( rotation ) );
+        resize( &combined.to_rotation_vector() )
+    } else if DIM == 2 {
+        let mut combined = *rotation + *delta;
+        combined[ 0 ] = planar::wrap( combined[ 0 ] );
+        combined
+    } else {
+        *rotation + *delta
+    }
+}
+
+/// Rotates `vector` by `rotation`, using the same conventions as `compose`.
+pub fn rotate<T, const DIM: usize>( rotation: &Vector<T, DIM>, vector: &Vector<T, DIM> ) -> Vector<T, DIM>
+where
+    T: Scalar
+{
+    if DIM == 3 {
+        resize( &Quaternion::from_rotation_vector( &resize( rotation ) ).rotate( &resize( vector ) ) )
+    } else if DIM == 2 {
+        resize( &Angle::new( rotation[ 0 ] ).rotate( &resize( vector ) ) )
+    } else {
+        *vector
+    }
+}
+
+/// Unit quaternion `w + xi + yj + zk` using the Hamilton convention.
+#[derive( Clone, Copy, Debug, PartialEq )]
+pub struct Quaternion<T> {
+    w: T,
+    x: T,
+    y: T,
+    z: T
+}
+
+impl<T> Quaternion<T>
+where
+    T: Scalar
+{
+    pub fn new( w: T, x: T, y: T, z: T ) -> Self {
+        Self { w, x, y, z }
+    }
+
+    pub fn identity() -> Self {
+        Self::new( T::one(), T::zero(), T::zero(), T::zero() )
+    }
+
+    pub fn w( &self ) -> &T { &self.w }
+    pub fn x( &self ) -> &T { &self.x }
+    pub fn y( &self ) -> &T { &self.y }
+    pub fn z( &self ) -> &T { &self.z }
+
+    pub fn vector( &self ) -> Vector<T, 3> {
+        Vector::from([ self.x, self.y, self.z ])
+    }
+
+    pub fn norm( &self ) -> T {
+        ( self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z ).sqrt()
+    }
+
+    pub fn normalize( &self ) -> Self {
+        let norm = self.norm();
+        if norm == T::zero() {
+            return Self::identity();
+        }
+        Self::new( self.w / norm, self.x / norm, self.y / norm, self.z / norm )
+    }
+
+    pub fn conjugate( &self ) -> Self {
+        Self::new( self.w, -self.x, -self.y, -self.z )
+    }
+
+    pub fn from_axis_angle( axis: &Vector<T, 3>, angle: T ) -> Self {
+        let length = norm( axis );
+        if length == T::zero() {
+            return Self::identity();
+        }
+        let half = angle * T::half();
+        let scale = half.sin() / length;
+        Self::new( half.cos(), axis[ 0 ] * scale, axis[ 1 ] * scale, axis[ 2 ] * scale )
+    }
+
+    /// Returns the unit rotation axis and the angle in `[0, π]`; the identity maps to the x axis.
+    pub fn to_axis_angle( &self ) -> ( Vector<T, 3>, T ) {
+        let rotation = self.to_rotation_vector();
+        let angle = norm( &rotation );
+        if angle <= T::epsilon() {
+            return ( Vector::from([ T::one(), T::zero(), T::zero() ]), T::zero() );
+        }
+        ( rotation / angle, angle )
+    }
+
+    /// Exponential map from a rotation vector (axis scaled by angle).
+    pub fn from_rotation_vector( rotation: &Vector<T, 3> ) -> Self {
+        let angle = norm( rotation );
+        if angle <= T::epsilon() {
+            return Self::new( T::one(), rotation[ 0 ] * T::half(), rotation[ 1 ] * T::half(), rotation[ 2 ] * T::half() ).normalize();
+        }
+        Self::from_axis_angle( rotation, angle )
+    }
+
+    /// Logarithmic map to a rotation vector with an angle in `[0, π]`.
+    pub fn to_rotation_vector( &self ) -> Vector<T, 3> {
+        let unit = self.normalize();
+        let unit = if unit.w < T::zero() { Self::new( -unit.w, -unit.x, -unit.y, -unit.z ) } else { unit };
+        let vector = unit.vector();
+        let sine = norm( &vector );
+        if sine <= T::epsilon() {
+            return vector * T::two();
+        }
+        vector * ( T::two() * sine.atan2( unit.w ) / sine )
+    }
+
+    /// Splits the rotation into `( swing, twist )` with `self = swing * twist`, where `twist` turns about
+    /// `axis` and `swing` about an axis perpendicular to it. A swing by half a turn leaves no twist.
+    pub fn swing_twist( &self, axis: &Vector<T, 3> ) -> ( Self, Self ) {
+        let unit = self.normalize();
+        let length = norm( axis );
+        if length == T::zero() {
+            return ( unit, Self::identity() );
+        }
+        let projection = *axis * ( dot( &unit.vector(), axis ) / ( length * length ) );
+        let twist = Self::new( unit.w, projection[ 0 ], projection[ 1 ], projection[ 2 ] );
+        let twist = if twist.norm() <= T::epsilon() { Self::identity() } else { twist.normalize() };
+        ( unit * twist.conjugate(), twist )
+    }
+
+    /// Builds the rotation `Rz(yaw) * Ry(pitch) * Rx(roll)`.
+    pub fn from_euler( roll: T, pitch: T, yaw: T ) -> Self {
+        let ( sr, cr ) = ( roll * T::half() ).sin_cos();
+        let ( sp, cp ) = ( pitch * T::half() ).sin_cos();
+        let ( sy, cy ) = ( yaw * T::half() ).sin_cos();
+        Self::new(
+            cr * cp * cy + sr * sp * sy,
+            sr * cp * cy - cr * sp * sy,
+            cr * sp * cy + sr * cp * sy,
+            cr * cp * sy - sr * sp * cy
+        )
+    }
+
+    /// Returns `[ roll, pitch, yaw ]` for the convention used by `from_euler`.
+    pub fn to_euler( &self ) -> Vector<T, 3> {
+        let q = self.normalize();
+        let two = T::two();
+        let roll = ( two * ( q.w * q.x + q.y * q.z ) ).atan2( T::one() - two * ( q.x * q.x + q.y * q.y ) );
+        let sine = two * ( q.w * q.y - q.z * q.x );
+        let pitch = if sine.abs() >= T::one() {
+            T::from_f64( std::f64::consts::FRAC_PI_2 ).copysign( sine )
+        } else {
+            sine.asin()
+        };
+        let yaw = ( two * ( q.w * q.z + q.x * q.y ) ).atan2( T::one() - two * ( q.y * q.y + q.z * q.z ) );
+        Vector::from([ roll, pitch, yaw ])
+    }
+
+    pub fn from_rotation_matrix( m: &Matrix3<T> ) -> Self {
+        let one = T::one();
+        let two = T::two();
+        let trace = m[ 0 ][ 0 ] + m[ 1 ][ 1 ] + m[ 2 ][ 2 ];
+        let q = if trace > T::zero() {
+            let s = ( trace + one ).sqrt() * two;
+            Self::new( s / two / two, ( m[ 2 ][ 1 ] - m[ 1 ][ 2 ] ) / s, ( m[ 0 ][ 2 ] - m[ 2 ][ 0 ] ) / s, ( m[ 1 ][ 0 ] - m[ 0 ][ 1 ] ) / s )
+        } else if m[ 0 ][ 0 ] > m[ 1 ][ 1 ] && m[ 0 ][ 0 ] > m[ 2 ][ 2 ] {
+            let s = ( one + m[ 0 ][ 0 ] - m[ 1 ][ 1 ] - m[ 2 ][ 2 ] ).sqrt() * two;
+            Self::new( ( m[ 2 ][ 1 ] - m[ 1 ][ 2 ] ) / s, s / two / two, ( m[ 0 ][ 1 ] + m[ 1 ][ 0 ] ) / s, ( m[ 0 ][ 2 ] + m[ 2 ][ 0 ] ) / s )
+        } else if m[ 1 ][ 1 ] > m[ 2 ][ 2 ] {
+            let s = ( one + m[ 1 ][ 1 ] - m[ 0 ][ 0 ] - m[ 2 ][ 2 ] ).sqrt() * two;
+            Self::new( ( m[ 0 ][ 2 ] - m[ 2 ][ 0 ] ) / s, ( m[ 0 ][ 1 ] + m[ 1 ][ 0 ] ) / s, s / two / two, ( m[ 1 ][ 2 ] + m[ 2 ][ 1 ] ) / s )
+        } else {
+            let s = ( one + m[ 2 ][ 2 ] - m[ 0 ][ 0 ] - m[ 1 ][ 1 ] ).sqrt() * two;
+            Self::new( ( m[ 1 ][ 0 ] - m[ 0 ][ 1 ] ) / s, ( m[ 0 ][ 2 ] + m[ 2 ][ 0 ] ) / s, ( m[ 1 ][ 2 ] + m[ 2 ][ 1 ] ) / s, s / two / two )
+        };
+        q.normalize()
+    }
+
+    pub fn to_rotation_matrix( &self ) -> Matrix3<T> {
+        let Self { w, x, y, z } = self.normalize();
+        let one = T::one();
+        let two = T::two();
+        Vector::from([
+            Vector::from([ one - two * ( y * y + z * z ), two * ( x * y - w * z ), two * ( x * z + w * y ) ]),
+            Vector::from([ two * ( x * y + w * z ), one - two * ( x * x + z * z ), two * ( y * z - w * x ) ]),
+            Vector::from([ two * ( x * z - w * y ), two * ( y * z + w * x ), one - two * ( x * x + y * y ) ])
+        ])
+    }
+
+    pub fn rotate( &self, vector: &Vector<T, 3> ) -> Vector<T, 3> {
+        let axis = self.vector();
+        let t = cross( &axis, vector ) * T::two();
+        *vector + t * self.w + cross( &axis, &t )
+    }
+
+    /// Advances the orientation by a world frame angular velocity held for `time_step`.
+    pub fn integrate( &self, angular_velocity: &Vector<T, 3>, time_step: T ) -> Self {
+        ( Self::from_rotation_vector( &( *angular_velocity * time_step ) ) * *self ).normalize()
+    }
+}
+
+impl<T> Default for Quaternion<T>
+where
+    T: Scalar
+{
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl<T> Mul for Quaternion<T>
+where
+    T: Scalar
+{
+    type Output = Self;
+
+    fn mul( self, rhs: Self ) -> Self::Output {
+        Self::new(
+            self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+
+    fn assert_close( a: &Vector<f64, 3>, b: &Vector<f64, 3> ) {
+        for i in 0..3 {
+            assert!( ( a[ i ] - b[ i ] ).abs() < 1e-9, "{:?} != {:?}", a, b );
+        }
+    }
+
+    #[test]
+    fn conversion_test() {
+        let q = Quaternion::from_euler( 0.3, -0.7, 1.9 );
+        assert_close( &q.to_euler(), &Vector3::from([ 0.3, -0.7, 1.9 ]) );
+
+        let m = Quaternion::from_rotation_matrix( &q.to_rotation_matrix() );
+        assert_close( &m.to_rotation_vector(), &q.to_rotation_vector() );
+
+        let ( axis, angle ) = q.to_axis_angle();
+        assert_close( &Quaternion::from_axis_angle( &axis, angle ).to_rotation_vector(), &q.to_rotation_vector() );
+
+        let v = Vector3::from([ 1.0, 2.0, 3.0 ]);
+        assert_close( &q.rotate( &v ), &matrix_vector( &q.to_rotation_matrix(), &v ) );
+
+        let m = q.to_rotation_matrix();
+        let inverse = inverse( &m ).unwrap();
+        for i in 0..3 {
+            assert_close( &inverse[ i ], &transpose( &m )[ i ] );
+        }
+    }
+
+    #[test]
+    fn inverse_test() {
+        // Inertia of a 1 cm, 10 g sphere: tiny, but perfectly conditioned.
+        let small = Vector3::from([ Vector3::from([ 4e-7, 0.0, 0.0 ]), Vector3::from([ 0.0, 4e-7, 0.0 ]), Vector3::from([ 0.0, 0.0, 4e-7 ]) ]);
+        assert!( ( inverse( &small ).unwrap()[ 1 ][ 1 ] - 2.5e6 ).abs() < 1e-3 );
+        let rod = Vector3::from([ Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::from([ 0.0, 1.0, 0.0 ]), Vector3::default() ]);
+        assert!( inverse( &rod ).is_none() );
+        assert!( inverse( &Matrix3::<f64>::default() ).is_none() );
+    }
+
+    #[test]
+    fn compose_test() {
+        let quarter = std::f64::consts::FRAC_PI_2;
+        let rotation = compose( &Vector3::from([ quarter, 0.0, 0.0 ]), &Vector3::from([ 0.0, quarter, 0.0 ]) );
+        let expected = Quaternion::from_euler( 0.0, quarter, 0.0 ) * Quaternion::from_euler( quarter, 0.0, 0.0 );
+        assert_close( &rotation, &expected.to_rotation_vector() );
+        assert_close( &rotate( &rotation, &Vector3::from([ 0.0, 1.0, 0.0 ]) ), &Vector3::from([ 1.0, 0.0, 0.0 ]) );
+    }
+}
@@ -0,0 +1,20 @@
+// Copyright 2024 Bewusstsein Labs
+
+use std::{
+    fmt::Debug,
+    ops::{ AddAssign, SubAssign, MulAssign, DivAssign }
+};
+
+use num::{ Float, NumCast };
+
+pub trait Scalar: 'static + Default + Copy + Debug + Float + AddAssign + SubAssign + MulAssign + DivAssign {
+    fn two() -> Self { Self::one() + Self::one() }
+    fn half() -> Self { Self::one() / Self::two() }
+    fn from_usize( value: usize ) -> Self { <Self as NumCast>::from( value ).unwrap() }
+    fn from_f64( value: f64 ) -> Self { <Self as NumCast>::from( value ).unwrap() }
+}
+
+impl<T> Scalar for T
+where
+    T: 'static + Default + Copy + Debug + Float + AddAssign + SubAssign + MulAssign + DivAssign
+{}
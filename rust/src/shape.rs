@@ -0,0 +1,305 @@
+// Copyright 2024 Bewusstsein Labs
+
+//! Convex sets a constrained vector can be confined to beyond the axis aligned box of its `Range`s, each
+//! with the Euclidean projection onto it.
+
+use std::fmt::Debug;
+
+use linear_algebra::vector::Vector;
+
+use crate::{
+    scalar::Scalar,
+    rotation
+};
+
+/// Sweeps over the faces of a `Polytope` before its projection gives up.
+const MAX_SWEEPS: usize = 1000;
+
+/// Norm measuring the radius of a `ConstraintShape::Ball`.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub enum Norm {
+    /// Sum of the absolute components, e.g. a total effort budget.
+    L1,
+    /// Euclidean length, e.g. a maximum speed.
+    #[default]
+    L2,
+    /// Largest absolute component, the same box a `Range` on every axis gives.
+    Infinity
+}
+
+/// The set `normal · x <= offset`.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct HalfSpace<T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    normal: Vector<T, DIM>,
+    offset: T
+}
+
+impl<T, const DIM: usize> HalfSpace<T, DIM>
+where
+    T: Scalar
+{
+    pub fn new( normal: Vector<T, DIM>, offset: T ) -> Self {
+        Self { normal, offset }
+    }
+
+    pub fn normal( &self ) -> &Vector<T, DIM> { &self.normal }
+    pub fn offset( &self ) -> &T { &self.offset }
+
+    /// Distance of `x` beyond the boundary in multiples of the normal's length, zero or negative inside.
+    pub fn excess( &self, x: &Vector<T, DIM> ) -> T {
+        rotation::dot( &self.normal, x ) - self.offset
+    }
+
+    pub fn project( &self, x: &Vector<T, DIM> ) -> Vector<T, DIM> {
+        let excess = self.excess( x );
+        let length = rotation::dot( &self.normal, &self.normal );
+        if excess <= T::zero() || length == T::zero() {
+            *x
+        } else {
+            *x - self.normal * ( excess / length )
+        }
+    }
+}
+
+/// Intersection of half-spaces.
+#[derive( Clone, Default, Debug, PartialEq )]
+pub struct Polytope<T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    faces: Vec<HalfSpace<T, DIM>>
+}
+
+/// Result of `Polytope::project`.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct Projection<T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    point: Vector<T, DIM>,
+    sweeps: usize,
+    converged: bool
+}
+
+impl<T, const DIM: usize> Projection<T, DIM>
+where
+    T: 'static + Default + Copy + Debug
+{
+    pub fn point( &self ) -> &Vector<T, DIM> { &self.point }
+    pub fn sweeps( &self ) -> usize { self.sweeps }
+    /// Whether the sweeps settled on a point of the polytope, false e.g. for an empty polytope.
+    pub fn converged( &self ) -> bool { self.converged }
+}
+
+impl<T, const DIM: usize> Polytope<T, DIM>
+where
+    T: Scalar
+{
+    pub fn new( faces: &[HalfSpace<T, DIM>] ) -> Self {
+        Self { faces: faces.to_vec() }
+    }
+
+    pub fn faces( &self ) -> &[HalfSpace<T, DIM>] { &self.faces }
+
+    /// Nearest point of the polytope by Dykstra's alternating projections onto its faces, giving up
+    /// after `MAX_SWEEPS` sweeps with the last point reached.
+    pub fn project( &self, x: &Vector<T, DIM> ) -> Projection<T, DIM> {
+        let mut point = *x;
+        let mut increments = vec![ Vector::<T, DIM>::default(); self.faces.len() ];
+        for sweep in 1..=MAX_SWEEPS {
+            let previous = point;
+            for ( face, increment ) in self.faces.iter().zip( increments.iter_mut() ) {
+                let shifted = point + *increment;
+                point = face.project( &shifted );
+                *increment = shifted - point;
+            }
+            if rotation::norm( &( point - previous ) ) <= T::epsilon() * ( T::one() + rotation::norm( &point ) ) {
+                let tolerance = T::epsilon().sqrt() * ( T::one() + rotation::norm( &point ) );
+                let converged = self.faces.iter().all( |face| face.excess( &point ) <= tolerance * ( T::one() + rotation::norm( face.normal() ) ) );
+                return Projection { point, sweeps: sweep, converged };
+            }
+        }
+        Projection { point, sweeps: MAX_SWEEPS, converged: false }
+    }
+}
+
+/// Convex set a joint or link confines a constrained vector to after clamping its ranges. It acts like a hard
+/// limit: the vector is projected back and its derivatives are left untouched.
+#[derive( Clone, Debug, PartialEq )]
+pub enum ConstraintShape<T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    /// Points within `radius` of `center` in `norm`.
+    Ball { center: Vector<T, DIM>, radius: T, norm: Norm },
+    /// Circular cone with its apex at the origin, opening around `axis` by `half_angle`, which must be
+    /// below a right angle; see `cone`.
+    Cone { axis: Vector<T, DIM>, half_angle: T },
+    HalfSpace( HalfSpace<T, DIM> ),
+    Polytope( Polytope<T, DIM> )
+}
+
+impl<T, const DIM: usize> ConstraintShape<T, DIM>
+where
+    T: Scalar
+{
+    /// Euclidean ball of `radius` about the origin, e.g. a limit on speed rather than on each velocity
+    /// component.
+    pub fn magnitude( radius: T ) -> Self {
+        Self::Ball { center: Vector::default(), radius, norm: Norm::L2 }
+    }
+
+    /// Cone around `axis` opening by `half_angle`, `None` unless the axis is non-zero and the angle lies in
+    /// `[ 0, π / 2 )`.
+    pub fn cone( axis: Vector<T, DIM>, half_angle: T ) -> Option<Self> {
+        let valid = rotation::norm( &axis ) > T::zero() && half_angle >= T::zero() && half_angle < T::from_f64( std::f64::consts::FRAC_PI_2 );
+        valid.then_some( Self::Cone { axis, half_angle } )
+    }
+
+    pub fn contains( &self, x: &Vector<T, DIM> ) -> bool {
+        self.project( x ) == *x
+    }
+
+    /// Nearest point of the shape to `x`, `x` itself if it is inside. A polytope whose projection does not
+    /// converge gives the last point reached.
+    pub fn project( &self, x: &Vector<T, DIM> ) -> Vector<T, DIM> {
+        match self {
+            Self::Ball { center, radius, norm } => *center + project_ball( &( *x - *center ), *radius, *norm ),
+            Self::Cone { axis, half_angle } => project_cone( x, axis, *half_angle ),
+            Self::HalfSpace( half_space ) => half_space.project( x ),
+            Self::Polytope( polytope ) => *polytope.project( x ).point()
+        }
+    }
+}
+
+fn project_ball<T, const DIM: usize>( x: &Vector<T, DIM>, radius: T, norm: Norm ) -> Vector<T, DIM>
+where
+    T: Scalar
+{
+    let mut result = *x;
+    match norm {
+        Norm::L2 => {
+            let length = rotation::norm( x );
+            if length > radius {
+                result = *x * ( radius / length );
+            }
+        },
+        Norm::Infinity => {
+            for i in 0..DIM {
+                result[ i ] = x[ i ].max( -radius ).min( radius );
+            }
+        },
+        Norm::L1 => {
+            if ( 0..DIM ).fold( T::zero(), |sum, i| sum + x[ i ].abs() ) <= radius {
+                return result;
+            }
+            // Soft threshold every component by the level that brings the sum of magnitudes to `radius`.
+            let mut magnitudes: Vec<T> = ( 0..DIM ).map( |i| x[ i ].abs() ).collect();
+            magnitudes.sort_by( |a, b| b.partial_cmp( a ).unwrap_or( std::cmp::Ordering::Equal ) );
+            let mut sum = T::zero();
+            let mut threshold = T::zero();
+            for ( k, magnitude ) in magnitudes.iter().enumerate() {
+                sum += *magnitude;
+                let level = ( sum - radius ) / T::from_usize( k + 1 );
+                if *magnitude > level {
+                    threshold = level;
+                }
+            }
+            for i in 0..DIM {
+                let magnitude = ( x[ i ].abs() - threshold ).max( T::zero() );
+                result[ i ] = if x[ i ] < T::zero() { -magnitude } else { magnitude };
+            }
+        }
+    }
+    result
+}
+
+fn project_cone<T, const DIM: usize>( x: &Vector<T, DIM>, axis: &Vector<T, DIM>, half_angle: T ) -> Vector<T, DIM>
+where
+    T: Scalar
+{
+    let length = rotation::norm( axis );
+    if length == T::zero() {
+        return *x;
+    }
+    let unit = *axis * ( T::one() / length );
+    let along = rotation::dot( x, &unit );
+    let radial = *x - unit * along;
+    let distance = rotation::norm( &radial );
+    let slope = half_angle.tan();
+    if distance <= along * slope {
+        *x
+    } else if distance * slope <= -along {
+        // Inside the polar cone the apex is nearest.
+        Vector::default()
+    } else {
+        let height = ( along + slope * distance ) / ( T::one() + slope * slope );
+        unit * height + radial * ( height * slope / distance )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+
+    fn close<const DIM: usize>( a: &Vector<f64, DIM>, b: [f64; DIM] ) -> bool {
+        ( 0..DIM ).all( |i| ( a[ i ] - b[ i ] ).abs() < 1e-9 )
+    }
+
+    #[test]
+    fn ball_test() {
+        let speed = ConstraintShape::magnitude( 1.0 );
+        assert!( close( &speed.project( &Vector3::from([ 3.0, 0.0, 4.0 ]) ), [ 0.6, 0.0, 0.8 ] ) );
+        assert!( speed.contains( &Vector3::from([ 0.5, 0.5, 0.5 ]) ) );
+
+        let l1 = ConstraintShape::Ball { center: Vector::default(), radius: 1.0, norm: Norm::L1 };
+        assert!( close( &l1.project( &Vector::from([ 1.0, 1.0 ]) ), [ 0.5, 0.5 ] ) );
+        assert!( close( &l1.project( &Vector::from([ -2.0, 0.5 ]) ), [ -1.0, 0.0 ] ) );
+
+        let square = ConstraintShape::Ball { center: Vector::from([ 1.0, 0.0 ]), radius: 1.0, norm: Norm::Infinity };
+        assert!( close( &square.project( &Vector::from([ 3.0, -0.5 ]) ), [ 2.0, -0.5 ] ) );
+    }
+
+    #[test]
+    fn cone_test() {
+        let cone = ConstraintShape::cone( Vector3::from([ 0.0, 0.0, 2.0 ]), std::f64::consts::FRAC_PI_4 ).unwrap();
+        assert!( cone.contains( &Vector3::from([ 0.5, 0.0, 1.0 ]) ) );
+        assert!( close( &cone.project( &Vector3::from([ 2.0, 0.0, 0.0 ]) ), [ 1.0, 0.0, 1.0 ] ) );
+        assert!( close( &cone.project( &Vector3::from([ 0.1, 0.0, -1.0 ]) ), [ 0.0, 0.0, 0.0 ] ) );
+        assert!( ConstraintShape::cone( Vector3::from([ 0.0, 0.0, 1.0 ]), std::f64::consts::FRAC_PI_2 ).is_none() );
+        assert!( ConstraintShape::cone( Vector3::default(), 0.5 ).is_none() );
+    }
+
+    #[test]
+    fn polytope_test() {
+        let half_space = HalfSpace::new( Vector::from([ 1.0, 1.0 ]), 1.0 );
+        assert!( close( &half_space.project( &Vector::from([ 1.0, 1.0 ]) ), [ 0.5, 0.5 ] ) );
+
+        // Triangle x >= 0, y >= 0, x + y <= 1.
+        let triangle = ConstraintShape::Polytope( Polytope::new( &[
+            HalfSpace::new( Vector::from([ -1.0, 0.0 ]), 0.0 ),
+            HalfSpace::new( Vector::from([ 0.0, -1.0 ]), 0.0 ),
+            half_space
+        ] ) );
+        assert!( close( &triangle.project( &Vector::from([ 2.0, -1.0 ]) ), [ 1.0, 0.0 ] ) );
+        assert!( close( &triangle.project( &Vector::from([ 1.0, 1.0 ]) ), [ 0.5, 0.5 ] ) );
+        assert!( close( &triangle.project( &Vector::from([ 0.2, 0.3 ]) ), [ 0.2, 0.3 ] ) );
+
+        // Any number of faces, e.g. a 32-gon around a unit circle.
+        let faces: Vec<_> = ( 0..32 ).map( |k| {
+            let angle = k as f64 * std::f64::consts::TAU / 32.0;
+            HalfSpace::new( Vector::from([ angle.cos(), angle.sin() ]), 1.0 )
+        } ).collect();
+        let projection = Polytope::new( &faces ).project( &Vector::from([ 2.0, 0.0 ]) );
+        assert!( projection.converged() );
+        assert!( close( projection.point(), [ 1.0, 0.0 ] ) );
+
+        // Disjoint faces never settle on a point of the polytope.
+        let empty = Polytope::new( &[ HalfSpace::new( Vector::from([ 1.0, 0.0 ]), -1.0 ), HalfSpace::new( Vector::from([ -1.0, 0.0 ]), -1.0 ) ] );
+        assert!( !empty.project( &Vector::from([ 0.0, 0.0 ]) ).converged() );
+    }
+}
@@ -0,0 +1,311 @@
+// Copyright 2024 Bewusstsein Labs
+
+//! Spatial vector algebra for tree dynamics. Motions and wrenches are expressed in world axes and
+//! referred to the world origin, so quantities of different bodies can be added without transforms.
+//! Their flat form lists the rotational components before the translational ones.
+
+use std::fmt::Debug;
+
+use linear_algebra::vector::Vector;
+
+use crate::{
+    scalar::Scalar,
+    constraint::Constraint,
+    body::Body,
+    kinematics::{ Pose, Motion },
+    linkage::{ Linkage, Error },
+    force::Wrench,
+    dense::DenseMatrix,
+    rotation
+};
+
+/// Number of components of a spatial vector in `dim` dimensions.
+pub const fn spatial_dimension( dim: usize ) -> usize {
+    rotation::angular_dimension( dim ) + dim
+}
+
+impl<T, const DIM: usize> Motion<T, DIM>
+where
+    T: Scalar
+{
+    /// Spatial cross product with another motion, `self ×ₘ other`.
+    pub fn cross( &self, other: &Self ) -> Self {
+        Self::new(
+            rotation::angular_tangent( &self.angular, &other.angular ),
+            rotation::tangent( &self.angular, &other.linear ) - rotation::tangent( &other.angular, &self.linear )
+        )
+    }
+
+    /// Spatial cross product with a wrench, `self ×* wrench`.
+    pub fn cross_force( &self, wrench: &Wrench<T, DIM> ) -> Wrench<T, DIM> {
+        Wrench::new(
+            rotation::tangent( &self.angular, &wrench.force ),
+            rotation::angular_tangent( &self.angular, &wrench.torque ) + rotation::moment( &self.linear, &wrench.force )
+        )
+    }
+
+    /// Power delivered by `wrench` on this motion.
+    pub fn power( &self, wrench: &Wrench<T, DIM> ) -> T {
+        rotation::dot( &self.angular, &wrench.torque ) + rotation::dot( &self.linear, &wrench.force )
+    }
+
+    pub fn to_vec( &self ) -> Vec<T> {
+        ( 0..rotation::angular_dimension( DIM ) ).map( |i| self.angular[ i ] ).chain( ( 0..DIM ).map( |i| self.linear[ i ] ) ).collect()
+    }
+
+    pub fn from_slice( values: &[T] ) -> Self {
+        let offset = rotation::angular_dimension( DIM );
+        let mut motion = Self::default();
+        for i in 0..offset {
+            motion.angular[ i ] = values[ i ];
+        }
+        for i in 0..DIM {
+            motion.linear[ i ] = values[ offset + i ];
+        }
+        motion
+    }
+}
+
+impl<T, const DIM: usize> Wrench<T, DIM>
+where
+    T: Scalar
+{
+    /// Moment and force of the wrench referred to `point` instead of the world origin.
+    pub fn at( &self, point: &Vector<T, DIM> ) -> Self {
+        Self::new( self.force, self.torque - rotation::moment( point, &self.force ) )
+    }
+
+    pub fn to_vec( &self ) -> Vec<T> {
+        ( 0..rotation::angular_dimension( DIM ) ).map( |i| self.torque[ i ] ).chain( ( 0..DIM ).map( |i| self.force[ i ] ) ).collect()
+    }
+
+    pub fn from_slice( values: &[T] ) -> Self {
+        let offset = rotation::angular_dimension( DIM );
+        let mut wrench = Self::default();
+        for i in 0..offset {
+            wrench.torque[ i ] = values[ i ];
+        }
+        for i in 0..DIM {
+            wrench.force[ i ] = values[ offset + i ];
+        }
+        wrench
+    }
+}
+
+/// Mass, world centre of mass and inertia tensor about it in world axes.
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct RigidInertia<T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    mass: T,
+    center: Vector<T, DIM>,
+    rotational: Vector<Vector<T, DIM>, DIM>
+}
+
+impl<T, const DIM: usize> RigidInertia<T, DIM>
+where
+    T: Scalar
+{
+    pub fn new( mass: T, center: Vector<T, DIM>, rotational: Vector<Vector<T, DIM>, DIM> ) -> Self {
+        Self { mass, center, rotational }
+    }
+
+    /// Inertia of `body` when its origin is placed at `pose`.
+    pub fn of<const ORD: usize>( body: &Body<T, DIM, ORD>, pose: &Pose<T, DIM> ) -> Self
+    where
+        [(); ORD + 1]:
+    {
+        Self::new( *body.mass(), pose.transform_point( body.center_of_mass() ), body.inertia_at( pose.rotation() ) )
+    }
+
+    pub fn mass( &self ) -> &T { &self.mass }
+    pub fn center( &self ) -> &Vector<T, DIM> { &self.center }
+    pub fn rotational( &self ) -> &Vector<Vector<T, DIM>, DIM> { &self.rotational }
+
+    /// Momentum of the body moving with `motion`.
+    pub fn apply( &self, motion: &Motion<T, DIM> ) -> Wrench<T, DIM> {
+        let linear = ( motion.linear + rotation::tangent( &motion.angular, &self.center ) ) * self.mass;
+        let mut angular = Vector::<T, DIM>::default();
+        for i in 0..DIM {
+            for j in 0..DIM {
+                angular[ i ] += self.rotational[ i ][ j ] * motion.angular[ j ];
+            }
+        }
+        Wrench::new( linear, angular + rotation::moment( &self.center, &linear ) )
+    }
+
+    /// Flat spatial inertia matrix mapping flat motions onto flat momenta.
+    pub fn matrix( &self ) -> DenseMatrix<T> {
+        let size = spatial_dimension( DIM );
+        let columns: Vec<Vec<T>> = ( 0..size ).map( |j| {
+            let mut unit = vec![ T::zero(); size ];
+            unit[ j ] = T::one();
+            self.apply( &Motion::from_slice( &unit ) ).to_vec()
+        } ).collect();
+        ( 0..size ).map( |i| columns.iter().map( |column| column[ i ] ).collect() ).collect()
+    }
+}
+
+/// Snapshot of a tree shaped linkage for the dynamics algorithms: joints in breadth first order from the
+/// root, the parent index of each, their motion subspaces and inertias in world coordinates at the
+/// current joint coordinates, and their coordinate rates.
+#[derive( Clone, Debug, PartialEq )]
+pub struct TreeModel<I, T, const DIM: usize>
+where
+    T: 'static + Default + Copy + Debug
+{
+    ids: Vec<I>,
+    parents: Vec<Option<usize>>,
+    subspaces: Vec<Vec<Motion<T, DIM>>>,
+    inertias: Vec<RigidInertia<T, DIM>>,
+    rates: Vec<Vec<T>>
+}
+
+impl<I, T, const DIM: usize> TreeModel<I, T, DIM>
+where
+    I: Copy + PartialEq,
+    T: Scalar
+{
+    pub fn ids( &self ) -> &[I] { &self.ids }
+    pub fn parents( &self ) -> &[Option<usize>] { &self.parents }
+    pub fn subspaces( &self ) -> &[Vec<Motion<T, DIM>>] { &self.subspaces }
+    pub fn inertias( &self ) -> &[RigidInertia<T, DIM>] { &self.inertias }
+    pub fn rates( &self ) -> &[Vec<T>] { &self.rates }
+
+    pub fn len( &self ) -> usize { self.ids.len() }
+    pub fn is_empty( &self ) -> bool { self.ids.is_empty() }
+
+    pub fn index( &self, id: &I ) -> Option<usize> {
+        self.ids.iter().position( |other| other == id )
+    }
+
+    /// Offset of each joint's coordinates in the stacked coordinate vector, and the total.
+    pub fn offsets( &self ) -> ( Vec<usize>, usize ) {
+        let mut offsets = Vec::with_capacity( self.len() );
+        let mut total = 0;
+        for subspace in self.subspaces.iter() {
+            offsets.push( total );
+            total += subspace.len();
+        }
+        ( offsets, total )
+    }
+
+    /// Velocity of every joint body, with the rate dependent part of each acceleration `Ṡ q̇`.
+    ///
+    /// Subspace columns are differentiated in the order they are listed, each carried by the parent's
+    /// velocity and the earlier columns of the same joint.
+    pub fn velocities( &self ) -> ( Vec<Motion<T, DIM>>, Vec<Motion<T, DIM>> ) {
+        let mut velocities: Vec<Motion<T, DIM>> = Vec::with_capacity( self.len() );
+        let mut biases = Vec::with_capacity( self.len() );
+        for i in 0..self.len() {
+            let mut velocity = self.parents[ i ].map_or( Motion::default(), |parent| velocities[ parent ] );
+            let mut bias = Motion::default();
+            for ( column, rate ) in self.subspaces[ i ].iter().zip( self.rates[ i ].iter() ) {
+                let joint = *column * *rate;
+                bias = bias + velocity.cross( &joint );
+                velocity = velocity + joint;
+            }
+            velocities.push( velocity );
+            biases.push( bias );
+        }
+        ( velocities, biases )
+    }
+
+    /// Acceleration of every joint body, without gravity, given the coordinate accelerations of each
+    /// joint in model order.
+    pub fn accelerations( &self, coordinate_accelerations: &[Vec<T>] ) -> Vec<Motion<T, DIM>> {
+        let ( _, biases ) = self.velocities();
+        let mut accelerations: Vec<Motion<T, DIM>> = Vec::with_capacity( self.len() );
+        for i in 0..self.len() {
+            let mut acceleration = self.parents[ i ].map_or( Motion::default(), |parent| accelerations[ parent ] ) + biases[ i ];
+            for ( column, value ) in self.subspaces[ i ].iter().zip( coordinate_accelerations[ i ].iter() ) {
+                acceleration = acceleration + *column * *value;
+            }
+            accelerations.push( acceleration );
+        }
+        accelerations
+    }
+}
+
+impl <I, T, const DIM: usize, const ORD: usize> Linkage<I, T, DIM, ORD>
+where
+    I: 'static + Default + Copy + Debug + Ord,
+    T: Scalar,
+    [Constraint<T, DIM>; (ORD + 1) * 2]: Default,
+    [(); ORD + 1]:,
+    [(); (ORD + 1) * 2]:
+{
+    /// Tree model of the joints reachable from `root`, with `root` placed at `base`. Links closing loops
+    /// are ignored.
+    pub fn tree_model( &self, root: I, base: Pose<T, DIM> ) -> Result<TreeModel<I, T, DIM>, Error> {
+        let kinematics = self.forward_kinematics( root, base )?;
+        let ids = kinematics.tree().order().to_vec();
+        let mut parents = Vec::with_capacity( ids.len() );
+        let mut subspaces = Vec::with_capacity( ids.len() );
+        let mut inertias = Vec::with_capacity( ids.len() );
+        let mut rates = Vec::with_capacity( ids.len() );
+        for id in ids.iter() {
+            let joint = self.get_joint( *id ).ok_or( Error::JointNotFound )?;
+            let pose = kinematics.pose( id ).ok_or( Error::JointNotFound )?;
+            let frame = pose.compose( &joint.displacement().inverse() );
+            let origin = Vector::default() - *pose.position();
+            parents.push( kinematics.tree().parent( id ).and_then( |parent| ids.iter().position( |other| other == parent ) ) );
+            subspaces.push( joint.motion_subspace().iter().map( |motion| {
+                Motion::new( frame.transform_angular( &motion.angular ), frame.transform_vector( &motion.linear ) ).shift( &origin )
+            } ).collect() );
+            inertias.push( RigidInertia::of( joint, pose ) );
+            let mut joint_rates = joint.rates().to_vec();
+            joint_rates.resize( joint.degrees_of_freedom(), T::zero() );
+            rates.push( joint_rates );
+        }
+        Ok( TreeModel { ids, parents, subspaces, inertias, rates } )
+    }
+
+    /// Writes the world velocity of every tree joint from `root`, placed at `base`, from the joint rates,
+    /// returning the model it came from. The joint poses must already be those of
+    /// `apply_forward_kinematics`.
+    pub fn apply_tree_velocities( &mut self, root: I, base: Pose<T, DIM> ) -> Result<TreeModel<I, T, DIM>, Error> {
+        let model = self.tree_model( root, base )?;
+        if ORD >= 1 {
+            let ( velocities, _ ) = model.velocities();
+            for ( id, velocity ) in model.ids().iter().zip( velocities.iter() ) {
+                let Some( joint ) = self.get_joint_mut( *id ) else { continue };
+                let position = *joint.position();
+                joint.spatial[ 1 ] = velocity.shift( &position ).linear;
+                joint.angular[ 1 ] = velocity.angular;
+            }
+        }
+        Ok( model )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+
+    #[test]
+    fn cross_test() {
+        let v = Motion::new( Vector3::from([ 0.1, -0.4, 0.9 ]), Vector3::from([ 1.0, 2.0, -0.5 ]) );
+        let m = Motion::new( Vector3::from([ -0.3, 0.2, 0.5 ]), Vector3::from([ 0.7, -1.1, 0.4 ]) );
+        let f = Wrench::new( Vector3::from([ 0.2, 0.3, -0.6 ]), Vector3::from([ 1.5, -0.2, 0.1 ]) );
+        // Duality: ( v ×ₘ m ) · f = -m · ( v ×* f ).
+        assert!( ( v.cross( &m ).power( &f ) + m.power( &v.cross_force( &f ) ) ).abs() < 1e-12 );
+
+        let inertia = RigidInertia::new( 2.0, Vector3::from([ 0.5, 0.0, 1.0 ]), Vector::from([
+            Vector3::from([ 0.3, 0.0, 0.0 ]),
+            Vector3::from([ 0.0, 0.2, 0.0 ]),
+            Vector3::from([ 0.0, 0.0, 0.1 ])
+        ]) );
+        let matrix = inertia.matrix();
+        for i in 0..6 {
+            for j in 0..6 {
+                assert!( ( matrix[ i ][ j ] - matrix[ j ][ i ] ).abs() < 1e-12 );
+            }
+        }
+        // Kinetic energy of a pure translation is m v² / 2.
+        let translation = Motion::new( Vector3::default(), Vector3::from([ 1.0, 2.0, 2.0 ]) );
+        assert!( ( translation.power( &inertia.apply( &translation ) ) - 18.0 ).abs() < 1e-12 );
+    }
+}
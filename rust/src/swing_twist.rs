@@ -0,0 +1,193 @@
+// Copyright 2024 Bewusstsein Labs
+
+//! Orientation limits of spherical joints. The relative orientation is split into a twist about the
+//! bone axis and a swing of that axis; the swing is bounded by an elliptical cone and the twist by a
+//! range, which per axis ranges on a rotation vector cannot express.
+
+use std::fmt::Debug;
+
+use linear_algebra::vector::Vector;
+
+use crate::{
+    scalar::Scalar,
+    constraint::Range,
+    rotation::{ self, Quaternion }
+};
+
+/// Bisection steps locating the nearest point of the swing ellipse.
+const BISECTIONS: usize = 100;
+
+#[derive( Clone, Copy, Default, Debug, PartialEq )]
+pub struct SwingTwist<T>
+where
+    T: 'static + Default + Copy + Debug
+{
+    axis: Vector<T, 3>,
+    reference: Vector<T, 3>,
+    swing: [T; 2],
+    twist: Range<T>
+}
+
+impl<T> SwingTwist<T>
+where
+    T: Scalar
+{
+    /// Limit on rotations about the bone `axis`. `swing` holds the largest swing angles about `reference`
+    /// and about `axis × reference`, the semi-axes of the elliptical swing cone; `reference` is made
+    /// perpendicular to `axis`. `None` if `axis` is zero or `reference` is zero or parallel to it.
+    pub fn new( axis: Vector<T, 3>, reference: Vector<T, 3>, swing: [T; 2], twist: Range<T> ) -> Option<Self> {
+        let length = rotation::norm( &axis );
+        if !( length > T::zero() ) || !length.is_finite() {
+            return None;
+        }
+        let axis = axis / length;
+        let perpendicular = reference - axis * rotation::dot( &reference, &axis );
+        let length = rotation::norm( &perpendicular );
+        if !( length > T::epsilon().sqrt() * rotation::norm( &reference ) ) || !length.is_finite() {
+            return None;
+        }
+        Some( Self { axis, reference: perpendicular / length, swing, twist } )
+    }
+
+    pub fn axis( &self ) -> &Vector<T, 3> { &self.axis }
+    pub fn reference( &self ) -> &Vector<T, 3> { &self.reference }
+    pub fn swing( &self ) -> &[T; 2] { &self.swing }
+    pub fn twist( &self ) -> &Range<T> { &self.twist }
+
+    /// Swing angles about `reference` and `axis × reference`, and the twist angle in `[-π, π]`, of the
+    /// rotation vector `rotation`.
+    pub fn decompose( &self, rotation: &Vector<T, 3> ) -> ( [T; 2], T ) {
+        let ( swing, twist ) = Quaternion::from_rotation_vector( rotation ).swing_twist( &self.axis );
+        let swing = swing.to_rotation_vector();
+        let twist = twist.to_rotation_vector();
+        let normal = rotation::cross( &self.axis, &self.reference );
+        ( [ rotation::dot( &swing, &self.reference ), rotation::dot( &swing, &normal ) ], rotation::dot( &twist, &self.axis ) )
+    }
+
+    /// Rotation vector of the swing angles and twist angle, the inverse of `decompose`.
+    pub fn compose( &self, swing: [T; 2], twist: T ) -> Vector<T, 3> {
+        let normal = rotation::cross( &self.axis, &self.reference );
+        let swing = Quaternion::from_rotation_vector( &( self.reference * swing[ 0 ] + normal * swing[ 1 ] ) );
+        ( swing * Quaternion::from_axis_angle( &self.axis, twist ) ).to_rotation_vector()
+    }
+
+    pub fn contains( &self, rotation: &Vector<T, 3> ) -> bool {
+        let ( swing, twist ) = self.decompose( rotation );
+        let mut clamped = twist;
+        self.twist.clamp( &mut clamped );
+        clamped == twist && inside_ellipse( &swing, &self.swing )
+    }
+
+    /// Moves `rotation` back onto the allowed region: the twist is clamped into its range and the swing
+    /// onto the nearest point of the ellipse. Allowed rotations are returned unchanged.
+    pub fn project( &self, rotation: &Vector<T, 3> ) -> Vector<T, 3> {
+        let ( swing, twist ) = self.decompose( rotation );
+        let mut clamped = twist;
+        self.twist.clamp( &mut clamped );
+        if clamped == twist && inside_ellipse( &swing, &self.swing ) {
+            return *rotation;
+        }
+        self.compose( nearest_on_ellipse( &swing, &self.swing ), clamped )
+    }
+}
+
+/// Whether `point` is inside the ellipse with semi-axes `semi_axes`, a zero semi-axis allowing only zero.
+fn inside_ellipse<T>( point: &[T; 2], semi_axes: &[T; 2] ) -> bool
+where
+    T: Scalar
+{
+    let mut sum = T::zero();
+    for i in 0..2 {
+        if semi_axes[ i ] == T::zero() {
+            if point[ i ] != T::zero() {
+                return false;
+            }
+        } else {
+            sum += ( point[ i ] / semi_axes[ i ] ).powi( 2 );
+        }
+    }
+    sum <= T::one()
+}
+
+/// Nearest point of the ellipse to `point` outside it, `x_i = e_i² y_i / ( t + e_i² )` with `t` found by
+/// bisection so that `x` lies on the boundary.
+fn nearest_on_ellipse<T>( point: &[T; 2], semi_axes: &[T; 2] ) -> [T; 2]
+where
+    T: Scalar
+{
+    let nearest = |t: T| -> [T; 2] {
+        let mut x = [ T::zero(); 2 ];
+        for i in 0..2 {
+            let square = semi_axes[ i ] * semi_axes[ i ];
+            if square != T::zero() {
+                x[ i ] = square * point[ i ] / ( t + square );
+            }
+        }
+        x
+    };
+    let level = |t: T| -> T {
+        let x = nearest( t );
+        ( 0..2 ).filter( |i| semi_axes[ *i ] != T::zero() ).fold( T::zero(), |sum, i| sum + ( x[ i ] / semi_axes[ i ] ).powi( 2 ) )
+    };
+    let mut low = T::zero();
+    let mut high = ( ( semi_axes[ 0 ] * point[ 0 ] ).powi( 2 ) + ( semi_axes[ 1 ] * point[ 1 ] ).powi( 2 ) ).sqrt();
+    for _ in 0..BISECTIONS {
+        let middle = ( low + high ) * T::half();
+        if level( middle ) > T::one() {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    nearest( high )
+}
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+
+    fn assert_close( a: &Vector<f64, 3>, b: [f64; 3] ) {
+        for i in 0..3 {
+            assert!( ( a[ i ] - b[ i ] ).abs() < 1e-9, "{:?} != {:?}", a, b );
+        }
+    }
+
+    fn limit() -> SwingTwist<f64> {
+        SwingTwist::new( Vector3::from([ 0.0, 0.0, 2.0 ]), Vector3::from([ 1.0, 0.0, 0.5 ]), [ 0.5, 0.2 ], Range::new( -0.3, 0.6 ) ).unwrap()
+    }
+
+    #[test]
+    fn new_test() {
+        let twist = Range::new( -0.3, 0.6 );
+        assert!( SwingTwist::new( Vector3::default(), Vector3::from([ 1.0, 0.0, 0.0 ]), [ 0.5, 0.2 ], twist ).is_none() );
+        assert!( SwingTwist::new( Vector3::from([ 0.0, 0.0, 1.0 ]), Vector3::from([ 0.0, 0.0, -3.0 ]), [ 0.5, 0.2 ], twist ).is_none() );
+        assert!( SwingTwist::new( Vector3::from([ 0.0, 0.0, 1.0 ]), Vector3::default(), [ 0.5, 0.2 ], twist ).is_none() );
+        assert_close( limit().reference(), [ 1.0, 0.0, 0.0 ] );
+    }
+
+    #[test]
+    fn decompose_test() {
+        let limit = limit();
+        let rotation = Vector3::from([ 0.3, -0.1, 0.8 ]);
+        let ( swing, twist ) = limit.decompose( &rotation );
+        assert_close( &limit.compose( swing, twist ), [ 0.3, -0.1, 0.8 ] );
+        let ( swing, twist ) = limit.decompose( &Vector3::from([ 0.0, 0.0, -0.7 ]) );
+        assert!( swing[ 0 ].abs() < 1e-12 && swing[ 1 ].abs() < 1e-12 && ( twist + 0.7 ).abs() < 1e-12 );
+    }
+
+    #[test]
+    fn project_test() {
+        let limit = limit();
+        assert!( limit.contains( &Vector3::from([ 0.1, 0.1, 0.2 ]) ) );
+        assert_close( &limit.project( &Vector3::from([ 0.0, 0.0, 1.0 ]) ), [ 0.0, 0.0, 0.6 ] );
+        assert_close( &limit.project( &Vector3::from([ 1.0, 0.0, 0.0 ]) ), [ 0.5, 0.0, 0.0 ] );
+        assert_close( &limit.project( &Vector3::from([ 0.0, -1.0, 0.0 ]) ), [ 0.0, -0.2, 0.0 ] );
+
+        let projected = limit.project( &Vector3::from([ 0.6, 0.6, -1.2 ]) );
+        assert!( !limit.contains( &Vector3::from([ 0.6, 0.6, -1.2 ]) ) );
+        let ( swing, twist ) = limit.decompose( &projected );
+        assert!( ( ( swing[ 0 ] / 0.5 ).powi( 2 ) + ( swing[ 1 ] / 0.2 ).powi( 2 ) - 1.0 ).abs() < 1e-9 );
+        assert!( ( twist + 0.3 ).abs() < 1e-9 );
+    }
+}
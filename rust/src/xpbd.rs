@@ -0,0 +1,376 @@
+// Copyright 2024 Bewusstsein Labs
+
+//! Extended position based dynamics: joints are predicted forward under their accumulated forces, links
+//! and joint constraints are then satisfied by Gauss-Seidel position projection, and velocities are
+//! recovered by finite differencing the corrected positions.
+
+use std::{
+    fmt::Debug,
+    collections::{ BTreeMap, BTreeSet }
+};
+
+use linear_algebra::vector::Vector;
+use const_expr_bounds::{ Assert, IsTrue };
+
+use crate::{
+    scalar::Scalar,
+    constraint::{ Constraint, Limit },
+    joint::Quantity,
+    kinematics::Pose,
+    linkage::{ Linkage, Error },
+    rotation
+};
+
+#[derive( Clone, Copy, Debug, PartialEq )]
+pub struct Xpbd<T> {
+    substeps: usize,
+    iterations: usize,
+    compliance: T,
+    angular_compliance: T
+}
+
+impl<T> Xpbd<T>
+where
+    T: Scalar
+{
+    /// `compliance` is the inverse stiffness of the positional part of every link and
+    /// `angular_compliance` that of its rotational part; zero makes links rigid.
+    pub fn new( substeps: usize, iterations: usize, compliance: T, angular_compliance: T ) -> Self {
+        Self { substeps: substeps.max( 1 ), iterations: iterations.max( 1 ), compliance, angular_compliance }
+    }
+
+    pub fn substeps( &self ) -> &usize { &self.substeps }
+    pub fn iterations( &self ) -> &usize { &self.iterations }
+    pub fn compliance( &self ) -> &T { &self.compliance }
+    pub fn angular_compliance( &self ) -> &T { &self.angular_compliance }
+}
+
+impl<T> Default for Xpbd<T>
+where
+    T: Scalar
+{
+    fn default() -> Self {
+        Self::new( 8, 4, T::zero(), T::zero() )
+    }
+}
+
+/// XPBD update of one constraint with current violation `violation`, returning the multiplier step.
+fn multiplier<T>( violation: T, lambda: &mut T, weights: T, compliance: T, time_step: T ) -> T
+where
+    T: Scalar
+{
+    let alpha = compliance / ( time_step * time_step );
+    let denominator = weights + alpha;
+    if denominator <= T::zero() {
+        return T::zero();
+    }
+    let delta = ( violation - alpha * *lambda ) / denominator;
+    *lambda += delta;
+    delta
+}
+
+impl <I, T, const DIM: usize, const ORD: usize> Linkage<I, T, DIM, ORD>
+where
+    I: 'static + Default + Copy + Debug + Ord,
+    T: Scalar,
+    [Constraint<T, DIM>; (ORD + 1) * 2]: Default,
+    [(); ORD + 1]:,
+    [(); (ORD + 1) * 2]:
+{
+    fn inverse_mass( &self, id: I ) -> T {
+        self.get_joint( id ).map_or( T::zero(), |joint| {
+            if *joint.mass() > T::zero() { T::one() / *joint.mass() } else { T::zero() }
+        } )
+    }
+
+    /// Inverse world inertia of joint `id` applied to `direction`, zero for a massless joint.
+    fn inverse_inertia_along( &self, id: I, direction: &Vector<T, DIM> ) -> Vector<T, DIM> {
+        let mut turned = Vector::default();
+        if self.inverse_mass( id ) == T::zero() {
+            return turned;
+        }
+        if let Some( inverse ) = self.get_joint( id ).and_then( |joint| joint.inverse_world_inertia() ) {
+            for i in 0..DIM {
+                turned[ i ] = rotation::dot( &inverse[ i ], direction );
+            }
+        }
+        turned
+    }
+
+    /// Projects the link between `first` and `second` onto the relative transform it allows, sharing
+    /// positional corrections by inverse mass and rotational ones by inverse world inertia.
+    pub(crate) fn project_link( &mut self, first: I, second: I, lambdas: &mut ( T, T ), solver: &Xpbd<T>, time_step: T ) {
+        let Some( link ) = self.get_link( first, second ).cloned() else { return };
+        let ( Some( joint1 ), Some( joint2 ) ) = ( self.get_joint( first ), self.get_joint( second ) ) else { return };
+        let ( w1, w2 ) = ( self.inverse_mass( first ), self.inverse_mass( second ) );
+
+        let ( position1, rotation1 ) = ( *joint1.position(), *joint1.rotation() );
+        let ( position2, rotation2 ) = ( *joint2.position(), *joint2.rotation() );
+        let inverse = Vector::default() - rotation1;
+        let relative = rotation::rotate( &inverse, &( position2 - position1 ) );
+        let offset = link.bound( Quantity::Spatial, &( relative - *link.transform().position() ) );
+        let target = position1 + rotation::rotate( &rotation1, &( *link.transform().position() + offset ) );
+        let error = target - position2;
+        let violation = rotation::norm( &error );
+        if violation > T::epsilon() {
+            let direction = error / violation;
+            let delta = multiplier( violation, &mut lambdas.0, w1 + w2, *solver.compliance(), time_step );
+            if let Some( joint ) = self.get_joint_mut( second ) {
+                *joint.position_mut() = position2 + direction * ( delta * w2 );
+            }
+            if let Some( joint ) = self.get_joint_mut( first ) {
+                *joint.position_mut() = position1 - direction * ( delta * w1 );
+            }
+        }
+
+        let relative_rotation = rotation::compose( &rotation2, &inverse );
+        let nominal = Vector::default() - *link.transform().rotation();
+        let deviation = rotation::compose( &relative_rotation, &nominal );
+        let allowed = link.bound( Quantity::Angular, &deviation );
+        // World rotation taking joint 2 onto its nearest allowed orientation.
+        let target = rotation::compose( &rotation::compose( &allowed, link.transform().rotation() ), &rotation1 );
+        let error = rotation::compose( &( Vector::default() - rotation2 ), &target );
+        let violation = rotation::norm( &error );
+        if violation > T::epsilon() {
+            let direction = error / violation;
+            let ( turn1, turn2 ) = ( self.inverse_inertia_along( first, &direction ), self.inverse_inertia_along( second, &direction ) );
+            let weights = rotation::dot( &direction, &turn1 ) + rotation::dot( &direction, &turn2 );
+            let delta = multiplier( violation, &mut lambdas.1, weights, *solver.angular_compliance(), time_step );
+            if let Some( joint ) = self.get_joint_mut( second ) {
+                *joint.rotation_mut() = rotation::compose( &rotation2, &( turn2 * delta ) );
+            }
+            if let Some( joint ) = self.get_joint_mut( first ) {
+                *joint.rotation_mut() = rotation::compose( &rotation1, &( turn1 * -delta ) );
+            }
+        }
+    }
+
+    /// Projects the ranges of joint `id` one axis at a time, soft limits with compliance
+    /// `1 / ( mass * stiffness )`, then its shapes and swing twist limit rigidly.
+    pub(crate) fn project_joint( &mut self, id: I, lambdas: &mut [T], time_step: T ) {
+        let inverse_mass = self.inverse_mass( id );
+        let Some( joint ) = self.get_joint_mut( id ) else { return };
+        if inverse_mass == T::zero() {
+            let nearest = joint.nearest();
+            *joint.position_mut() = *nearest.position();
+            *joint.rotation_mut() = *nearest.rotation();
+            return;
+        }
+        let ( spatial, angular ) = lambdas.split_at_mut( DIM );
+        for ( quantity, lambdas ) in [ ( Quantity::Spatial, spatial ), ( Quantity::Angular, angular ) ] {
+            let constraint = joint.current_constraint( quantity, 0 ).unwrap_or_default();
+            let mut value = if quantity == Quantity::Spatial { joint.spatial[ 0 ] } else { joint.angular[ 0 ] };
+            for ( i, lambda ) in lambdas.iter_mut().enumerate() {
+                let Some( range ) = &constraint[ i ] else { continue };
+                let excess = range.excess( value[ i ] );
+                let compliance = match *range.limit() {
+                    Limit::Soft { stiffness, .. } if stiffness > T::zero() => inverse_mass / stiffness,
+                    Limit::Soft { .. } => continue,
+                    _ => T::zero()
+                };
+                if excess == T::zero() {
+                    continue;
+                }
+                // The multiplier of an inequality only ever pushes back into the range.
+                let before = *lambda;
+                let mut delta = multiplier( excess.abs(), lambda, inverse_mass, compliance, time_step );
+                if *lambda < T::zero() {
+                    delta = -before;
+                    *lambda = T::zero();
+                }
+                value[ i ] -= excess.signum() * delta * inverse_mass;
+            }
+            if let Some( shape ) = joint.shape( quantity, 0 ) {
+                value = shape.project( &value );
+            }
+            match quantity {
+                Quantity::Spatial => joint.spatial[ 0 ] = value,
+                Quantity::Angular => joint.angular[ 0 ] = joint.swing_twisted( &value )
+            }
+        }
+    }
+
+    /// Advances the linkage by `time_step` with XPBD: force fields are applied, each substep predicts
+    /// every joint from its velocity, accumulated force and accumulated torque, projects links and
+    /// joint position and rotation ranges for a fixed number of Gauss-Seidel iterations, and then sets
+    /// velocities to the finite difference of the corrected poses. The joint coordinates and rates are
+    /// then synced along the spanning tree of each connected group of joints. Fails with
+    /// `Error::SingularInertia`, before any joint moves and with the accumulated forces cleared, if a
+    /// joint's inertia tensor cannot be inverted.
+    pub fn update_xpbd( &mut self, solver: &Xpbd<T>, time_step: T ) -> Result<(), Error>
+    where
+        Assert<{ ORD >= 1 }>: IsTrue
+    {
+        if self.joint_ids().iter().any( |id| self.get_joint( *id ).is_some_and( |joint| joint.inverse_world_inertia().is_none() ) ) {
+            self.clear_forces();
+            return Err( Error::SingularInertia );
+        }
+        self.apply_force_fields();
+        let substep = time_step / T::from_usize( *solver.substeps() );
+        let ids = self.joint_ids();
+        let frames: BTreeMap<I, Pose<T, DIM>> = ids.iter()
+            .filter_map( |id| self.get_joint( *id ).map( |joint| ( *id, Pose::new( *joint.position(), *joint.rotation() ).compose( &joint.displacement().inverse() ) ) ) )
+            .collect();
+        let links = self.links().to_vec();
+        for _ in 0..*solver.substeps() {
+            let mut previous = Vec::new();
+            for id in ids.iter() {
+                let inverse_mass = self.inverse_mass( *id );
+                let Some( joint ) = self.get_joint_mut( *id ) else { continue };
+                previous.push( ( *id, joint.spatial[ 0 ], joint.angular[ 0 ] ) );
+                if inverse_mass == T::zero() {
+                    continue;
+                }
+                let force = *joint.force();
+                joint.spatial[ 1 ] += force * ( inverse_mass * substep );
+                // Singular inertia is rejected above.
+                let angular = joint.angular_acceleration_at( &joint.angular[ 1 ] ).unwrap_or_default();
+                joint.angular[ 1 ] += angular * substep;
+                let ( velocity, angular_velocity ) = ( joint.spatial[ 1 ], joint.angular[ 1 ] );
+                joint.spatial[ 0 ] += velocity * substep;
+                joint.turn( &( angular_velocity * substep ) );
+            }
+
+            let mut lambdas = vec![ ( T::zero(), T::zero() ); links.len() ];
+            let mut joint_lambdas = vec![ vec![ T::zero(); DIM * 2 ]; ids.len() ];
+            for _ in 0..*solver.iterations() {
+                for ( ( first, second ), lambda ) in links.iter().zip( lambdas.iter_mut() ) {
+                    self.project_link( *first, *second, lambda, solver, substep );
+                }
+                for ( id, lambdas ) in ids.iter().zip( joint_lambdas.iter_mut() ) {
+                    self.project_joint( *id, lambdas, substep );
+                }
+            }
+
+            for ( id, position, rotation ) in previous {
+                if let Some( joint ) = self.get_joint_mut( id ) {
+                    joint.spatial[ 1 ] = ( joint.spatial[ 0 ] - position ) / substep;
+                    joint.angular[ 1 ] = rotation::compose( &( Vector::default() - rotation ), &joint.angular[ 0 ] ) / substep;
+                }
+            }
+        }
+        self.clear_forces();
+        self.advance_time( time_step );
+
+        // Each group is synced from its first joint, whose parent frame does not move.
+        let mut synced = BTreeSet::new();
+        for id in ids.iter() {
+            if synced.contains( id ) {
+                continue;
+            }
+            let tree = self.spanning_tree( *id )?;
+            self.sync_coordinates( *id, frames[ id ] )?;
+            synced.extend( tree.order().iter().copied() );
+        }
+        Ok( () )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use linear_algebra::vector::Vector3;
+    use super::*;
+    use crate::{
+        body::Body3D,
+        joint::Joint3D,
+        link::Link,
+        constraint::{ Range, Limit, Constraint3D },
+        force::ForceField,
+        kinematics::Pose,
+        linkage::Linkage3D
+    };
+
+    #[test]
+    fn rigid_link_test() {
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        linkage.add_joint( 0, Joint3D::new( Body3D::new( 1.0, [ Vector3::default(); 2 ], [ Vector3::default(); 2 ] ), [ Constraint3D::default(); 4 ] ) ).unwrap();
+        linkage.add_joint( 1, Joint3D::new( Body3D::new( 1.0, [ Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::default() ], [ Vector3::default(); 2 ] ), [ Constraint3D::default(); 4 ] ) ).unwrap();
+        linkage.add_link( 0, 1, Link::rigid( 1.0, Pose::new( Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::default() ) ) ).unwrap();
+        linkage.add_force_field( ForceField::Gravity { acceleration: Vector3::from([ 0.0, 0.0, -10.0 ]) } );
+        *linkage.get_joint_mut( 1 ).unwrap().spatial_velocity_mut() = Vector3::from([ 0.0, 0.0, 2.0 ]);
+        let solver = Xpbd::default();
+        for _ in 0..10 {
+            linkage.update_xpbd( &solver, 0.01 ).unwrap();
+        }
+        let ( joint0, joint1 ) = ( linkage.get_joint( 0 ).unwrap(), linkage.get_joint( 1 ).unwrap() );
+        let separation = *joint1.position() - *joint0.position();
+        assert!( ( rotation::norm( &separation ) - 1.0 ).abs() < 1e-6 );
+        assert!( ( *linkage.time() - 0.1 ).abs() < 1e-12 );
+    }
+
+    #[test]
+    fn inertia_test() {
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        for ( id, moment ) in [ ( 0, 100.0 ), ( 1, 1.0 ) ] {
+            linkage.add_joint( id, Joint3D::new( Body3D::new( 1.0, [ Vector3::default(); 2 ], [ Vector3::default(); 2 ] ), [ Constraint3D::default(); 4 ] ) ).unwrap();
+            *linkage.get_joint_mut( id ).unwrap().inertia_mut() = Vector::from([ Vector3::from([ moment, 0.0, 0.0 ]), Vector3::from([ 0.0, moment, 0.0 ]), Vector3::from([ 0.0, 0.0, moment ]) ]);
+        }
+        *linkage.get_joint_mut( 1 ).unwrap().rotation_mut() = Vector3::from([ 0.0, 0.0, 0.1 ]);
+        linkage.add_link( 0, 1, Link::rigid( 0.0, Pose::identity() ) ).unwrap();
+        linkage.update_xpbd( &Xpbd::default(), 0.01 ).unwrap();
+
+        // The rotational correction is shared by inverse inertia, so the heavy joint barely turns.
+        for id in 0..2 {
+            assert!( ( linkage.get_joint( id ).unwrap().rotation()[ 2 ] - 0.1 / 101.0 ).abs() < 1e-6 );
+        }
+    }
+
+    #[test]
+    fn coordinates_test() {
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        linkage.add_joint( 0, Joint3D::new( Body3D::new( 1.0, [ Vector3::default(); 2 ], [ Vector3::default(); 2 ] ), [ Constraint3D::default(); 4 ] ) ).unwrap();
+        *linkage.get_joint_mut( 0 ).unwrap().spatial_velocity_mut() = Vector3::from([ 1.0, 0.0, 0.0 ]);
+        linkage.update_xpbd( &Xpbd::default(), 0.1 ).unwrap();
+        let joint = linkage.get_joint( 0 ).unwrap();
+        assert!( ( joint.coordinates()[ 0 ] - 0.1 ).abs() < 1e-12 );
+        assert!( ( joint.rates()[ 0 ] - 1.0 ).abs() < 1e-12 );
+    }
+
+    #[test]
+    fn floor_test() {
+        let floor = Constraint3D::new([ None, None, Some( Range::new( 0.0, f64::MAX ) ) ]);
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        linkage.add_joint( 0, Joint3D::new( Body3D::new( 1.0, [ Vector3::from([ 0.0, 0.0, 0.1 ]), Vector3::default() ], [ Vector3::default(); 2 ] ), [ floor, Constraint3D::default(), Constraint3D::default(), Constraint3D::default() ] ) ).unwrap();
+        linkage.add_force_field( ForceField::Gravity { acceleration: Vector3::from([ 0.0, 0.0, -10.0 ]) } );
+        let solver = Xpbd::default();
+        for _ in 0..100 {
+            linkage.update_xpbd( &solver, 0.01 ).unwrap();
+        }
+        let joint = linkage.get_joint( 0 ).unwrap();
+        assert_eq!( joint.position()[ 2 ], 0.0 );
+        assert!( joint.spatial_velocity()[ 2 ].abs() < 1e-12 );
+    }
+
+    #[test]
+    fn torque_test() {
+        let mut linkage = Linkage3D::<usize, f64, 1>::new();
+        linkage.add_joint( 0, Joint3D::new( Body3D::new( 1.0, [ Vector3::default(); 2 ], [ Vector3::default(); 2 ] ), [ Constraint3D::default(); 4 ] ) ).unwrap();
+        let spinner = linkage.get_joint_mut( 0 ).unwrap();
+        *spinner.inertia_mut() = Vector::from([ Vector3::from([ 2.0, 0.0, 0.0 ]), Vector3::from([ 0.0, 2.0, 0.0 ]), Vector3::from([ 0.0, 0.0, 2.0 ]) ]);
+        spinner.apply_torque( Vector3::from([ 0.0, 0.0, 1.0 ]) );
+        linkage.update_xpbd( &Xpbd::default(), 0.1 ).unwrap();
+        let joint = linkage.get_joint( 0 ).unwrap();
+        assert!( ( joint.angular_velocity()[ 2 ] - 0.05 ).abs() < 1e-12 );
+        assert_eq!( *joint.torque(), Vector3::default() );
+
+        *linkage.get_joint_mut( 0 ).unwrap().inertia_mut() = Vector::from([ Vector3::from([ 1.0, 0.0, 0.0 ]), Vector3::default(), Vector3::default() ]);
+        assert!( matches!( linkage.update_xpbd( &Xpbd::default(), 0.1 ), Err( Error::SingularInertia ) ) );
+    }
+
+    #[test]
+    fn soft_range_test() {
+        let range = Range::new( -0.5, 0.5 );
+        let mut positions = Vec::new();
+        for limit in [ Limit::Hard, Limit::Soft { stiffness: 100.0, damping: 0.0 } ] {
+            let constraint = Constraint3D::new([ Some( range.with_limit( limit ) ), None, None ]);
+            let mut linkage = Linkage3D::<usize, f64, 1>::new();
+            linkage.add_joint( 0, Joint3D::new( Body3D::new( 1.0, [ Vector3::from([ 0.5, 0.0, 0.0 ]), Vector3::default() ], [ Vector3::default(); 2 ] ), [ constraint, Constraint3D::default(), Constraint3D::default(), Constraint3D::default() ] ) ).unwrap();
+            *linkage.get_joint_mut( 0 ).unwrap().spatial_velocity_mut() = Vector3::from([ 1.0, 0.0, 0.0 ]);
+            linkage.update_xpbd( &Xpbd::default(), 0.01 ).unwrap();
+            positions.push( linkage.get_joint( 0 ).unwrap().position()[ 0 ] );
+        }
+        // Leaving the range, a hard limit holds the joint at once while a soft one only pulls it back.
+        assert!( ( positions[ 0 ] - 0.5 ).abs() < 1e-12 );
+        assert!( positions[ 1 ] > 0.501 && positions[ 1 ] < 0.51 );
+    }
+}